@@ -1,45 +1,446 @@
-use crate::horust::formats::Event;
-use crossbeam::channel::{unbounded, Receiver, Sender};
+use crate::horust::formats::{Event, EventPriority};
+use crossbeam::channel::{bounded, unbounded, Receiver, SendTimeoutError, Sender};
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, VecDeque};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+type Filter = Box<dyn Fn(&Event) -> bool + Send>;
+
+/// The slow subscribers are warned about at most once in this interval.
+const SEND_TIMEOUT_WARNING_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A connection to the bus, which receives only the events accepted by its filter.
+struct Subscriber {
+    /// The joining order.
+    id: usize,
+    sender: Sender<Event>,
+    filter: Filter,
+    /// The events dropped because its queue was full for longer than the send timeout.
+    timeouts: u64,
+    last_timeout_warning: Option<Instant>,
+}
+
+impl Subscriber {
+    /// Returns false if the subscriber has disconnected (e.g. the runtime, once it's done).
+    /// If its queue is still full after `timeout`, the event is dropped.
+    fn send(&mut self, ev: &Event, timeout: Duration) -> bool {
+        if !(self.filter)(ev) {
+            return true;
+        }
+        match self.sender.send_timeout(ev.clone(), timeout) {
+            Ok(()) => true,
+            Err(SendTimeoutError::Timeout(ev)) => {
+                self.timeouts += 1;
+                let is_warned = self
+                    .last_timeout_warning
+                    .is_some_and(|warned| warned.elapsed() < SEND_TIMEOUT_WARNING_INTERVAL);
+                if !is_warned {
+                    warn!(
+                        "Bus: subscriber {} is too slow, dropped {} events so far, the last one: {:?}",
+                        self.id, self.timeouts, ev
+                    );
+                    self.last_timeout_warning = Some(Instant::now());
+                }
+                true
+            }
+            Err(SendTimeoutError::Disconnected(_)) => false,
+        }
+    }
+}
+
+impl fmt::Debug for Subscriber {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Subscriber")
+            .field("sender", &self.sender)
+            .finish()
+    }
+}
+
+/// An event received by the bus, waiting to be dispatched. The higher priority ones come first,
+/// and the ones with the same priority in the order they were sent.
+#[derive(Debug)]
+struct Queued {
+    priority: EventPriority,
+    seq: Reverse<u64>,
+    event: Event,
+    received: Instant,
+}
+
+impl PartialEq for Queued {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Queued {}
+
+impl PartialOrd for Queued {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Queued {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.priority, self.seq).cmp(&(other.priority, other.seq))
+    }
+}
+
+/// The state of the bus' queues at some point, for debugging a stuck Horust.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BusSnapshot {
+    /// The events sent on the bus, not dispatched yet (also the ones taken for being prioritized).
+    pub pending: usize,
+    /// The events dispatched to every subscriber, which it hasn't received yet. In joining order.
+    pub subscribers_pending: Vec<usize>,
+    /// The last dispatched events (see `set_history_size`), oldest first.
+    pub last_events: Vec<Event>,
+}
+
+/// The default upper bounds of the dispatch latency's buckets.
+pub const DEFAULT_LATENCY_BUCKETS: [Duration; 5] = [
+    Duration::from_micros(100),
+    Duration::from_millis(1),
+    Duration::from_millis(10),
+    Duration::from_millis(100),
+    Duration::from_secs(1),
+];
+
+/// How long the events have waited in the bus, from being taken from the channel to being
+/// dispatched.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LatencyHistogram {
+    /// The upper bounds of the buckets, ascending.
+    pub buckets: Vec<Duration>,
+    /// The events in every bucket, and the last one for the events slower than all the bounds.
+    pub counts: Vec<u64>,
+    pub sum: Duration,
+}
+
+impl LatencyHistogram {
+    fn new(mut buckets: Vec<Duration>) -> Self {
+        buckets.sort();
+        buckets.dedup();
+        LatencyHistogram {
+            counts: vec![0; buckets.len() + 1],
+            buckets,
+            sum: Duration::default(),
+        }
+    }
+
+    fn observe(&mut self, latency: Duration) {
+        let bucket = self
+            .buckets
+            .iter()
+            .position(|bound| latency <= *bound)
+            .unwrap_or(self.buckets.len());
+        self.counts[bucket] += 1;
+        self.sum += latency;
+    }
+}
+
+/// The bus' activity since it's been created, for diagnosing Horust's own performance.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BusMetrics {
+    pub dispatched_events: u64,
+    /// Average, since the bus has been created.
+    pub events_per_sec: f64,
+    /// The events sent on the bus, not dispatched yet.
+    pub queue_depth: usize,
+    /// The events dispatched to every subscriber, which it hasn't received yet. In joining order.
+    pub subscribers_pending: Vec<usize>,
+    /// The events dropped for every subscriber, as its queue was full (see `set_subscriber_capacity`).
+    /// Always zero for the subscribers whose queue is unbounded.
+    pub subscribers_timeouts: Vec<u64>,
+    pub dispatch_latency: LatencyHistogram,
+}
 
 /// A simple bus implementation: distributes the messages among the queues
 #[derive(Debug)]
 pub struct Bus {
     public_sender: Sender<Event>,
     receiver: Receiver<Event>,
-    senders: Vec<Sender<Event>>,
+    subscribers: Vec<Subscriber>,
+    /// The events taken from `receiver`, by priority.
+    queue: BinaryHeap<Queued>,
+    /// How many events have been queued, for keeping the sending order within a priority.
+    queued_count: u64,
+    /// The last dispatched events, up to `history_size`.
+    history: VecDeque<Event>,
+    history_size: usize,
+    created: Instant,
+    dispatched_count: u64,
+    latency: LatencyHistogram,
+    /// Collapsible events equal to one of these, dispatched less than `dedup_window` ago, are dropped.
+    recently_dispatched: Vec<(Event, Instant)>,
+    dedup_window: Duration,
+    /// The capacity of the auxiliary subscribers' queues. Unbounded if None.
+    subscriber_capacity: Option<usize>,
+    send_timeout: Duration,
+    /// How many subscribers have joined, for their ids.
+    joined_count: usize,
 }
 
+/// How long the bus waits for a full subscriber's queue, before dropping the event.
+pub const DEFAULT_SEND_TIMEOUT: Duration = Duration::from_millis(100);
+
 impl Bus {
     pub fn new() -> Self {
         let (pub_sx, rx) = unbounded();
         Bus {
             public_sender: pub_sx,
             receiver: rx,
-            senders: Vec::new(),
+            subscribers: Vec::new(),
+            queue: BinaryHeap::new(),
+            queued_count: 0,
+            history: VecDeque::new(),
+            history_size: 0,
+            created: Instant::now(),
+            dispatched_count: 0,
+            latency: LatencyHistogram::new(DEFAULT_LATENCY_BUCKETS.to_vec()),
+            recently_dispatched: Vec::new(),
+            dedup_window: Duration::from_secs(0),
+            subscriber_capacity: None,
+            send_timeout: DEFAULT_SEND_TIMEOUT,
+            joined_count: 0,
         }
     }
 
-    /// Blocking
-    pub fn run(mut self) {
-        self.dispatch();
+    /// The capacity of the queues of the auxiliary subscribers joining afterwards (see
+    /// `join_bus_auxiliary`). With a capacity, the bus waits up to the send timeout for a slow
+    /// auxiliary subscriber, then drops the event for it.
+    pub fn set_subscriber_capacity(&mut self, capacity: usize) {
+        self.subscriber_capacity = Some(capacity);
+    }
+
+    /// How long the bus waits for a full subscriber's queue. Default: `DEFAULT_SEND_TIMEOUT`.
+    pub fn set_send_timeout(&mut self, send_timeout: Duration) {
+        self.send_timeout = send_timeout;
+    }
+
+    /// The collapsible events (see `Event::is_collapsible`) equal to one dispatched less than
+    /// `dedup_window` ago are dropped. Zero disables it.
+    pub fn set_dedup_window(&mut self, dedup_window: Duration) {
+        self.dedup_window = dedup_window;
+    }
+
+    /// The upper bounds of the dispatch latency's buckets. It resets the latency's metrics.
+    pub fn set_latency_buckets(&mut self, buckets: Vec<Duration>) {
+        self.latency = LatencyHistogram::new(buckets);
+    }
+
+    /// How many of the last dispatched events are kept, for replaying them to new subscribers.
+    pub fn set_history_size(&mut self, history_size: usize) {
+        self.history_size = history_size;
+        while self.history.len() > history_size {
+            self.history.pop_front();
+        }
+    }
+
+    /// The last `n` dispatched events (fewer, if the history is shorter), oldest first.
+    pub fn replay_last_n(&self, n: usize) -> Vec<Event> {
+        let skip = self.history.len().saturating_sub(n);
+        self.history.iter().skip(skip).cloned().collect()
+    }
+
+    /// How many events are queued, and the last dispatched ones. It doesn't affect the dispatching.
+    pub fn snapshot(&self) -> BusSnapshot {
+        BusSnapshot {
+            pending: self.receiver.len() + self.queue.len(),
+            subscribers_pending: self
+                .subscribers
+                .iter()
+                .map(|subscriber| subscriber.sender.len())
+                .collect(),
+            last_events: self.history.iter().cloned().collect(),
+        }
+    }
+
+    /// The bus' metrics at this point. It doesn't affect the dispatching.
+    pub fn metrics(&self) -> BusMetrics {
+        let snapshot = self.snapshot();
+        let elapsed = self.created.elapsed().as_secs_f64();
+        BusMetrics {
+            dispatched_events: self.dispatched_count,
+            events_per_sec: if elapsed > 0.0 {
+                self.dispatched_count as f64 / elapsed
+            } else {
+                0.0
+            },
+            queue_depth: snapshot.pending,
+            subscribers_pending: snapshot.subscribers_pending,
+            subscribers_timeouts: self
+                .subscribers
+                .iter()
+                .map(|subscriber| subscriber.timeouts)
+                .collect(),
+            dispatch_latency: self.latency.clone(),
+        }
+    }
+
+    /// Blocking, until an `Exiting` event has been dispatched. Then drains the events sent in the
+    /// meanwhile, and returns the exit code.
+    pub fn run(mut self) -> i32 {
+        let exit_code = self.dispatch();
+        let undelivered = self.drain(Duration::from_millis(500));
+        if !undelivered.is_empty() {
+            warn!("Bus: {} events have not been delivered.", undelivered.len());
+            debug!("Undelivered events: {:?}", undelivered);
+            debug!("Bus: {:?}", self.snapshot());
+        }
+        debug!("Bus metrics: {:?}", self.metrics());
+        exit_code
+    }
+
+    /// Dispatches the events which are pending at the time of the call. Returns the events which
+    /// couldn't be dispatched within `timeout`, followed by the ones sent in the meanwhile.
+    pub fn drain(&mut self, timeout: Duration) -> Vec<Event> {
+        let deadline = Instant::now() + timeout;
+        self.enqueue_pending();
+        let mut pending = std::mem::take(&mut self.queue);
+        let mut undelivered = vec![];
+        while let Some(queued) = pending.pop() {
+            if Instant::now() < deadline {
+                self.latency.observe(queued.received.elapsed());
+                self.dispatch_event(queued.event);
+            } else {
+                undelivered.push(queued.event);
+            }
+        }
+        // Not dispatched either: nobody is going to take them from the channel anymore.
+        let sent_meanwhile = self.receiver.try_iter().take(self.receiver.len());
+        undelivered.extend(sent_meanwhile);
+        undelivered
     }
 
-    /// Add another connection to the bus
+    /// A sender for publishing events on the bus, without receiving them.
+    pub fn publisher(&self) -> Sender<Event> {
+        self.public_sender.clone()
+    }
+
+    /// Add another connection to the bus. It will receive the events in the history first.
     pub fn join_bus(&mut self) -> BusConnector {
-        let (mysx, rx) = unbounded();
-        self.senders.push(mysx);
+        self.join_bus_filtered(|_| true)
+    }
+
+    /// Add another connection to the bus, receiving only the events for which `filter` is true.
+    /// The filter runs on the dispatcher's thread, so it should be cheap.
+    /// Its queue is unbounded: no event is ever dropped for it, as needed by the components
+    /// driving the services' state (e.g. the runtime and the reaper).
+    pub fn join_bus_filtered<F>(&mut self, filter: F) -> BusConnector
+    where
+        F: Fn(&Event) -> bool + Send + 'static,
+    {
+        self.join(Box::new(filter), None)
+    }
+
+    /// As `join_bus_filtered`, for the components which only observe the events (e.g. the events
+    /// fifo): with `set_subscriber_capacity`, the events are dropped for it if it's too slow.
+    pub fn join_bus_auxiliary<F>(&mut self, filter: F) -> BusConnector
+    where
+        F: Fn(&Event) -> bool + Send + 'static,
+    {
+        self.join(Box::new(filter), self.subscriber_capacity)
+    }
+
+    fn join(&mut self, filter: Filter, capacity: Option<usize>) -> BusConnector {
+        let (mysx, rx) = match capacity {
+            Some(capacity) => bounded(capacity),
+            None => unbounded(),
+        };
+        let mut subscriber = Subscriber {
+            id: self.joined_count,
+            sender: mysx,
+            filter,
+            timeouts: 0,
+            last_timeout_warning: None,
+        };
+        self.joined_count += 1;
+        self.replay_last_n(self.history_size).iter().for_each(|ev| {
+            subscriber.send(ev, self.send_timeout);
+        });
+        self.subscribers.push(subscriber);
         BusConnector::new(self.public_sender.clone(), rx)
     }
 
-    // Infinite dispatching loop.
-    // TODO: handle error or try_send or send_timeout
-    pub fn dispatch(&mut self) {
+    // Dispatching loop, until an `Exiting` event. Returns its exit code.
+    pub fn dispatch(&mut self) -> i32 {
         loop {
-            self.receiver.iter().for_each(|el| {
-                self.senders
-                    .iter()
-                    .for_each(|sender| sender.send(el.clone()).expect("Failed sending message"))
-            });
+            while let Some(el) = self.next_event() {
+                let exit_code = match el {
+                    Event::Exiting(exit_code) => Some(exit_code),
+                    _ => None,
+                };
+                self.dispatch_event(el);
+                if let Some(exit_code) = exit_code {
+                    return exit_code;
+                }
+            }
+        }
+    }
+
+    /// Blocking, until there is an event. The pending events are all taken from the channel, so
+    /// the critical ones among them are dispatched first.
+    fn next_event(&mut self) -> Option<Event> {
+        if self.queue.is_empty() {
+            let ev = self.receiver.recv().ok()?;
+            self.enqueue(ev);
+        }
+        self.enqueue_pending();
+        let queued = self.queue.pop()?;
+        self.latency.observe(queued.received.elapsed());
+        Some(queued.event)
+    }
+
+    fn enqueue_pending(&mut self) {
+        let pending: Vec<Event> = self.receiver.try_iter().take(self.receiver.len()).collect();
+        pending.into_iter().for_each(|ev| self.enqueue(ev));
+    }
+
+    fn enqueue(&mut self, event: Event) {
+        self.queue.push(Queued {
+            priority: event.priority(),
+            seq: Reverse(self.queued_count),
+            event,
+            received: Instant::now(),
+        });
+        self.queued_count += 1;
+    }
+
+    /// True if `ev` should be dropped, as it's been dispatched within the dedup window.
+    fn is_duplicate(&mut self, ev: &Event) -> bool {
+        if self.dedup_window == Duration::from_secs(0) || !ev.is_collapsible() {
+            return false;
+        }
+        let dedup_window = self.dedup_window;
+        self.recently_dispatched
+            .retain(|(_ev, dispatched)| dispatched.elapsed() < dedup_window);
+        if self
+            .recently_dispatched
+            .iter()
+            .any(|(recent, _)| recent == ev)
+        {
+            return true;
+        }
+        self.recently_dispatched.push((ev.clone(), Instant::now()));
+        false
+    }
+
+    fn dispatch_event(&mut self, el: Event) {
+        if self.is_duplicate(&el) {
+            trace!("Bus: collapsing duplicated event: {:?}", el);
+            return;
+        }
+        let send_timeout = self.send_timeout;
+        self.subscribers
+            .retain_mut(|subscriber| subscriber.send(&el, send_timeout));
+        self.dispatched_count += 1;
+        if self.history_size > 0 {
+            if self.history.len() == self.history_size {
+                self.history.pop_front();
+            }
+            self.history.push_back(el);
         }
     }
 }
@@ -60,6 +461,14 @@ impl BusConnector {
         self.receiver.recv().unwrap()
     }
 
+    /// Blocking, up to `timeout`
+    pub fn get_events_timeout(&self, timeout: Duration) -> Vec<Event> {
+        self.receiver
+            .recv_timeout(timeout)
+            .map(|ev| vec![ev])
+            .unwrap_or_default()
+    }
+
     /// Non blocking
     pub fn try_get_events(&self) -> Vec<Event> {
         self.receiver.try_iter().collect()
@@ -69,3 +478,255 @@ impl BusConnector {
         self.sender.send(ev).expect("Failed sending update event!");
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::horust::bus::Bus;
+    use crate::horust::formats::{Event, ExitStatus, ServiceStatus};
+    use std::time::Duration;
+
+    #[test]
+    fn test_replay_last_n() {
+        let mut bus = Bus::new();
+        bus.set_history_size(2);
+        let status =
+            |name: &str| Event::new_status_changed(&name.to_string(), ServiceStatus::Running);
+        let first = bus.join_bus();
+        ["a", "b", "c"]
+            .iter()
+            .for_each(|name| bus.dispatch_event(status(name)));
+        assert_eq!(first.try_get_events().len(), 3);
+        assert_eq!(bus.replay_last_n(1), vec![status("c")]);
+        assert_eq!(bus.replay_last_n(5), vec![status("b"), status("c")]);
+
+        // A new subscriber catches up on the history:
+        let second = bus.join_bus();
+        assert_eq!(second.try_get_events(), vec![status("b"), status("c")]);
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let mut bus = Bus::new();
+        bus.set_history_size(1);
+        let first = bus.join_bus();
+        let _second = bus.join_bus_filtered(|ev| ev == &Event::ShuttingDownInitiated);
+        bus.dispatch_event(Event::Exiting(0));
+        bus.dispatch_event(Event::ShuttingDownInitiated);
+        bus.publisher().send(Event::Exiting(1)).unwrap();
+        let snapshot = bus.snapshot();
+        assert_eq!(snapshot.pending, 1);
+        assert_eq!(snapshot.subscribers_pending, vec![2, 1]);
+        assert_eq!(snapshot.last_events, vec![Event::ShuttingDownInitiated]);
+
+        first.try_get_events();
+        assert_eq!(bus.snapshot().subscribers_pending, vec![0, 1]);
+    }
+
+    #[test]
+    fn test_drain() {
+        let mut bus = Bus::new();
+        let connector = bus.join_bus();
+        let publisher = bus.publisher();
+        publisher.send(Event::ShuttingDownInitiated).unwrap();
+        publisher.send(Event::ShuttingDownInitiated).unwrap();
+        assert!(bus.drain(Duration::from_secs(1)).is_empty());
+        assert_eq!(connector.try_get_events().len(), 2);
+
+        // Nothing is dispatched after the timeout:
+        publisher.send(Event::ShuttingDownInitiated).unwrap();
+        assert_eq!(
+            bus.drain(Duration::from_secs(0)),
+            vec![Event::ShuttingDownInitiated]
+        );
+        assert!(connector.try_get_events().is_empty());
+
+        // Sent while draining, as a reaction to a dispatched event:
+        bus.set_subscriber_capacity(1);
+        bus.set_send_timeout(Duration::from_millis(200));
+        let reacting = bus.join_bus();
+        let full = bus.join_bus_auxiliary(|_| true);
+        let reaction = Event::new_status_changed(&"a".into(), ServiceStatus::ToBeKilled);
+        let sent = reaction.clone();
+        let reactor = std::thread::spawn(move || {
+            reacting.get_events_blocking();
+            reacting.send_event(sent);
+        });
+        publisher.send(Event::ShuttingDownInitiated).unwrap();
+        publisher.send(Event::ShuttingDownInitiated).unwrap();
+        // The full subscriber holds the drain, while the reaction is sent:
+        assert_eq!(bus.drain(Duration::from_secs(1)), vec![reaction]);
+        reactor.join().unwrap();
+        assert_eq!(connector.try_get_events().len(), 2);
+        assert_eq!(full.try_get_events().len(), 1);
+    }
+
+    #[test]
+    fn test_priority() {
+        let mut bus = Bus::new();
+        let connector = bus.join_bus();
+        let publisher = bus.publisher();
+        let a = "a".to_string();
+        let healthcheck_results = vec![
+            Event::new_service_healthy(&a),
+            Event::new_status_changed(&a, ServiceStatus::Running),
+        ];
+        healthcheck_results
+            .iter()
+            .for_each(|ev| publisher.send(ev.clone()).unwrap());
+        publisher.send(Event::ShuttingDownInitiated).unwrap();
+        assert_eq!(bus.next_event(), Some(Event::ShuttingDownInitiated));
+        // The others keep their order:
+        assert_eq!(bus.snapshot().pending, 2);
+        publisher.send(Event::Exiting(0)).unwrap();
+        assert_eq!(bus.dispatch(), 0);
+        assert_eq!(connector.try_get_events(), vec![Event::Exiting(0)]);
+        assert_eq!(bus.drain(Duration::from_secs(1)), vec![]);
+        assert_eq!(connector.try_get_events(), healthcheck_results);
+    }
+
+    #[test]
+    fn test_metrics() {
+        let mut bus = Bus::new();
+        bus.set_latency_buckets(vec![Duration::from_secs(60), Duration::from_nanos(1)]);
+        let connector = bus.join_bus();
+        let publisher = bus.publisher();
+        publisher.send(Event::ShuttingDownInitiated).unwrap();
+        publisher.send(Event::ShuttingDownInitiated).unwrap();
+        assert_eq!(bus.metrics().queue_depth, 2);
+        // The latency is counted from when the bus takes them:
+        bus.enqueue_pending();
+        std::thread::sleep(Duration::from_millis(1));
+        assert!(bus.drain(Duration::from_secs(1)).is_empty());
+
+        let metrics = bus.metrics();
+        assert_eq!(metrics.dispatched_events, 2);
+        assert!(metrics.events_per_sec > 0.0);
+        assert_eq!(metrics.queue_depth, 0);
+        assert_eq!(metrics.subscribers_pending, vec![2]);
+        assert_eq!(metrics.subscribers_timeouts, vec![0]);
+        let latency = metrics.dispatch_latency;
+        assert_eq!(
+            latency.buckets,
+            vec![Duration::from_nanos(1), Duration::from_secs(60)]
+        );
+        assert_eq!(latency.counts, vec![0, 2, 0]);
+        assert!(latency.sum >= Duration::from_millis(2));
+
+        connector.try_get_events();
+        assert_eq!(bus.metrics().subscribers_pending, vec![0]);
+    }
+
+    #[test]
+    fn test_dedup_window() {
+        let mut bus = Bus::new();
+        let connector = bus.join_bus();
+        let publisher = bus.publisher();
+        let a = "a".to_string();
+        let healthy = Event::new_service_healthy(&a);
+        let running = Event::new_status_changed(&a, ServiceStatus::Running);
+        let send = |ev: &Event, count| (0..count).for_each(|_| publisher.send(ev.clone()).unwrap());
+        // Disabled by default:
+        send(&healthy, 2);
+        bus.drain(Duration::from_secs(1));
+        assert_eq!(connector.try_get_events().len(), 2);
+
+        bus.set_dedup_window(Duration::from_millis(200));
+        send(&healthy, 100);
+        send(&running, 2);
+        send(&Event::new_service_healthy(&"b".to_string()), 1);
+        bus.drain(Duration::from_secs(1));
+        assert_eq!(
+            connector.try_get_events(),
+            vec![
+                healthy.clone(),
+                running.clone(),
+                running,
+                Event::new_service_healthy(&"b".to_string())
+            ]
+        );
+
+        // Once the window is over:
+        std::thread::sleep(Duration::from_millis(200));
+        send(&healthy, 2);
+        bus.drain(Duration::from_secs(1));
+        assert_eq!(connector.try_get_events(), vec![healthy]);
+    }
+
+    #[test]
+    fn test_subscriber_send_timeout() {
+        let mut bus = Bus::new();
+        bus.set_subscriber_capacity(1);
+        bus.set_send_timeout(Duration::from_millis(10));
+        let slow = bus.join_bus_auxiliary(|_| true);
+        let fast = bus.join_bus_auxiliary(|_| true);
+        let publisher = bus.publisher();
+        (0..3).for_each(|_| publisher.send(Event::ShuttingDownInitiated).unwrap());
+        bus.enqueue_pending();
+        let mut received = vec![];
+        while let Some(ev) = bus.next_event() {
+            bus.dispatch_event(ev);
+            received.extend(fast.try_get_events());
+            if received.len() == 3 {
+                break;
+            }
+        }
+        // The slow subscriber doesn't block the other one, it only gets the first event:
+        assert_eq!(bus.metrics().subscribers_timeouts, vec![2, 0]);
+        assert_eq!(slow.try_get_events(), vec![Event::ShuttingDownInitiated]);
+    }
+
+    #[test]
+    fn test_full_runtime_queue() {
+        let mut bus = Bus::new();
+        bus.set_subscriber_capacity(1);
+        bus.set_send_timeout(Duration::from_millis(10));
+        // Nobody is reading from them:
+        let runtime = bus.join_bus();
+        let fifo = bus.join_bus_auxiliary(|_| true);
+        let exited = |name: &str| Event::new_service_exited(name.into(), ExitStatus::Exited(0));
+        let publisher = bus.publisher();
+        ["a", "b", "c"]
+            .iter()
+            .for_each(|name| publisher.send(exited(name)).unwrap());
+        assert!(bus.drain(Duration::from_secs(1)).is_empty());
+        // Only the auxiliary subscriber loses them:
+        assert_eq!(bus.metrics().subscribers_timeouts, vec![0, 2]);
+        assert_eq!(
+            runtime.try_get_events(),
+            vec![exited("a"), exited("b"), exited("c")]
+        );
+        assert_eq!(fifo.try_get_events(), vec![exited("a")]);
+    }
+
+    #[test]
+    fn test_run_until_exiting() {
+        let mut bus = Bus::new();
+        let connector = bus.join_bus();
+        let publisher = bus.publisher();
+        publisher.send(Event::Exiting(3)).unwrap();
+        publisher.send(Event::ShuttingDownInitiated).unwrap();
+        assert_eq!(bus.run(), 3);
+        // The events sent before the end are drained:
+        assert_eq!(
+            connector.try_get_events(),
+            vec![Event::Exiting(3), Event::ShuttingDownInitiated]
+        );
+    }
+
+    #[test]
+    fn test_join_bus_filtered() {
+        let mut bus = Bus::new();
+        let all = bus.join_bus();
+        let filtered = bus.join_bus_filtered(|ev| ev == &Event::ShuttingDownInitiated);
+        bus.dispatch_event(Event::new_status_changed(
+            &"a".to_string(),
+            ServiceStatus::Running,
+        ));
+        bus.dispatch_event(Event::ShuttingDownInitiated);
+        assert_eq!(all.try_get_events().len(), 2);
+        assert_eq!(
+            filtered.try_get_events(),
+            vec![Event::ShuttingDownInitiated]
+        );
+    }
+}
@@ -0,0 +1,86 @@
+use libc::{c_ulong, prctl, PR_CAPBSET_DROP, PR_CAPBSET_READ};
+
+/// The capabilities' names, indexed by their number (see capabilities(7)).
+const CAPABILITIES: [&str; 41] = [
+    "CAP_CHOWN",
+    "CAP_DAC_OVERRIDE",
+    "CAP_DAC_READ_SEARCH",
+    "CAP_FOWNER",
+    "CAP_FSETID",
+    "CAP_KILL",
+    "CAP_SETGID",
+    "CAP_SETUID",
+    "CAP_SETPCAP",
+    "CAP_LINUX_IMMUTABLE",
+    "CAP_NET_BIND_SERVICE",
+    "CAP_NET_BROADCAST",
+    "CAP_NET_ADMIN",
+    "CAP_NET_RAW",
+    "CAP_IPC_LOCK",
+    "CAP_IPC_OWNER",
+    "CAP_SYS_MODULE",
+    "CAP_SYS_RAWIO",
+    "CAP_SYS_CHROOT",
+    "CAP_SYS_PTRACE",
+    "CAP_SYS_PACCT",
+    "CAP_SYS_ADMIN",
+    "CAP_SYS_BOOT",
+    "CAP_SYS_NICE",
+    "CAP_SYS_RESOURCE",
+    "CAP_SYS_TIME",
+    "CAP_SYS_TTY_CONFIG",
+    "CAP_MKNOD",
+    "CAP_LEASE",
+    "CAP_AUDIT_WRITE",
+    "CAP_AUDIT_CONTROL",
+    "CAP_SETFCAP",
+    "CAP_MAC_OVERRIDE",
+    "CAP_MAC_ADMIN",
+    "CAP_SYSLOG",
+    "CAP_WAKE_ALARM",
+    "CAP_BLOCK_SUSPEND",
+    "CAP_AUDIT_READ",
+    "CAP_PERFMON",
+    "CAP_BPF",
+    "CAP_CHECKPOINT_RESTORE",
+];
+
+/// The number of the capability called `name`, e.g. `CAP_NET_ADMIN`.
+pub(crate) fn capability_number(name: &str) -> Option<c_ulong> {
+    CAPABILITIES
+        .iter()
+        .position(|capability| *capability == name)
+        .map(|number| number as c_ulong)
+}
+
+/// In the service's process, before the exec: drops every capability not in `keep` from the
+/// bounding set, including the ones unknown to Horust. It requires CAP_SETPCAP.
+pub(crate) fn drop_bounding_set(keep: &[String]) -> nix::Result<()> {
+    let keep: Vec<c_ulong> = keep
+        .iter()
+        .filter_map(|name| capability_number(name))
+        .collect();
+    // The capabilities not supported by the kernel are invalid:
+    let is_supported = |capability: c_ulong| unsafe { prctl(PR_CAPBSET_READ, capability) >= 0 };
+    (0..)
+        .take_while(|capability| is_supported(*capability))
+        .filter(|capability| !keep.contains(capability))
+        .try_for_each(|capability| {
+            let res = unsafe { prctl(PR_CAPBSET_DROP, capability, 0, 0, 0) };
+            nix::errno::Errno::result(res).map(drop)
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::horust::capabilities::capability_number;
+
+    #[test]
+    fn test_capability_number() {
+        assert_eq!(capability_number("CAP_CHOWN"), Some(0));
+        assert_eq!(capability_number("CAP_NET_ADMIN"), Some(12));
+        assert_eq!(capability_number("CAP_CHECKPOINT_RESTORE"), Some(40));
+        assert_eq!(capability_number("NET_ADMIN"), None);
+        assert_eq!(capability_number("cap_net_admin"), None);
+    }
+}
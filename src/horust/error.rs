@@ -85,6 +85,10 @@ pub struct ValidationError {
 #[derive(Debug)]
 pub enum ValidationErrorKind {
     MissingDependency,
+    DuplicateService,
+    UnknownService,
+    MissingValue,
+    InvalidValue,
 }
 
 impl std::error::Error for ValidationError {}
@@ -100,6 +104,6 @@ impl ValidationError {
 
 impl fmt::Display for ValidationError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), fmt::Error> {
-        write!(f, "{}", self.context)
+        write!(f, "{:?}: {}", self.kind, self.context)
     }
 }
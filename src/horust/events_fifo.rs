@@ -0,0 +1,220 @@
+use crate::horust::bus::BusConnector;
+use crate::horust::error::Result;
+use crate::horust::formats::Event;
+use nix::sys::stat::Mode;
+use nix::unistd::mkfifo;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::{FileTypeExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+
+/// Creates the fifo in `path`, and spawns a thread which writes there every event coming from the bus.
+pub(crate) fn spawn(bus: BusConnector, path: PathBuf) -> Result<()> {
+    create_fifo(&path)?;
+    std::thread::spawn(move || {
+        let mut events_fifo = EventsFifo::new(path);
+        loop {
+            let ev = bus.get_events_blocking();
+            events_fifo.write_event(&ev);
+        }
+    });
+    Ok(())
+}
+
+/// Create the fifo (and the missing parent directories). An already existing fifo is reused.
+fn create_fifo(path: &Path) -> Result<()> {
+    if let Ok(metadata) = std::fs::metadata(path) {
+        if metadata.file_type().is_fifo() {
+            return Ok(());
+        }
+    }
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    mkfifo(path, Mode::S_IRUSR | Mode::S_IWUSR)?;
+    Ok(())
+}
+
+struct EventsFifo {
+    path: PathBuf,
+    /// Write end of the fifo, available only if a reader was connected.
+    file: Option<File>,
+    /// Count of events which couldn't be written.
+    dropped_events: u64,
+    /// Count of events dropped since the last successful write.
+    dropping: u64,
+    /// True once at least an event has been written.
+    has_written: bool,
+}
+
+impl EventsFifo {
+    fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            file: None,
+            dropped_events: 0,
+            dropping: 0,
+            has_written: false,
+        }
+    }
+
+    /// Count of events which couldn't be written so far.
+    fn dropped_events(&self) -> u64 {
+        self.dropped_events
+    }
+
+    /// Opening a fifo in non blocking mode, fails if there is no reader on the other side.
+    fn open(&self) -> std::io::Result<File> {
+        OpenOptions::new()
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(&self.path)
+    }
+
+    /// Writes the event as a json line. It never blocks: if there is no reader or the fifo is full,
+    /// the event is dropped.
+    /// Lines are written with a single `write`, so they are either written whole or not at all:
+    /// the ones longer than PIPE_BUF (e.g. a big `AddService`) are dropped, as their writes
+    /// wouldn't be atomic.
+    fn write_event(&mut self, ev: &Event) {
+        if self.file.is_none() {
+            self.file = self.open().ok();
+        }
+        let line = match serde_json::to_string(ev) {
+            Ok(line) => format!("{}\n", line),
+            Err(error) => return self.drop_event(ev, error.into()),
+        };
+        if line.len() > libc::PIPE_BUF {
+            let error = format!("The event is too long: {} bytes", line.len());
+            return self.drop_event(ev, std::io::Error::other(error));
+        }
+        let res = match self.file.as_mut() {
+            Some(file) => file.write(line.as_bytes()),
+            None => Err(std::io::Error::from_raw_os_error(libc::ENXIO)),
+        };
+        match res {
+            Ok(written) if written == line.len() => {
+                self.has_written = true;
+                if self.dropping > 0 {
+                    warn!(
+                        "Events fifo: {} events have been dropped.",
+                        std::mem::take(&mut self.dropping)
+                    );
+                }
+            }
+            Ok(written) => self.drop_event(
+                ev,
+                std::io::Error::other(format!("Short write: {} bytes", written)),
+            ),
+            Err(error) => {
+                if error.raw_os_error() == Some(libc::EPIPE) {
+                    // The reader has gone away: reopen the fifo on the next event.
+                    self.file = None;
+                }
+                self.drop_event(ev, error)
+            }
+        }
+    }
+
+    fn drop_event(&mut self, ev: &Event, error: std::io::Error) {
+        // Warn only once, if a reader was connected:
+        if self.dropping == 0 && self.has_written {
+            warn!(
+                "Events fifo: dropping events, error: {}. Dropped events so far: {}",
+                error,
+                self.dropped_events()
+            );
+        }
+        self.dropped_events += 1;
+        self.dropping += 1;
+        debug!("Dropped event: {:?}, error: {}", ev, error);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::horust::error::Result;
+    use crate::horust::events_fifo::{create_fifo, EventsFifo};
+    use crate::horust::formats::{Event, ServiceStatus};
+    use std::fs::OpenOptions;
+    use std::io::{BufRead, BufReader};
+    use std::os::unix::fs::OpenOptionsExt;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_write_event() -> Result<()> {
+        let tempdir = TempDir::new("events-fifo")?;
+        let path = tempdir.path().join("run").join("events");
+        create_fifo(&path)?;
+        // Should be idempotent:
+        create_fifo(&path)?;
+        let mut events_fifo = EventsFifo::new(path.clone());
+        let ev = Event::new_status_changed(&"a".to_string(), ServiceStatus::Running);
+
+        // No reader connected:
+        events_fifo.write_event(&ev);
+        assert_eq!(events_fifo.dropped_events, 1);
+
+        let reader = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(&path)?;
+        events_fifo.write_event(&ev);
+        assert_eq!(events_fifo.dropped_events, 1);
+        let mut line = String::new();
+        BufReader::new(reader).read_line(&mut line)?;
+        assert_eq!(line, "{\"StatusChanged\":[\"a\",\"Running\"]}\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_event_too_long() -> Result<()> {
+        let tempdir = TempDir::new("events-fifo")?;
+        let path = tempdir.path().join("events");
+        create_fifo(&path)?;
+        let mut events_fifo = EventsFifo::new(path.clone());
+        let reader = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(&path)?;
+        let long = Event::new_status_changed(&"a".repeat(5000), ServiceStatus::Running);
+        events_fifo.write_event(&long);
+        assert_eq!(events_fifo.dropped_events(), 1);
+        // Nothing of it has been written, so the next line is intact:
+        let ev = Event::new_status_changed(&"b".to_string(), ServiceStatus::Running);
+        events_fifo.write_event(&ev);
+        let mut line = String::new();
+        BufReader::new(reader).read_line(&mut line)?;
+        assert_eq!(line, "{\"StatusChanged\":[\"b\",\"Running\"]}\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_event_full_fifo() -> Result<()> {
+        let tempdir = TempDir::new("events-fifo")?;
+        let path = tempdir.path().join("events");
+        create_fifo(&path)?;
+        let mut events_fifo = EventsFifo::new(path.clone());
+        let reader = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(&path)?;
+        let service_name = "a".repeat(100);
+        let ev = Event::new_status_changed(&service_name, ServiceStatus::Running);
+        // Nobody is reading, so the fifo's buffer will fill up:
+        let count = 10_000;
+        (0..count).for_each(|_| events_fifo.write_event(&ev));
+        assert!(events_fifo.dropped_events() > 0);
+        // The fifo is still open, and only whole lines have been written:
+        assert!(events_fifo.file.is_some());
+        let lines = BufReader::new(reader)
+            .lines()
+            .map_while(std::io::Result::ok)
+            .collect::<Vec<String>>();
+        assert_eq!(lines.len() as u64, count - events_fifo.dropped_events());
+        assert!(lines
+            .iter()
+            .all(|line| serde_json::from_str::<serde_json::Value>(line).is_ok()));
+        Ok(())
+    }
+}
@@ -0,0 +1,164 @@
+use crate::horust::formats::EnvFileFormat;
+use std::io;
+use std::path::Path;
+
+/// Reads the variables defined in the `env-file` at `path`, in their order.
+pub(crate) fn read_env_file(
+    path: &Path,
+    format: EnvFileFormat,
+) -> io::Result<Vec<(String, String)>> {
+    let content = std::fs::read_to_string(path)?;
+    parse(content.as_str(), format).map_err(|error| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: {}", path.display(), error),
+        )
+    })
+}
+
+fn parse(content: &str, format: EnvFileFormat) -> Result<Vec<(String, String)>, String> {
+    match format {
+        EnvFileFormat::Dotenv => parse_lines(content, false),
+        EnvFileFormat::Shell => parse_lines(content, true),
+        EnvFileFormat::Json => parse_json(content),
+    }
+}
+
+/// `KEY=VALUE` lines, with `#` comments. The values can be quoted.
+/// With `is_shell`, lines can start with `export`, and `$NAME` or `${NAME}` are replaced by the
+/// variables defined before in the file, or by Horust's own (except in single quotes).
+fn parse_lines(content: &str, is_shell: bool) -> Result<Vec<(String, String)>, String> {
+    let mut vars: Vec<(String, String)> = vec![];
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = match line.strip_prefix("export ") {
+            Some(exported) if is_shell => exported.trim_start(),
+            _ => line,
+        };
+        let (key, value) = line
+            .split_once('=')
+            .map(|(key, value)| (key.trim(), value.trim()))
+            .filter(|(key, _value)| is_valid_name(key))
+            .ok_or_else(|| format!("line {}: expected KEY=VALUE, got: {}", index + 1, line))?;
+        let value = if let Some(value) = unquote(value, '\'') {
+            value.to_string()
+        } else {
+            let value = unquote(value, '"').unwrap_or(value);
+            if is_shell {
+                expand(value, &vars)
+            } else {
+                value.to_string()
+            }
+        };
+        vars.push((key.to_string(), value));
+    }
+    Ok(vars)
+}
+
+/// A json object, whose values are strings, numbers or booleans.
+fn parse_json(content: &str) -> Result<Vec<(String, String)>, String> {
+    let object: serde_json::Map<String, serde_json::Value> =
+        serde_json::from_str(content).map_err(|error| error.to_string())?;
+    object
+        .into_iter()
+        .map(|(key, value)| match value {
+            serde_json::Value::String(value) => Ok((key, value)),
+            serde_json::Value::Number(_) | serde_json::Value::Bool(_) => {
+                Ok((key, value.to_string()))
+            }
+            _ => Err(format!("the value of {} should be a string", key)),
+        })
+        .collect()
+}
+
+fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|first| first.is_ascii_alphabetic() || first == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn unquote(value: &str, quote: char) -> Option<&str> {
+    value.strip_prefix(quote)?.strip_suffix(quote)
+}
+
+/// Replaces `$NAME` and `${NAME}`: an undefined variable is replaced by an empty string.
+fn expand(value: &str, vars: &[(String, String)]) -> String {
+    let lookup = |name: &str| {
+        vars.iter()
+            .rev()
+            .find(|(key, _value)| key == name)
+            .map(|(_key, value)| value.clone())
+            .or_else(|| std::env::var(name).ok())
+            .unwrap_or_default()
+    };
+    let mut expanded = String::new();
+    let mut rest = value;
+    while let Some(start) = rest.find('$') {
+        expanded.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let (name, len) = match after.strip_prefix('{') {
+            Some(braced) => match braced.find('}') {
+                Some(end) => (&braced[..end], end + 2),
+                None => ("", 0),
+            },
+            None => {
+                let end = after
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(after.len());
+                (&after[..end], end)
+            }
+        };
+        if is_valid_name(name) {
+            expanded.push_str(lookup(name).as_str());
+        } else {
+            expanded.push('$');
+            expanded.push_str(&after[..len]);
+        }
+        rest = &after[len..];
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+#[cfg(test)]
+mod test {
+    use crate::horust::formats::env_file::parse;
+    use crate::horust::formats::EnvFileFormat;
+
+    fn vars(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        let dotenv = "# comment\nA=1\n\nB = \"two words\"\nC='$A'\nD=$A\n";
+        assert_eq!(
+            parse(dotenv, EnvFileFormat::Dotenv).unwrap(),
+            vars(&[("A", "1"), ("B", "two words"), ("C", "$A"), ("D", "$A")])
+        );
+        parse("export A=1", EnvFileFormat::Dotenv).unwrap_err();
+        parse("A", EnvFileFormat::Dotenv).unwrap_err();
+
+        let shell = "export A=1\nB=\"${A}-$A.$\"\nC='${A}'\nD=${HORUST_TEST_UNDEFINED}x\n";
+        assert_eq!(
+            parse(shell, EnvFileFormat::Shell).unwrap(),
+            vars(&[("A", "1"), ("B", "1-1.$"), ("C", "${A}"), ("D", "x")])
+        );
+
+        let json = r#"{"A": "1", "B": 2, "C": true}"#;
+        assert_eq!(
+            parse(json, EnvFileFormat::Json).unwrap(),
+            vars(&[("A", "1"), ("B", "2"), ("C", "true")])
+        );
+        parse(r#"{"A": ["1"]}"#, EnvFileFormat::Json).unwrap_err();
+        parse(r#"["A"]"#, EnvFileFormat::Json).unwrap_err();
+    }
+}
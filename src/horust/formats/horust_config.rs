@@ -0,0 +1,337 @@
+use crate::horust::error::{Result, ValidationError, ValidationErrorKind};
+use crate::horust::formats::{Service, TerminationSignal};
+use crate::horust::HorustError;
+use nix::sys::signal::Signal;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+use structopt::StructOpt;
+
+/// Horust's own configuration, read from `horust.toml`.
+/// Every option can also be provided via the command line: cli options take precedence.
+/// Boolean flags are additive: they are enabled if they are set in either of the two.
+#[derive(StructOpt, Serialize, Clone, Default, Deserialize, Debug, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct HorustConfig {
+    #[structopt(long)]
+    #[serde(default)]
+    /// Exit with an error if any service fails to start.
+    pub fail_fast: bool,
+    #[structopt(long)]
+    #[serde(default)]
+    /// Write every event as a json line into this fifo. It will be created if missing.
+    pub events_fifo: Option<PathBuf>,
+    #[structopt(long)]
+    #[serde(default)]
+    /// Write Horust's pid in this file. It's removed on exit.
+    pub pid_file: Option<PathBuf>,
+    #[structopt(long)]
+    #[serde(default)]
+    /// Overwrite the pid file, even if it belongs to a running process.
+    pub pid_file_force: bool,
+    #[structopt(long)]
+    #[serde(default)]
+    /// Max number of services started at every runtime's iteration. Unlimited by default.
+    pub startup_burst_limit: Option<usize>,
+    #[structopt(long, parse(try_from_str = humantime::parse_duration))]
+    #[serde(default, with = "humantime_serde")]
+    /// Termination wait of the services which don't set their own. Default: 5s.
+    pub default_termination_wait: Option<Duration>,
+    #[structopt(long)]
+    #[serde(default)]
+    /// Prefix Horust's log lines with a timestamp. Default: true.
+    pub log_timestamps: Option<bool>,
+    #[structopt(long)]
+    #[serde(default)]
+    /// How many of the last events are replayed to the components connecting to the bus. Default: 0.
+    pub event_history_size: Option<usize>,
+    #[structopt(long, parse(try_from_str = humantime::parse_duration))]
+    #[serde(default, with = "humantime_serde")]
+    /// Collapse the same healthcheck result of a service, repeated within this window. Default: disabled.
+    pub bus_dedup_window: Option<Duration>,
+    #[structopt(long)]
+    #[serde(default)]
+    /// The capacity of the queue of the auxiliary components connected to the bus (e.g. the events
+    /// fifo), which drop the events once it is full. Unbounded by default.
+    pub bus_subscriber_capacity: Option<usize>,
+    #[structopt(long, parse(try_from_str = humantime::parse_duration))]
+    #[serde(default, with = "humantime_serde")]
+    /// How long the bus waits for a component's full queue, before dropping the event for it. Default: 100ms.
+    pub bus_subscriber_send_timeout: Option<Duration>,
+    #[structopt(long)]
+    #[serde(default)]
+    /// Persist here the services which have been stopped, so they aren't started after a restart.
+    pub state_file: Option<PathBuf>,
+    #[structopt(skip)]
+    #[serde(default)]
+    /// On this signal, Horust re-reads this file and applies the options which can change at runtime.
+    /// TERM, INT and KILL are not allowed. Default: HUP.
+    pub horust_config_reload_signal: Option<TerminationSignal>,
+    #[structopt(skip)]
+    #[serde(default)]
+    /// Log level by module, e.g. `"horust::horust::healthcheck" = "debug"`. Overridden by HORUST_LOG.
+    /// Every service also has its own module: `horust::runtime::<service-name>`.
+    pub log_levels: BTreeMap<String, String>,
+    #[structopt(skip)]
+    #[serde(default)]
+    /// The upper bounds of the bus' dispatch latency buckets, e.g. `["1ms", "10ms"]`.
+    /// Default: 100us, 1ms, 10ms, 100ms and 1s.
+    pub bus_latency_buckets: Vec<humantime_serde::Serde<Duration>>,
+    #[structopt(skip)]
+    #[serde(default = "Vec::new")]
+    /// Services defined inline, as `[[services]]` tables. They need a name, and they take precedence
+    /// over the services in the services' directory with the same name.
+    pub services: Vec<Service>,
+}
+
+impl FromStr for HorustConfig {
+    type Err = HorustError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        toml::from_str::<HorustConfig>(s).map_err(HorustError::from)
+    }
+}
+
+impl HorustConfig {
+    /// Load the config file stored in `path` (if it exists), and merge it with `cli_config`.
+    pub fn load_and_merge(cli_config: HorustConfig, path: &Path) -> Result<Self> {
+        let config_file = if path.exists() {
+            let content = std::fs::read_to_string(path)?;
+            content.parse::<HorustConfig>()?
+        } else {
+            debug!("Config file: {} not found, using defaults.", path.display());
+            Default::default()
+        };
+        Ok(HorustConfig {
+            fail_fast: cli_config.fail_fast || config_file.fail_fast,
+            events_fifo: cli_config.events_fifo.or(config_file.events_fifo),
+            pid_file: cli_config.pid_file.or(config_file.pid_file),
+            pid_file_force: cli_config.pid_file_force || config_file.pid_file_force,
+            startup_burst_limit: cli_config
+                .startup_burst_limit
+                .or(config_file.startup_burst_limit),
+            default_termination_wait: cli_config
+                .default_termination_wait
+                .or(config_file.default_termination_wait),
+            log_timestamps: cli_config.log_timestamps.or(config_file.log_timestamps),
+            event_history_size: cli_config
+                .event_history_size
+                .or(config_file.event_history_size),
+            bus_dedup_window: cli_config.bus_dedup_window.or(config_file.bus_dedup_window),
+            bus_subscriber_capacity: cli_config
+                .bus_subscriber_capacity
+                .or(config_file.bus_subscriber_capacity),
+            bus_subscriber_send_timeout: cli_config
+                .bus_subscriber_send_timeout
+                .or(config_file.bus_subscriber_send_timeout),
+            state_file: cli_config.state_file.or(config_file.state_file),
+            horust_config_reload_signal: config_file.horust_config_reload_signal,
+            log_levels: config_file.log_levels,
+            bus_latency_buckets: config_file.bus_latency_buckets,
+            services: config_file.services,
+        })
+    }
+
+    /// The signal for reloading the config (see `horust-config-reload-signal`).
+    pub(crate) fn reload_signal(&self) -> Result<Signal> {
+        match self.horust_config_reload_signal.as_ref() {
+            None => Ok(Signal::SIGHUP),
+            Some(TerminationSignal::TERM | TerminationSignal::INT | TerminationSignal::KILL) => {
+                Err(vec![ValidationError::new(
+                    "horust-config-reload-signal can't be TERM, INT or KILL.",
+                    ValidationErrorKind::InvalidValue,
+                )]
+                .into())
+            }
+            Some(signal) => Ok(signal.as_signal()),
+        }
+    }
+
+    /// Applies the options of `reloaded` which can change at runtime. Returns the names of the
+    /// other ones which have changed: they are ignored until Horust is restarted.
+    pub(crate) fn reload(&mut self, reloaded: HorustConfig) -> Vec<&'static str> {
+        let HorustConfig {
+            fail_fast,
+            events_fifo,
+            pid_file,
+            pid_file_force,
+            startup_burst_limit,
+            default_termination_wait,
+            log_timestamps,
+            event_history_size,
+            bus_dedup_window,
+            bus_subscriber_capacity,
+            bus_subscriber_send_timeout,
+            state_file,
+            horust_config_reload_signal,
+            log_levels,
+            bus_latency_buckets,
+            services,
+        } = reloaded;
+        self.fail_fast = fail_fast;
+        self.startup_burst_limit = startup_burst_limit;
+        self.default_termination_wait = default_termination_wait;
+        self.log_timestamps = log_timestamps;
+        self.log_levels = log_levels;
+        let mut deferred = vec![];
+        let mut defer = |name: &'static str, is_changed: bool| {
+            if is_changed {
+                deferred.push(name);
+            }
+        };
+        defer("events-fifo", events_fifo != self.events_fifo);
+        defer("pid-file", pid_file != self.pid_file);
+        defer("pid-file-force", pid_file_force != self.pid_file_force);
+        defer(
+            "event-history-size",
+            event_history_size != self.event_history_size,
+        );
+        defer(
+            "bus-dedup-window",
+            bus_dedup_window != self.bus_dedup_window,
+        );
+        defer(
+            "bus-subscriber-capacity",
+            bus_subscriber_capacity != self.bus_subscriber_capacity,
+        );
+        defer(
+            "bus-subscriber-send-timeout",
+            bus_subscriber_send_timeout != self.bus_subscriber_send_timeout,
+        );
+        defer("state-file", state_file != self.state_file);
+        defer(
+            "horust-config-reload-signal",
+            horust_config_reload_signal != self.horust_config_reload_signal,
+        );
+        defer(
+            "bus-latency-buckets",
+            bus_latency_buckets != self.bus_latency_buckets,
+        );
+        defer("services", services != self.services);
+        deferred
+    }
+}
+
+/// Where Horust's config has been read from, for reloading it.
+#[derive(Debug, Clone)]
+pub(crate) struct ConfigSource {
+    pub path: PathBuf,
+    /// The cli options, which still take precedence over the reloaded file.
+    pub cli_config: HorustConfig,
+}
+
+impl ConfigSource {
+    pub(crate) fn load(&self) -> Result<HorustConfig> {
+        HorustConfig::load_and_merge(self.cli_config.clone(), &self.path)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::horust::error::Result;
+    use crate::horust::formats::{HorustConfig, TerminationSignal};
+    use nix::sys::signal::Signal;
+    use std::time::Duration;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_load_and_merge() -> Result<()> {
+        let tempdir = TempDir::new("horust-config")?;
+        let config_path = tempdir.path().join("horust.toml");
+        let config = HorustConfig::load_and_merge(Default::default(), &config_path)?;
+        assert_eq!(config, HorustConfig::default());
+
+        std::fs::write(&config_path, "fail-fast = true")?;
+        let config = HorustConfig::load_and_merge(Default::default(), &config_path)?;
+        assert!(config.fail_fast);
+
+        let cli = HorustConfig {
+            fail_fast: true,
+            events_fifo: Some("/tmp/cli-fifo".into()),
+            ..Default::default()
+        };
+        std::fs::write(
+            &config_path,
+            "fail-fast = false\nevents-fifo = \"/tmp/config-fifo\"",
+        )?;
+        let config = HorustConfig::load_and_merge(cli, &config_path)?;
+        assert!(config.fail_fast);
+        assert_eq!(config.events_fifo, Some("/tmp/cli-fifo".into()));
+
+        std::fs::write(
+            &config_path,
+            "[log-levels]\n\"horust::runtime::my-service\" = \"debug\"",
+        )?;
+        let config = HorustConfig::load_and_merge(Default::default(), &config_path)?;
+        assert_eq!(
+            config.log_levels.get("horust::runtime::my-service"),
+            Some(&"debug".to_string())
+        );
+
+        std::fs::write(
+            &config_path,
+            "[[services]]\nname = \"inline\"\ncommand = \"/bin/true\"",
+        )?;
+        let config = HorustConfig::load_and_merge(Default::default(), &config_path)?;
+        assert_eq!(config.services.len(), 1);
+        assert_eq!(config.services[0].name, "inline");
+
+        std::fs::write(&config_path, "default-termination-wait = \"1m\"")?;
+        let config = HorustConfig::load_and_merge(Default::default(), &config_path)?;
+        assert_eq!(
+            config.default_termination_wait,
+            Some(Duration::from_secs(60))
+        );
+
+        std::fs::write(&config_path, "bus-dedup-window = \"500ms\"")?;
+        let config = HorustConfig::load_and_merge(Default::default(), &config_path)?;
+        assert_eq!(config.bus_dedup_window, Some(Duration::from_millis(500)));
+
+        std::fs::write(
+            &config_path,
+            "bus-subscriber-capacity = 64\nbus-subscriber-send-timeout = \"50ms\"",
+        )?;
+        let config = HorustConfig::load_and_merge(Default::default(), &config_path)?;
+        assert_eq!(config.bus_subscriber_capacity, Some(64));
+        assert_eq!(
+            config.bus_subscriber_send_timeout,
+            Some(Duration::from_millis(50))
+        );
+
+        std::fs::write(&config_path, "bus-latency-buckets = [\"1ms\", \"1s\"]")?;
+        let config = HorustConfig::load_and_merge(Default::default(), &config_path)?;
+        assert_eq!(
+            config.bus_latency_buckets,
+            vec![
+                Duration::from_millis(1).into(),
+                Duration::from_secs(1).into()
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_reload() {
+        let mut config = HorustConfig::default();
+        let reloaded = HorustConfig {
+            fail_fast: true,
+            startup_burst_limit: Some(2),
+            events_fifo: Some("/tmp/fifo".into()),
+            horust_config_reload_signal: Some(TerminationSignal::USR1),
+            ..Default::default()
+        };
+        let deferred = config.reload(reloaded);
+        assert_eq!(deferred, vec!["events-fifo", "horust-config-reload-signal"]);
+        assert!(config.fail_fast);
+        assert_eq!(config.startup_burst_limit, Some(2));
+        assert_eq!(config.events_fifo, None);
+        assert_eq!(config.reload_signal().unwrap(), Signal::SIGHUP);
+
+        config.horust_config_reload_signal = Some(TerminationSignal::USR1);
+        assert_eq!(config.reload_signal().unwrap(), Signal::SIGUSR1);
+        config.horust_config_reload_signal = Some(TerminationSignal::TERM);
+        config.reload_signal().unwrap_err();
+    }
+}
@@ -1,30 +1,126 @@
+mod env_file;
+mod horust_config;
 mod service;
+mod service_builder;
+mod service_diff;
 mod service_handler;
+pub(crate) use env_file::read_env_file;
+pub(crate) use horust_config::ConfigSource;
+pub use horust_config::HorustConfig;
+use nix::sys::signal::Signal;
 use nix::unistd::Pid;
+use serde::{Serialize, Serializer};
 pub use service::*;
+pub use service_builder::ServiceBuilder;
+pub use service_diff::{changes_impact, ChangeImpact, ServiceFieldChange};
 pub use service_handler::ServiceHandler;
+use std::convert::TryFrom;
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Serialize, Debug, Clone, PartialEq)]
 pub enum Event {
-    PidChanged(ServiceName, Pid),
+    PidChanged(ServiceName, #[serde(serialize_with = "serialize_pid")] Pid),
     StatusChanged(ServiceName, ServiceStatus),
-    ServiceExited(ServiceName, i32),
+    ServiceExited(ServiceName, ExitStatus),
     ForceKill(ServiceName),
+    /// Start this step of the service's termination sequence.
+    TerminationStep(ServiceName, usize),
+    /// The healthchecks of the service have passed.
+    ServiceHealthy(ServiceName),
     ShuttingDownInitiated,
-    //ServiceCreated(ServiceHandler),
+    /// Horust has received a signal, to be forwarded to the services having it in `forward-signals`.
+    SignalReceived(TerminationSignal),
+    /// A new service, added after Horust's startup.
+    AddService(Box<Service>),
+    /// Stop the service and forget about it.
+    RemoveService(ServiceName),
+    /// All the services have finished: Horust will exit with this exit code.
+    Exiting(i32),
+}
+
+/// How the process of a service has terminated.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitStatus {
+    /// It has exited, with this exit code.
+    Exited(i32),
+    /// It has been killed by this signal, and true if it has dumped its core.
+    Signaled(i32, bool),
+}
+
+impl ExitStatus {
+    /// The exit code, as reported by a shell: 128 + the signal for the killed ones.
+    pub fn code(&self) -> i32 {
+        match self {
+            ExitStatus::Exited(exit_code) => *exit_code,
+            ExitStatus::Signaled(signal, _) => 128 + signal,
+        }
+    }
+
+    /// True if it has been killed by a signal, instead of exiting by itself.
+    pub fn is_signaled(&self) -> bool {
+        matches!(self, ExitStatus::Signaled(..))
+    }
+}
+
+impl std::fmt::Display for ExitStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExitStatus::Exited(exit_code) => write!(f, "exit code: {}", exit_code),
+            ExitStatus::Signaled(signal, core_dumped) => {
+                match Signal::try_from(*signal) {
+                    Ok(signal) => write!(f, "signal: {}", signal.as_str())?,
+                    Err(_) => write!(f, "signal: {}", signal)?,
+                }
+                if *core_dumped {
+                    f.write_str(" (core dumped)")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// How urgently an event is dispatched by the bus: the pending critical events go first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum EventPriority {
+    Normal,
+    /// The events which should preempt the services' updates, e.g. for shutting down quickly.
+    Critical,
 }
 
 impl Event {
+    pub fn priority(&self) -> EventPriority {
+        match self {
+            Event::ShuttingDownInitiated | Event::Exiting(_) => EventPriority::Critical,
+            _ => EventPriority::Normal,
+        }
+    }
+
+    /// True for the events which are only informative, so the same one repeated within the bus'
+    /// dedup window can be collapsed (e.g. healthcheck results).
+    pub fn is_collapsible(&self) -> bool {
+        matches!(self, Event::ServiceHealthy(_))
+    }
+
     pub(crate) fn new_pid_changed(service_name: ServiceName, pid: Pid) -> Self {
         Self::PidChanged(service_name, pid)
     }
     pub(crate) fn new_status_changed(service_name: &ServiceName, status: ServiceStatus) -> Self {
         Self::StatusChanged(service_name.clone(), status)
     }
-    pub(crate) fn new_service_exited(service_name: ServiceName, exit_status: i32) -> Self {
+    pub(crate) fn new_service_exited(service_name: ServiceName, exit_status: ExitStatus) -> Self {
         Self::ServiceExited(service_name, exit_status)
     }
+    pub(crate) fn new_service_healthy(service_name: &ServiceName) -> Self {
+        Self::ServiceHealthy(service_name.clone())
+    }
     pub(crate) fn new_force_kill(service_name: &ServiceName) -> Self {
         Self::ForceKill(service_name.clone())
     }
+    pub(crate) fn new_add_service(service: Service) -> Self {
+        Self::AddService(Box::new(service))
+    }
+}
+
+fn serialize_pid<S: Serializer>(pid: &Pid, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_i32(pid.as_raw())
 }
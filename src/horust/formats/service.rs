@@ -1,32 +1,94 @@
+use crate::horust::capabilities::capability_number;
 use crate::horust::error::{ValidationError, ValidationErrorKind};
+use crate::horust::formats::ServiceBuilder;
 use crate::horust::HorustError;
 use nix::sys::signal::Signal;
 use nix::sys::signal::{SIGHUP, SIGINT, SIGKILL, SIGQUIT, SIGTERM, SIGUSR1, SIGUSR2};
 use nix::unistd;
-use serde::export::fmt::Error;
-use serde::export::Formatter;
+use nix::unistd::getpid;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::path::PathBuf;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::fmt::{Error, Formatter};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::time::Duration;
+use url::Url;
 
 pub fn get_sample_service() -> String {
     r#"
 command = "/bin/bash -c 'echo hello world'"
+description = "Says hello to the world."
+documentation = ["https://github.com/FedericoPonzi/Horust", "man:bash(1)"]
+version = "1.0.0"
+description-file = "hello-world.md"
 working-directory = "/tmp/"
 start-delay = "2s"
 start-after = ["another.toml", "second.toml"]
+depends-on-healthy = ["db.toml"]
 user = "root"
+service-type = "simple"
+readiness-timeout = "30s"
+exec-wait-pid-file = "/run/myservice.pid"
+signal-process-group = false
+kill-mode = "process"
+new-mount-namespace = false
+tmpfs-paths = []
+read-only-paths = []
+inaccessible-paths = []
+private-tmp = false
+private-run = false
+private-network = false
+private-users = false
+new-cgroup-namespace = false
+ipc-namespace = "host"
+network-namespace = "host"
+pid-namespace = "host"
+protect-home = false
+protect-system = "no"
+capabilities-bounding-set = ["CAP_NET_BIND_SERVICE"]
+coredump-filter = "0x33"
+close-extra-fds = true
+exec-search-path = []
+command-search-paths = []
+exec-as-shell = false
+shell = "/bin/bash"
+argv0 = "hello-world"
+pre-exec-command = "/bin/mkdir -p /tmp/hello"
+pre-exec-timeout = "30s"
+priority = 50
+forward-signals = ["USR1"]
+inherit-signals = false
+inherit-systemd-activation = false
+condition-hostname = "web-[0-9]+"
+wait-for-port = { host = "db", port = 5432, timeout = "30s" }
+wait-for-file = { path = "/run/db.ready", timeout = "1m" }
+ports = [8080, 8443]
 
 [restart]
-strategy = "never"
+on-success = false
+on-failure = false
 backoff = "0s"
 attempts = 0
+min-uptime-for-reset = "10s"
+start-limit-interval = "10s"
+start-limit-burst = 5
+start-limit-action = "restart"
+ignore-exit-codes = [2]
 
 [healthiness]
-http-endpoint = "http://localhost:8080/healthcheck"
+http-endpoint = "http://%h:%p/healthcheck"
 file-path = "/var/myservice/up"
+post-healthcheck-command = "/usr/bin/register-service"
+post-healthcheck-on-change-only = true
+
+[startup-probe]
+file-path = "/var/myservice/initialized"
+initial-delay = "5s"
+period = "2s"
+failure-threshold = 30
 
 [failure]
 successful-exit-code = [ 0, 1, 255]
@@ -35,11 +97,21 @@ strategy = "ignore"
 [environment]
 key = "value"
 DB_PASS = "MyPassword"
+inherit-env-from = { service = "another.toml", vars = ["PORT"] }
+env-file = "/etc/myservice/env"
+env-file-format = "shell"
 
 [termination]
 signal = "TERM"
 wait = "10s"
 die-if-failed  = [ "db.toml"]
+stop-command = "/usr/bin/myservice-ctl stop"
+stop-timeout-action = "force-kill"
+
+[[mounts.bind]]
+source = "/etc/myservice/secrets"
+destination = "/run/secrets"
+read-only = true
 "#
     .to_string()
 }
@@ -53,6 +125,13 @@ pub struct Service {
     pub name: ServiceName,
     #[serde()]
     pub command: String,
+    /// Not used by Horust: they're only shown to the users.
+    pub description: Option<String>,
+    pub documentation: Option<Documentation>,
+    pub version: Option<String>,
+    /// A longer documentation (e.g. a runbook), read when the services are loaded and shown by `describe`.
+    /// Relative to the services path.
+    pub description_file: Option<PathBuf>,
     #[serde(default)]
     pub user: User,
     pub environment: Option<Environment>,
@@ -61,30 +140,371 @@ pub struct Service {
     pub start_delay: Duration,
     #[serde(default = "Vec::new")]
     pub start_after: Vec<ServiceName>,
+    /// Like `start_after`, but these services should also have passed their healthchecks.
+    #[serde(default = "Vec::new")]
+    pub depends_on_healthy: Vec<ServiceName>,
+    #[serde(default)]
+    pub service_type: ServiceType,
+    /// Used by `notify` services: max time to wait for `READY=1`. Zero means no timeout.
+    #[serde(default = "default_readiness_timeout", with = "humantime_serde")]
+    pub readiness_timeout: Duration,
+    /// For wrappers: once the process has exited successfully, track the pid written in this file instead.
+    pub exec_wait_pid_file: Option<PathBuf>,
+    /// Send the signals to the whole process group of the service, instead of only to its pid.
+    #[serde(default)]
+    pub signal_process_group: bool,
+    /// Which processes receive the termination signals.
+    #[serde(default)]
+    pub kill_mode: KillMode,
+    /// Run the service in a new mount namespace.
+    #[serde(default)]
+    pub new_mount_namespace: bool,
+    /// Paths where a fresh tmpfs is mounted. Requires `new-mount-namespace`.
+    #[serde(default = "Vec::new")]
+    pub tmpfs_paths: Vec<PathBuf>,
+    /// Remounted read only (on themselves), visible only to this service.
+    #[serde(default = "Vec::new")]
+    pub read_only_paths: Vec<PathBuf>,
+    /// Hidden from this service: an empty read only tmpfs (or /dev/null, for files) is mounted on them.
+    #[serde(default = "Vec::new")]
+    pub inaccessible_paths: Vec<PathBuf>,
+    /// Mount a fresh tmpfs on /tmp. Implies `new-mount-namespace`.
+    #[serde(default)]
+    pub private_tmp: bool,
+    /// Mount a fresh tmpfs on /run. Implies `new-mount-namespace`.
+    #[serde(default)]
+    pub private_run: bool,
+    /// Run the service in a new network namespace, with only the loopback interface.
+    #[serde(default)]
+    pub private_network: bool,
+    /// Run the service in a new user namespace, where the ids from 0 map to a high range outside.
+    #[serde(default)]
+    pub private_users: bool,
+    /// Run the service in a new cgroup namespace, where its own cgroup is the root.
+    #[serde(default)]
+    pub new_cgroup_namespace: bool,
+    /// The IPC namespace of the service: Horust's, a new one, or the one of another service.
+    #[serde(default)]
+    pub ipc_namespace: Namespace,
+    /// The network namespace of the service. `"private"` is the same as `private-network = true`.
+    #[serde(default)]
+    pub network_namespace: Namespace,
+    /// The pid namespace of the service. In a private one, the service is the pid 1.
+    #[serde(default)]
+    pub pid_namespace: Namespace,
+    /// Hide /home, /root and /run/user. Implies `new-mount-namespace`.
+    #[serde(default)]
+    pub protect_home: bool,
+    /// Make the system directories read only. Implies `new-mount-namespace`.
+    #[serde(default)]
+    pub protect_system: ProtectSystem,
+    /// Capabilities kept in the bounding set, e.g. `CAP_NET_BIND_SERVICE`: all the others are dropped before exec.
+    pub capabilities_bounding_set: Option<Vec<String>>,
+    /// The memory mappings written in the coredumps, as a hex bitmask (see core(5)), e.g. `0x33`.
+    pub coredump_filter: Option<String>,
+    /// Use the stdout of this other service as stdin.
+    pub stdin_from_service: Option<ServiceName>,
+    /// Close every fd above stderr before exec, instead of letting the service inherit them.
+    #[serde(default = "default_close_extra_fds")]
+    pub close_extra_fds: bool,
+    /// Directories used for looking up the command, instead of the PATH env variable.
+    #[serde(default = "Vec::new")]
+    pub exec_search_path: Vec<PathBuf>,
+    /// Directories where the command is looked up first, before the exec search path (or the PATH).
+    #[serde(default = "Vec::new")]
+    pub command_search_paths: Vec<PathBuf>,
+    /// Run the command via the shell (`<shell> -c <command>`), instead of splitting it into argv.
+    #[serde(default)]
+    pub exec_as_shell: bool,
+    /// The shell used by `exec-as-shell`. Default: /bin/sh.
+    pub shell: Option<PathBuf>,
+    /// Used as the process name (argv[0]), instead of the command's program.
+    pub argv0: Option<String>,
+    /// Run in the child before exec, after the namespaces and the user have been set up.
+    pub pre_exec_command: Option<String>,
+    /// The pre exec command is killed if it runs longer than this. Zero disables it.
+    #[serde(default, with = "humantime_serde")]
+    pub pre_exec_timeout: Duration,
+    /// From 0 to 100: services ready to start at the same time are started by higher priority.
+    #[serde(default = "default_priority")]
+    pub priority: u8,
+    /// Signals received by Horust which are forwarded to this service. Only USR1 and USR2.
+    #[serde(default = "Vec::new")]
+    pub forward_signals: Vec<TerminationSignal>,
+    /// Keep Horust's signal mask and ignored signals (e.g. SIGPIPE), instead of resetting them.
+    #[serde(default)]
+    pub inherit_signals: bool,
+    /// Pass the sockets of systemd's socket activation, which Horust has received, to this service.
+    #[serde(default)]
+    pub inherit_systemd_activation: bool,
+    /// Start the service only on the hosts whose name matches this regex (e.g. `"db-server-01"`).
+    pub condition_hostname: Option<String>,
+    /// Before spawning the service, wait until this tcp port accepts connections.
+    pub wait_for_port: Option<WaitForPort>,
+    /// Before spawning the service, wait until this file exists.
+    pub wait_for_file: Option<WaitForFile>,
+    /// The ports the service listens on. The first one replaces `%p` in the http healthcheck.
+    #[serde(default = "Vec::new")]
+    pub ports: Vec<u16>,
     #[serde(default)]
     pub restart: Restart,
     pub healthiness: Option<Healthness>,
+    pub startup_probe: Option<StartupProbe>,
     pub signal_rewrite: Option<String>,
     #[serde(skip)]
     pub last_mtime_sec: i64,
+    /// The content of the `description-file`, once it's been read.
+    #[serde(skip)]
+    pub description_file_content: Option<String>,
     #[serde(default)]
     pub failure: Failure,
     #[serde(default)]
     pub termination: Termination,
+    #[serde(default)]
+    pub mounts: Mounts,
+}
+
+/// Used by `exec-as-shell`, if the service doesn't set its `shell`.
+const DEFAULT_SHELL: &str = "/bin/sh";
+
+/// Looks for an executable `program` in `search_path`. Programs containing a `/` are not looked up.
+pub(crate) fn find_program(program: &str, search_path: &[PathBuf]) -> Option<PathBuf> {
+    if program.contains('/') {
+        return Some(program.into());
+    }
+    search_path
+        .iter()
+        .map(|dir| dir.join(program))
+        .find(|path| {
+            path.metadata()
+                .map(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+                .unwrap_or(false)
+        })
+}
+
+/// Directory holding the notify sockets of this Horust's instance.
+pub(crate) fn notify_sockets_dir() -> PathBuf {
+    std::env::temp_dir().join(format!("horust-{}", getpid()))
+}
+
+#[derive(Serialize, Clone, Default, Deserialize, Debug, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ServiceType {
+    /// The service is considered started as soon as it has a pid and its healthchecks are passing.
+    #[default]
+    Simple,
+    /// Like simple, but the service has also to send `READY=1` to the socket in `NOTIFY_SOCKET`.
+    Notify,
+    /// Like simple, but the service is started only once the other services have been started.
+    Idle,
+    /// The service is a template, as `worker@.toml`: it's not started, but its instances (as
+    /// `worker@1.toml`) are started as simple services, with `%i` replaced by the instance.
+    Template,
+}
+
+/// Which processes receive the termination signals of a service, as systemd's `KillMode=`.
+#[derive(Serialize, Clone, Copy, Default, Deserialize, Debug, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum KillMode {
+    /// Only the service's process.
+    #[default]
+    Process,
+    /// The whole process group of the service.
+    ProcessGroup,
+    /// No process: Horust just waits for the service to exit.
+    None,
+}
+
+/// A namespace of a service: `"host"`, `"private"` or `"service:<name>"`.
+#[derive(Serialize, Clone, Default, Deserialize, Debug, Eq, PartialEq)]
+#[serde(try_from = "String", into = "String")]
+pub enum Namespace {
+    /// The one of Horust.
+    #[default]
+    Host,
+    /// A new one, only for this service.
+    Private,
+    /// The one of this other service, which should have a private one.
+    Service(ServiceName),
+}
+
+impl TryFrom<String> for Namespace {
+    type Error = String;
+
+    fn try_from(value: String) -> std::result::Result<Self, Self::Error> {
+        match value.as_str() {
+            "host" => Ok(Namespace::Host),
+            "private" => Ok(Namespace::Private),
+            _ => match value.strip_prefix("service:") {
+                Some(name) if !name.is_empty() => Ok(Namespace::Service(name.to_string())),
+                _ => Err(format!(
+                    "unknown namespace '{}', expected host, private or service:<name>",
+                    value
+                )),
+            },
+        }
+    }
+}
+
+impl From<Namespace> for String {
+    fn from(namespace: Namespace) -> Self {
+        match namespace {
+            Namespace::Host => "host".to_string(),
+            Namespace::Private => "private".to_string(),
+            Namespace::Service(name) => format!("service:{}", name),
+        }
+    }
+}
+
+impl Namespace {
+    /// The service whose namespace is joined.
+    pub(crate) fn service(&self) -> Option<&ServiceName> {
+        match self {
+            Namespace::Service(name) => Some(name),
+            _ => None,
+        }
+    }
+}
+
+/// Which system directories are read only for a service, as systemd's `ProtectSystem=`.
+#[derive(Serialize, Clone, Copy, Default, Deserialize, Debug, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ProtectSystem {
+    /// They're as writable as they are for Horust.
+    #[default]
+    No,
+    /// /usr, /boot and /etc are read only.
+    Strict,
+}
+
+impl ProtectSystem {
+    /// The read only paths. The missing ones are skipped.
+    pub(crate) fn paths(&self) -> &'static [&'static str] {
+        match self {
+            ProtectSystem::No => &[],
+            ProtectSystem::Strict => &["/usr", "/boot", "/etc"],
+        }
+    }
 }
 
 #[derive(Serialize, Clone, Deserialize, Debug, Eq, PartialEq)]
 #[serde(rename_all = "kebab-case")]
 pub struct Environment {
+    /// Variables read from the process of another service, right before this one is started.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub inherit_env_from: Option<InheritEnvFrom>,
+    /// Variables read from this file, right before the service is started.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub env_file: Option<PathBuf>,
+    #[serde(default)]
+    pub env_file_format: EnvFileFormat,
     #[serde(flatten)]
     pub key_val: HashMap<String, String>,
 }
 
+/// How the `env-file` is written.
+#[derive(Serialize, Clone, Copy, Default, Deserialize, Debug, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum EnvFileFormat {
+    /// `KEY=VALUE` lines.
+    #[default]
+    Dotenv,
+    /// `export KEY=VALUE` lines, with `$NAME` or `${NAME}` references.
+    Shell,
+    /// A json object, e.g. `{"KEY": "VALUE"}`.
+    Json,
+}
+
+/// Which variables are read from which service's process, e.g. a port chosen at startup.
+#[derive(Serialize, Clone, Deserialize, Debug, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct InheritEnvFrom {
+    pub service: ServiceName,
+    pub vars: Vec<String>,
+}
+
 #[derive(Serialize, Clone, Deserialize, Debug, Eq, PartialEq)]
 #[serde(rename_all = "kebab-case", deny_unknown_fields)]
 pub struct Healthness {
     pub http_endpoint: Option<String>,
     pub file_path: Option<PathBuf>,
+    /// Run in the background after the checks have passed.
+    pub post_healthcheck_command: Option<String>,
+    /// Run the post healthcheck command only when the service becomes healthy, not after every check.
+    #[serde(default = "default_post_healthcheck_on_change_only")]
+    pub post_healthcheck_on_change_only: bool,
+}
+
+fn default_post_healthcheck_on_change_only() -> bool {
+    true
+}
+
+/// A tcp port which should accept connections, before the service is started.
+#[derive(Serialize, Clone, Deserialize, Debug, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct WaitForPort {
+    #[serde(default = "default_wait_for_port_host")]
+    pub host: String,
+    pub port: u16,
+    /// Give up after this long: the service fails to start. Zero means waiting forever.
+    #[serde(default = "default_wait_timeout", with = "humantime_serde")]
+    pub timeout: Duration,
+}
+
+fn default_wait_for_port_host() -> String {
+    "localhost".into()
+}
+
+fn default_wait_timeout() -> Duration {
+    Duration::from_secs(30)
+}
+
+/// A file which should exist, before the service is started.
+#[derive(Serialize, Clone, Deserialize, Debug, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct WaitForFile {
+    pub path: PathBuf,
+    /// Give up after this long: the service fails to start. Zero means waiting forever.
+    #[serde(default = "default_wait_timeout", with = "humantime_serde")]
+    pub timeout: Duration,
+}
+
+/// Checks run while the service is starting, before the healthiness ones: for services which take
+/// long to initialize, so they aren't considered failed before they can pass their healthiness checks.
+#[derive(Serialize, Clone, Deserialize, Debug, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct StartupProbe {
+    pub http_endpoint: Option<String>,
+    pub file_path: Option<PathBuf>,
+    /// Wait before the first check, since the service has been started.
+    #[serde(default, with = "humantime_serde")]
+    pub initial_delay: Duration,
+    /// Wait between the checks. Zero means at every healthchecks' iteration.
+    #[serde(default = "default_startup_probe_period", with = "humantime_serde")]
+    pub period: Duration,
+    /// Failed checks after which the service is killed. Zero means unlimited.
+    #[serde(default = "default_startup_probe_failure_threshold")]
+    pub failure_threshold: u32,
+}
+
+fn default_startup_probe_period() -> Duration {
+    Duration::from_secs(1)
+}
+
+fn default_startup_probe_failure_threshold() -> u32 {
+    3
+}
+
+impl Default for StartupProbe {
+    fn default() -> Self {
+        StartupProbe {
+            http_endpoint: None,
+            file_path: None,
+            initial_delay: Duration::from_secs(0),
+            period: default_startup_probe_period(),
+            failure_threshold: default_startup_probe_failure_threshold(),
+        }
+    }
 }
 
 impl Service {
@@ -93,6 +513,156 @@ impl Service {
         toml::from_str::<Service>(content.as_str()).map_err(HorustError::from)
     }
 
+    /// The http healthcheck's endpoint, with `%p` replaced by the first of the `ports` and `%h` by
+    /// `SERVICE_HOST`. It's read for every check, as the environment can change between restarts.
+    pub(crate) fn http_endpoint(&self) -> Option<String> {
+        let endpoint = self.healthiness.as_ref()?.http_endpoint.as_ref()?;
+        Some(self.expand_endpoint(endpoint))
+    }
+
+    /// The startup probe's http endpoint, replaced as the healthcheck's one.
+    pub(crate) fn startup_probe_http_endpoint(&self) -> Option<String> {
+        let endpoint = self.startup_probe.as_ref()?.http_endpoint.as_ref()?;
+        Some(self.expand_endpoint(endpoint))
+    }
+
+    fn expand_endpoint(&self, endpoint: &str) -> String {
+        let port = self
+            .ports
+            .first()
+            .map(u16::to_string)
+            .unwrap_or_else(|| "%p".into());
+        let host = self
+            .environment
+            .as_ref()
+            .and_then(|env| env.key_val.get("SERVICE_HOST").cloned())
+            .or_else(|| std::env::var("SERVICE_HOST").ok())
+            .unwrap_or_else(|| "localhost".into());
+        endpoint
+            .replace("%p", port.as_str())
+            .replace("%h", host.as_str())
+    }
+
+    /// The arguments the service is executed with: the command split as a shell would, or the
+    /// whole command passed to the shell with `exec-as-shell`. Empty if the command can't be split.
+    pub(crate) fn command_args(&self) -> Vec<String> {
+        if self.exec_as_shell {
+            return vec![
+                self.get_shell().display().to_string(),
+                "-c".into(),
+                self.command.clone(),
+            ];
+        }
+        shlex::split(&self.command).unwrap_or_default()
+    }
+
+    /// The shell used by `exec-as-shell`.
+    pub(crate) fn get_shell(&self) -> PathBuf {
+        self.shell
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_SHELL))
+    }
+
+    /// Reads the `description-file`, relative to `services_path`. A missing file is not an error.
+    pub(crate) fn load_description_file(&mut self, services_path: &Path) {
+        let path = match &self.description_file {
+            Some(path) => services_path.join(path),
+            None => return,
+        };
+        match std::fs::read_to_string(&path) {
+            Ok(content) => self.description_file_content = Some(content),
+            Err(error) => warn!(
+                "Service: {}, failed reading the description file {}: {}",
+                self.name,
+                path.display(),
+                error
+            ),
+        }
+    }
+
+    /// True if the healthiness section has at least a check.
+    pub(crate) fn has_healthchecks(&self) -> bool {
+        self.healthiness
+            .as_ref()
+            .map(|h| h.file_path.is_some() || h.http_endpoint.is_some())
+            .unwrap_or(false)
+    }
+
+    /// True if the service should run on the host named `hostname` (see `condition-hostname`).
+    /// An invalid regex doesn't match any host, but it won't pass the validation anyway.
+    pub fn runs_on_host(&self, hostname: &str) -> bool {
+        self.condition_hostname
+            .as_ref()
+            .map(|pattern| {
+                hostname_regex(pattern)
+                    .map(|regex| regex.is_match(hostname))
+                    .unwrap_or(false)
+            })
+            .unwrap_or(true)
+    }
+
+    /// True if the service runs in its own mount namespace: the `private-*` and `protect-*` options imply it.
+    pub(crate) fn has_mount_namespace(&self) -> bool {
+        self.new_mount_namespace
+            || self.private_tmp
+            || self.private_run
+            || self.protect_home
+            || self.protect_system != ProtectSystem::No
+    }
+
+    /// The instance of a template service, e.g. `1` for `worker@1.toml`.
+    /// None for the template itself (`worker@.toml`), and for the names without a `@`.
+    pub(crate) fn template_instance(&self) -> Option<&str> {
+        let name = self.name.trim_end_matches(".toml");
+        name.split_once('@')
+            .map(|(_, instance)| instance)
+            .filter(|instance| !instance.is_empty())
+    }
+
+    /// `network-namespace = "private"` is the same as `private-network = true`.
+    pub(crate) fn has_private_network(&self) -> bool {
+        self.private_network || self.network_namespace == Namespace::Private
+    }
+
+    /// `signal-process-group = true` is the same as `kill-mode = "process-group"`.
+    pub(crate) fn effective_kill_mode(&self) -> KillMode {
+        if self.signal_process_group {
+            KillMode::ProcessGroup
+        } else {
+            self.kill_mode
+        }
+    }
+
+    /// A human readable summary of this service.
+    pub fn describe(&self) -> String {
+        let mut description = match &self.version {
+            Some(version) => format!("Service: {} (version: {})\n", self.name, version),
+            None => format!("Service: {}\n", self.name),
+        };
+        if let Some(desc) = &self.description {
+            description.push_str(&format!("Description: {}\n", desc));
+        }
+        if let Some(documentation) = &self.documentation {
+            description.push_str("Documentation:\n");
+            documentation
+                .urls()
+                .iter()
+                .for_each(|url| description.push_str(&format!("  {}\n", url)));
+        }
+        description.push_str(&format!("Command: {}\n", self.command));
+        if !self.start_after.is_empty() {
+            description.push_str(&format!("Start after: {}\n", self.start_after.join(", ")));
+        }
+        if let Some(content) = &self.description_file_content {
+            description.push('\n');
+            description.push_str(content);
+            if !content.ends_with('\n') {
+                description.push('\n');
+            }
+        }
+        description
+    }
+
     /// Create the environment K=V variables, used for exec into the new process.
     /// User defined environment variables overwrite the predefined variables.
     pub fn get_environment(&self) -> Vec<String> {
@@ -100,7 +670,7 @@ impl Service {
             .environment
             .clone()
             .map(|env| env.key_val)
-            .unwrap_or_else(HashMap::new);
+            .unwrap_or_default();
         let get_env = |name: &str, default: &str| {
             (
                 name.to_string(),
@@ -127,12 +697,28 @@ impl Service {
             additional.insert("TERM".into(), term);
         }
 
+        if self.service_type == ServiceType::Notify {
+            additional.insert(
+                "NOTIFY_SOCKET".into(),
+                self.notify_socket_path().display().to_string(),
+            );
+        }
+
         additional
             .into_iter()
             .map(|(k, v)| format!("{}={}", k, v))
             .collect()
     }
 
+    /// Path of the socket used by `notify` services for sending their readiness.
+    pub(crate) fn notify_socket_path(&self) -> PathBuf {
+        notify_sockets_dir().join(format!("{}.sock", self.name))
+    }
+
+    pub fn builder() -> ServiceBuilder {
+        ServiceBuilder::default()
+    }
+
     pub fn from_command(command: String) -> Self {
         Service {
             name: command.clone(),
@@ -144,10 +730,56 @@ impl Service {
             start_delay: Duration::from_secs(0),
             command,
             healthiness: None,
+            startup_probe: None,
             signal_rewrite: None,
             last_mtime_sec: 0,
+            description_file_content: None,
             failure: Default::default(),
             termination: Default::default(),
+            service_type: Default::default(),
+            readiness_timeout: default_readiness_timeout(),
+            exec_wait_pid_file: None,
+            signal_process_group: false,
+            kill_mode: Default::default(),
+            new_mount_namespace: false,
+            tmpfs_paths: vec![],
+            read_only_paths: vec![],
+            inaccessible_paths: vec![],
+            private_tmp: false,
+            private_run: false,
+            private_network: false,
+            private_users: false,
+            new_cgroup_namespace: false,
+            ipc_namespace: Default::default(),
+            network_namespace: Default::default(),
+            pid_namespace: Default::default(),
+            protect_home: false,
+            protect_system: Default::default(),
+            capabilities_bounding_set: None,
+            coredump_filter: None,
+            stdin_from_service: None,
+            close_extra_fds: true,
+            exec_search_path: vec![],
+            command_search_paths: vec![],
+            exec_as_shell: false,
+            shell: None,
+            argv0: None,
+            pre_exec_command: None,
+            pre_exec_timeout: Duration::from_secs(0),
+            priority: default_priority(),
+            forward_signals: vec![],
+            inherit_signals: false,
+            inherit_systemd_activation: false,
+            condition_hostname: None,
+            wait_for_port: None,
+            wait_for_file: None,
+            ports: Vec::new(),
+            description: None,
+            documentation: None,
+            version: None,
+            description_file: None,
+            depends_on_healthy: vec![],
+            mounts: Default::default(),
         }
     }
 }
@@ -160,6 +792,23 @@ impl FromStr for Service {
     }
 }
 
+/// Links to the documentation of a service: either an url or a list of urls.
+#[derive(Serialize, Clone, Deserialize, Debug, Eq, PartialEq)]
+#[serde(untagged)]
+pub enum Documentation {
+    Url(String),
+    Urls(Vec<String>),
+}
+
+impl Documentation {
+    pub fn urls(&self) -> Vec<&str> {
+        match self {
+            Documentation::Url(url) => vec![url.as_str()],
+            Documentation::Urls(urls) => urls.iter().map(String::as_str).collect(),
+        }
+    }
+}
+
 /// A user in the system.
 /// It can be either a uuid or a username (available in passwd)
 #[derive(Serialize, Clone, Deserialize, Debug, Eq, PartialEq)]
@@ -243,6 +892,9 @@ pub enum ServiceStatus {
     /// This is the initial state: A service in Initial state is marked to be runnable:
     /// it will be run as soon as possible.
     Initial,
+    /// Restarted too often (see `start-limit-burst`): it's waiting for `start-limit-interval`
+    /// before being restarted again.
+    StartLimitHit,
 }
 
 impl std::fmt::Display for ServiceStatus {
@@ -258,6 +910,7 @@ impl std::fmt::Display for ServiceStatus {
             ServiceStatus::Initial => "Initial",
             ServiceStatus::Success => "Success",
             ServiceStatus::FinishedFailed => "FinishedFailed",
+            ServiceStatus::StartLimitHit => "StartLimitHit",
         })
     }
 }
@@ -265,41 +918,116 @@ impl std::fmt::Display for ServiceStatus {
 #[derive(Serialize, Clone, Deserialize, Debug, Eq, PartialEq)]
 #[serde(rename_all = "kebab-case", deny_unknown_fields)]
 pub struct Restart {
+    /// Superseded by `on_success` and `on_failure`, when they're set.
     #[serde(default)]
     pub strategy: RestartStrategy,
+    /// Restart the service after it has exited successfully.
+    pub on_success: Option<bool>,
+    /// Restart the service after it has failed.
+    pub on_failure: Option<bool>,
     #[serde(default, with = "humantime_serde")]
     pub backoff: Duration,
     #[serde(default = "default_attempts")]
     pub attempts: u32,
+    /// If set, a service which exits before running this long counts as an attempt, and the
+    /// attempts are reset only once it has run this long. Zero disables it.
+    #[serde(default, with = "humantime_serde")]
+    pub min_uptime_for_reset: Duration,
+    /// The window used by `start_limit_burst`. Zero disables the limit.
+    #[serde(default, with = "humantime_serde")]
+    pub start_limit_interval: Duration,
+    /// How many starts are allowed within `start_limit_interval`. Zero disables the limit.
+    #[serde(default)]
+    pub start_limit_burst: u32,
+    /// What to do once the limit has been hit.
+    #[serde(default)]
+    pub start_limit_action: StartLimitAction,
+    /// Exit codes treated as a clean exit by the restart policy, even if they're not successful.
+    #[serde(default = "Vec::new")]
+    pub ignore_exit_codes: Vec<i32>,
+}
+
+/// What happens to a service which has hit its start limit.
+#[derive(Serialize, Clone, Copy, Default, Deserialize, Debug, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum StartLimitAction {
+    /// It's restarted again, once `start-limit-interval` has passed since its last start.
+    Restart,
+    /// It's not restarted anymore: it's FinishedFailed.
+    #[default]
+    None,
 }
 fn default_attempts() -> u32 {
     10
 }
 
+fn default_readiness_timeout() -> Duration {
+    Duration::from_secs(30)
+}
+
+fn default_close_extra_fds() -> bool {
+    true
+}
+
+fn default_priority() -> u8 {
+    50
+}
+
+impl Restart {
+    pub fn restarts_on_success(&self) -> bool {
+        self.on_success
+            .unwrap_or(self.strategy == RestartStrategy::Always)
+    }
+
+    /// True if the `start-limit-burst` and `start-limit-interval` are set.
+    pub fn has_start_limit(&self) -> bool {
+        self.start_limit_burst > 0 && self.start_limit_interval > Duration::from_secs(0)
+    }
+
+    pub fn restarts_on_failure(&self) -> bool {
+        self.on_failure.unwrap_or(matches!(
+            self.strategy,
+            RestartStrategy::Always | RestartStrategy::OnFailure
+        ))
+    }
+
+    /// Restart the service after it has been killed by a signal (or dumped its core).
+    pub fn restarts_on_abnormal(&self) -> bool {
+        self.on_failure.unwrap_or(matches!(
+            self.strategy,
+            RestartStrategy::Always | RestartStrategy::OnFailure | RestartStrategy::OnAbnormal
+        ))
+    }
+}
+
 impl Default for Restart {
     fn default() -> Self {
         Restart {
             strategy: RestartStrategy::Never,
+            on_success: None,
+            on_failure: None,
             backoff: Duration::from_secs(0),
             attempts: 0,
+            min_uptime_for_reset: Duration::from_secs(0),
+            start_limit_interval: Duration::from_secs(0),
+            start_limit_burst: 0,
+            start_limit_action: Default::default(),
+            ignore_exit_codes: vec![],
         }
     }
 }
 
-#[derive(Serialize, Clone, Deserialize, Debug, Eq, PartialEq)]
+#[derive(Serialize, Clone, Default, Deserialize, Debug, Eq, PartialEq)]
 #[serde(rename_all = "kebab-case", deny_unknown_fields)]
 pub enum RestartStrategy {
     Always,
     OnFailure,
+    /// Only after it has been killed by a signal, not after a non zero exit code.
+    OnAbnormal,
+    #[default]
     Never,
 }
 
-impl Default for RestartStrategy {
-    fn default() -> Self {
-        RestartStrategy::Never
-    }
-}
-
 impl From<String> for RestartStrategy {
     fn from(strategy: String) -> Self {
         strategy.as_str().into()
@@ -311,6 +1039,7 @@ impl From<&str> for RestartStrategy {
         match strategy.to_lowercase().as_str() {
             "always" => RestartStrategy::Always,
             "on-failure" => RestartStrategy::OnFailure,
+            "on-abnormal" => RestartStrategy::OnAbnormal,
             "never" => RestartStrategy::Never,
             _ => RestartStrategy::Never,
         }
@@ -365,35 +1094,79 @@ impl From<&str> for FailureStrategy {
     }
 }
 
-#[derive(Serialize, Clone, Deserialize, Debug, Eq, PartialEq)]
+#[derive(Serialize, Clone, Default, Deserialize, Debug, Eq, PartialEq)]
 #[serde(rename_all = "kebab-case", deny_unknown_fields)]
 pub struct Termination {
     #[serde(default)]
     pub(crate) signal: TerminationSignal,
-    #[serde(default = "Termination::default_wait", with = "humantime_serde")]
-    pub wait: Duration,
+    /// If missing, Horust's `default-termination-wait` is used.
+    #[serde(default, with = "humantime_serde")]
+    pub wait: Option<Duration>,
     #[serde(default = "Vec::new")]
     pub die_if_failed: Vec<ServiceName>,
+    /// If set, it's used instead of `signal` and `wait`.
+    #[serde(default = "Vec::new")]
+    pub sequence: Vec<TerminationStep>,
+    /// Run instead of sending the first signal, e.g. `pg_ctl stop`. The wait still applies.
+    pub stop_command: Option<String>,
+    /// What happens once the termination wait is over, and the service is still running.
+    #[serde(default)]
+    pub stop_timeout_action: StopTimeoutAction,
+}
+
+/// What happens to a service which hasn't exited within its termination wait.
+#[derive(Serialize, Clone, Copy, Default, Deserialize, Debug, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum StopTimeoutAction {
+    /// Its whole process group is killed with a SIGKILL.
+    #[default]
+    ForceKill,
+    /// A SIGABRT is sent to its process, for getting a coredump. It's force killed if it's still
+    /// running after another termination wait.
+    Abort,
+    /// A warning is logged, and Horust keeps waiting for it to exit.
+    Warn,
+}
+
+/// A step of the termination sequence: `signal` is sent, and the next step starts after `wait`.
+#[derive(Serialize, Clone, Deserialize, Debug, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct TerminationStep {
+    pub signal: TerminationSignal,
+    #[serde(default, with = "humantime_serde")]
+    pub wait: Duration,
 }
 
 impl Termination {
-    fn default_wait() -> Duration {
-        Duration::from_secs(5)
+    /// The termination wait, falling back to `default_wait` (or 5 seconds) if it's not set.
+    pub fn wait_or(&self, default_wait: Option<Duration>) -> Duration {
+        self.wait
+            .or(default_wait)
+            .unwrap_or_else(|| Duration::from_secs(5))
     }
 }
 
-impl Default for Termination {
-    fn default() -> Self {
-        Termination {
-            signal: Default::default(),
-            wait: Self::default_wait(),
-            die_if_failed: Vec::new(),
-        }
-    }
+/// Mounts done in the service's mount namespace.
+#[derive(Serialize, Clone, Default, Deserialize, Debug, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Mounts {
+    #[serde(default = "Vec::new")]
+    pub bind: Vec<BindMount>,
 }
 
 #[derive(Serialize, Clone, Deserialize, Debug, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct BindMount {
+    pub source: PathBuf,
+    pub destination: PathBuf,
+    #[serde(default)]
+    pub read_only: bool,
+}
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Serialize, Clone, Default, Deserialize, Debug, Eq, PartialEq)]
 pub enum TerminationSignal {
+    #[default]
     TERM,
     HUP,
     INT,
@@ -416,16 +1189,447 @@ impl TerminationSignal {
         }
     }
 }
-impl Default for TerminationSignal {
-    fn default() -> Self {
-        TerminationSignal::TERM
+
+/// The whole hostname should match the pattern.
+fn hostname_regex(pattern: &str) -> Result<Regex, regex::Error> {
+    Regex::new(format!("^(?:{})$", pattern).as_str())
+}
+
+/// Parses a `coredump-filter`, as the hex bitmask of /proc/<pid>/coredump_filter, e.g. `0x33`.
+pub(crate) fn parse_coredump_filter(filter: &str) -> Option<u32> {
+    let digits = filter.strip_prefix("0x").unwrap_or(filter);
+    if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
     }
+    u32::from_str_radix(digits, 16).ok()
+}
+
+/// An invalid value in the options of `service`.
+fn invalid_option(service: &Service, message: &str) -> ValidationError {
+    let err = format!("Service '{}': {}", service.name, message);
+    ValidationError::new(err.as_str(), ValidationErrorKind::InvalidValue)
+}
+
+/// Checks on the options of a single service, regardless of the other services.
+pub(crate) fn validate_options(service: &Service) -> Vec<ValidationError> {
+    let mut errors = vec![];
+    service
+        .capabilities_bounding_set
+        .iter()
+        .flatten()
+        .filter(|name| capability_number(name).is_none())
+        .for_each(|name| {
+            errors.push(invalid_option(
+                service,
+                &format!(
+                    "capabilities-bounding-set has an unknown capability: '{}'.",
+                    name
+                ),
+            ));
+        });
+    if let Some(filter) = &service.coredump_filter {
+        if parse_coredump_filter(filter).is_none() {
+            errors.push(invalid_option(
+                service,
+                &format!(
+                    "coredump-filter should be a hex bitmask, as '0x33', found: '{}'.",
+                    filter
+                ),
+            ));
+        }
+    }
+    if service.service_type == ServiceType::Template {
+        errors.push(invalid_option(
+            service,
+            "it's a template, only its instances (as worker@1.toml for worker@.toml) can be run.",
+        ));
+    }
+    if !service.read_only_paths.is_empty() && !service.has_mount_namespace() {
+        errors.push(invalid_option(
+            service,
+            "read-only-paths require new-mount-namespace = true.",
+        ));
+    }
+    service
+        .read_only_paths
+        .iter()
+        .filter(|path| !path.is_absolute() || !path.exists())
+        .for_each(|path| {
+            errors.push(invalid_option(
+                service,
+                &format!(
+                    "read-only-paths '{}' should be an existing absolute path.",
+                    path.display()
+                ),
+            ));
+        });
+    if !service.inaccessible_paths.is_empty() && !service.has_mount_namespace() {
+        errors.push(invalid_option(
+            service,
+            "inaccessible-paths require new-mount-namespace = true.",
+        ));
+    }
+    service
+        .inaccessible_paths
+        .iter()
+        .filter(|path| !path.is_absolute() || !path.exists())
+        .for_each(|path| {
+            errors.push(invalid_option(
+                service,
+                &format!(
+                    "inaccessible-paths '{}' should be an existing absolute path.",
+                    path.display()
+                ),
+            ));
+        });
+    if !service.tmpfs_paths.is_empty() && !service.has_mount_namespace() {
+        errors.push(invalid_option(
+            service,
+            "tmpfs-paths require new-mount-namespace = true.",
+        ));
+    }
+    service
+        .documentation
+        .iter()
+        .flat_map(Documentation::urls)
+        .filter(|url| Url::parse(url).is_err())
+        .for_each(|url| {
+            errors.push(invalid_option(
+                service,
+                &format!("documentation '{}' is not a valid url.", url),
+            ));
+        });
+    if service.priority > 100 {
+        errors.push(invalid_option(
+            service,
+            &format!("priority {} should be between 0 and 100.", service.priority),
+        ));
+    }
+    service
+        .forward_signals
+        .iter()
+        .filter(|signal| ![TerminationSignal::USR1, TerminationSignal::USR2].contains(signal))
+        .for_each(|signal| {
+            errors.push(invalid_option(
+                service,
+                &format!(
+                    "signal {:?} can't be forwarded, only USR1 and USR2 are supported.",
+                    signal
+                ),
+            ));
+        });
+    if let Some(last) = service.termination.sequence.last() {
+        if last.signal != TerminationSignal::KILL && last.wait != Duration::from_secs(0) {
+            errors.push(invalid_option(
+                service,
+                "the termination sequence should end with KILL, or with a step with wait = \"0s\".",
+            ));
+        }
+    }
+    if let Some(error) = service
+        .condition_hostname
+        .as_ref()
+        .and_then(|pattern| hostname_regex(pattern).err())
+    {
+        errors.push(invalid_option(
+            service,
+            &format!("condition-hostname is not a valid regex: {}", error),
+        ));
+    }
+    let healthcheck_endpoint = service
+        .healthiness
+        .as_ref()
+        .and_then(|healthiness| healthiness.http_endpoint.as_ref());
+    let startup_probe_endpoint = service
+        .startup_probe
+        .as_ref()
+        .and_then(|probe| probe.http_endpoint.as_ref());
+    if service.ports.is_empty()
+        && healthcheck_endpoint
+            .into_iter()
+            .chain(startup_probe_endpoint)
+            .any(|endpoint| endpoint.contains("%p"))
+    {
+        errors.push(invalid_option(
+            service,
+            "the http-endpoint contains %p, but there are no ports.",
+        ));
+    }
+    if let Some(command) = service
+        .healthiness
+        .as_ref()
+        .and_then(|healthiness| healthiness.post_healthcheck_command.as_ref())
+        .filter(|command| shlex::split(command).unwrap_or_default().is_empty())
+    {
+        errors.push(invalid_option(
+            service,
+            &format!(
+                "post-healthcheck-command '{}' is not a valid command.",
+                command
+            ),
+        ));
+    }
+    if let Some(inherit) = service
+        .environment
+        .as_ref()
+        .and_then(|environment| environment.inherit_env_from.as_ref())
+        .filter(|inherit| {
+            !service.start_after.contains(&inherit.service)
+                && !service.depends_on_healthy.contains(&inherit.service)
+        })
+    {
+        errors.push(invalid_option(
+            service,
+            &format!(
+                "inherit-env-from '{}' should be in start-after or depends-on-healthy.",
+                inherit.service
+            ),
+        ));
+    }
+    if let Some(command) = service
+        .termination
+        .stop_command
+        .as_ref()
+        .filter(|command| shlex::split(command).unwrap_or_default().is_empty())
+    {
+        errors.push(invalid_option(
+            service,
+            &format!("stop-command '{}' is not a valid command.", command),
+        ));
+    }
+    if let Some(command) = service
+        .pre_exec_command
+        .as_ref()
+        .filter(|command| shlex::split(command).unwrap_or_default().is_empty())
+    {
+        errors.push(invalid_option(
+            service,
+            &format!("pre-exec-command '{}' is not a valid command.", command),
+        ));
+    }
+    if let Some(argv0) = service
+        .argv0
+        .as_ref()
+        .filter(|argv0| argv0.is_empty() || argv0.contains('/'))
+    {
+        errors.push(invalid_option(
+            service,
+            &format!(
+                "argv0 '{}' should be a non empty name, without any '/'.",
+                argv0
+            ),
+        ));
+    }
+    service
+        .exec_search_path
+        .iter()
+        .filter(|path| !path.is_dir())
+        .for_each(|path| {
+            errors.push(invalid_option(
+                service,
+                &format!(
+                    "exec-search-path '{}' is not an existing directory.",
+                    path.display()
+                ),
+            ));
+        });
+    if service.exec_as_shell || service.shell.is_some() {
+        let shell = service.get_shell();
+        if !shell.is_file() {
+            errors.push(invalid_option(
+                service,
+                &format!("the shell '{}' doesn't exist.", shell.display()),
+            ));
+        }
+    }
+    if !service.command_search_paths.is_empty() {
+        let program = service
+            .command_args()
+            .into_iter()
+            .next()
+            .unwrap_or_default();
+        let fallback = if service.exec_search_path.is_empty() {
+            std::env::var_os("PATH")
+                .map(|path| std::env::split_paths(&path).collect())
+                .unwrap_or_default()
+        } else {
+            service.exec_search_path.clone()
+        };
+        let search_path: Vec<PathBuf> = service
+            .command_search_paths
+            .iter()
+            .cloned()
+            .chain(fallback)
+            .collect();
+        if find_program(&program, &search_path).is_none() {
+            errors.push(invalid_option(
+                service,
+                &format!(
+                    "'{}' wasn't found in the command-search-paths, nor in the exec search path.",
+                    program
+                ),
+            ));
+        }
+    }
+    if !service.mounts.bind.is_empty() && !service.has_mount_namespace() {
+        errors.push(invalid_option(
+            service,
+            "bind mounts require new-mount-namespace = true.",
+        ));
+    }
+    service
+        .mounts
+        .bind
+        .iter()
+        .filter(|bind| !bind.source.exists())
+        .for_each(|bind| {
+            errors.push(invalid_option(
+                service,
+                &format!(
+                    "the source of the bind mount '{}' doesn't exist.",
+                    bind.source.display()
+                ),
+            ));
+        });
+    errors
+}
+
+/// The services whose namespaces are joined should exist, have a private namespace, and be started first.
+fn validate_joined_namespaces(service: &Service, services: &[Service]) -> Vec<ValidationError> {
+    let mut errors = vec![];
+    type IsPrivate = fn(&Service) -> bool;
+    let joined: [(&str, &Namespace, IsPrivate); 3] = [
+        ("ipc-namespace", &service.ipc_namespace, |other| {
+            other.ipc_namespace == Namespace::Private
+        }),
+        (
+            "network-namespace",
+            &service.network_namespace,
+            Service::has_private_network,
+        ),
+        ("pid-namespace", &service.pid_namespace, |other| {
+            other.pid_namespace == Namespace::Private
+        }),
+    ];
+    for (option, namespace, is_private) in joined.iter() {
+        let name = match namespace.service() {
+            Some(name) => name,
+            None => continue,
+        };
+        let err = match services.iter().find(|s| s.name == *name) {
+            None => Some(format!(
+                "Service '{}': {} '{}', there is no service with such name.",
+                service.name, option, name
+            )),
+            Some(other) if !is_private(other) => Some(format!(
+                "Service '{}': {} '{}' should have a private namespace.",
+                service.name, option, name
+            )),
+            Some(_)
+                if !service.start_after.contains(name)
+                    && !service.depends_on_healthy.contains(name) =>
+            {
+                Some(format!(
+                    "Service '{}': {} '{}' should be in start-after or depends-on-healthy.",
+                    service.name, option, name
+                ))
+            }
+            Some(_) => None,
+        };
+        if let Some(err) = err {
+            errors.push(ValidationError::new(
+                err.as_str(),
+                ValidationErrorKind::InvalidValue,
+            ));
+        }
+    }
+    if service.private_network && service.network_namespace.service().is_some() {
+        let err = format!(
+            "Service '{}' has private-network, but it joins the network-namespace of another service.",
+            service.name
+        );
+        errors.push(ValidationError::new(
+            err.as_str(),
+            ValidationErrorKind::InvalidValue,
+        ));
+    }
+    errors
+}
+
+/// The producer should exist, and it can't be used by more than one consumer.
+fn validate_stdin_from_service(service: &Service, services: &[Service]) -> Vec<ValidationError> {
+    let mut errors = vec![];
+    let producer = match &service.stdin_from_service {
+        Some(producer) => producer,
+        None => return errors,
+    };
+    if *producer == service.name {
+        let err = format!("Service '{}' can't read its own stdout.", service.name);
+        errors.push(ValidationError::new(
+            err.as_str(),
+            ValidationErrorKind::InvalidValue,
+        ));
+    } else if !services.iter().any(|s| s.name == *producer) {
+        let err = format!(
+            "Service '{}', should read the stdout of '{}', but there is no service with such name.",
+            service.name, producer
+        );
+        errors.push(ValidationError::new(
+            err.as_str(),
+            ValidationErrorKind::MissingDependency,
+        ));
+    }
+    let first_consumer = services
+        .iter()
+        .find(|s| s.stdin_from_service.as_ref() == Some(producer))
+        .map(|s| &s.name);
+    if first_consumer != Some(&service.name) {
+        let err = format!(
+            "Service '{}', should read the stdout of '{}', but it's already read by '{}'.",
+            service.name,
+            producer,
+            first_consumer.unwrap()
+        );
+        errors.push(ValidationError::new(
+            err.as_str(),
+            ValidationErrorKind::InvalidValue,
+        ));
+    }
+    errors
 }
 
 /// Runs some validation checks on the services.
 pub fn validate(services: Vec<Service>) -> Result<Vec<Service>, Vec<ValidationError>> {
     let mut errors = vec![];
     services.iter().for_each(|service| {
+        errors.extend(validate_options(service));
+        errors.extend(validate_stdin_from_service(service, &services));
+        errors.extend(validate_joined_namespaces(service, &services));
+        service.depends_on_healthy.iter().for_each(|name| {
+            match services.iter().find(|s| s.name == *name) {
+                Some(dependency) if !dependency.has_healthchecks() => {
+                    let err = format!(
+                        "Service '{}', depends on '{}' being healthy, but it has no healthchecks.",
+                        service.name, name
+                    );
+                    errors.push(ValidationError::new(
+                        err.as_str(),
+                        ValidationErrorKind::InvalidValue,
+                    ));
+                }
+                Some(_) => (),
+                None => {
+                    let err = format!(
+                        "Service '{}', depends on '{}' being healthy, but there is no service with such name.",
+                        service.name, name
+                    );
+                    errors.push(ValidationError::new(
+                        err.as_str(),
+                        ValidationErrorKind::MissingDependency,
+                    ));
+                }
+            }
+        });
         if !service.start_after.is_empty() {
             debug!(
                 "Checking if all depedencies of '{}' exists, deps: {:?}",
@@ -450,17 +1654,70 @@ pub fn validate(services: Vec<Service>) -> Result<Vec<Service>, Vec<ValidationEr
     }
 }
 
+/// Validates a service added at runtime, against the names of the services already known.
+pub fn validate_added(
+    service: &Service,
+    known_services: &HashSet<ServiceName>,
+) -> Result<(), Vec<ValidationError>> {
+    let mut errors = validate_options(service);
+    if known_services.contains(&service.name) {
+        let err = format!("Service '{}' already exists.", service.name);
+        errors.push(ValidationError::new(
+            err.as_str(),
+            ValidationErrorKind::DuplicateService,
+        ));
+    }
+    if let Some(producer) = service
+        .stdin_from_service
+        .as_ref()
+        .filter(|producer| !known_services.contains(*producer))
+    {
+        let err = format!(
+            "Service '{}', should read the stdout of '{}', but there is no service with such name.",
+            service.name, producer
+        );
+        errors.push(ValidationError::new(
+            err.as_str(),
+            ValidationErrorKind::MissingDependency,
+        ));
+    }
+    service
+        .start_after
+        .iter()
+        .chain(service.depends_on_healthy.iter())
+        .filter(|name| !known_services.contains(*name))
+        .for_each(|name| {
+            let err = format!(
+                "Service '{}', should start after '{}', but there is no service with such name.",
+                service.name, name
+            );
+            errors.push(ValidationError::new(
+                err.as_str(),
+                ValidationErrorKind::MissingDependency,
+            ));
+        });
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use crate::horust::formats::TerminationSignal::TERM;
     use crate::horust::formats::User::Name;
     use crate::horust::formats::{
-        validate, Environment, Failure, FailureStrategy, Healthness, Restart, RestartStrategy,
-        Service, Termination,
+        find_program, parse_coredump_filter, validate, validate_added, BindMount, Documentation,
+        EnvFileFormat, Environment, Failure, FailureStrategy, Healthness, InheritEnvFrom, Mounts,
+        Namespace, ProtectSystem, Restart, RestartStrategy, Service, ServiceType, StartLimitAction,
+        StartupProbe, StopTimeoutAction, Termination, TerminationSignal, TerminationStep,
+        WaitForFile, WaitForPort,
     };
     use crate::horust::get_sample_service;
     use std::str::FromStr;
     use std::time::Duration;
+    use tempdir::TempDir;
 
     impl Service {
         pub fn start_after(name: &str, start_after: Vec<&str>) -> Self {
@@ -473,11 +1730,57 @@ mod test {
                 start_delay: Duration::from_secs(0),
                 command: "".to_string(),
                 healthiness: None,
+                startup_probe: None,
                 signal_rewrite: None,
                 environment: None,
                 last_mtime_sec: 0,
+                description_file_content: None,
                 failure: Default::default(),
                 termination: Default::default(),
+                service_type: Default::default(),
+                readiness_timeout: Duration::from_secs(30),
+                exec_wait_pid_file: None,
+                signal_process_group: false,
+                kill_mode: Default::default(),
+                new_mount_namespace: false,
+                tmpfs_paths: vec![],
+                read_only_paths: vec![],
+                inaccessible_paths: vec![],
+                private_tmp: false,
+                private_run: false,
+                private_network: false,
+                private_users: false,
+                new_cgroup_namespace: false,
+                ipc_namespace: Default::default(),
+                network_namespace: Default::default(),
+                pid_namespace: Default::default(),
+                protect_home: false,
+                protect_system: Default::default(),
+                capabilities_bounding_set: None,
+                coredump_filter: None,
+                stdin_from_service: None,
+                close_extra_fds: true,
+                exec_search_path: vec![],
+                command_search_paths: vec![],
+                exec_as_shell: false,
+                shell: None,
+                argv0: None,
+                pre_exec_command: None,
+                pre_exec_timeout: Duration::from_secs(0),
+                priority: 50,
+                forward_signals: vec![],
+                inherit_signals: false,
+                inherit_systemd_activation: false,
+                condition_hostname: None,
+                wait_for_port: None,
+                wait_for_file: None,
+                ports: Vec::new(),
+                description: None,
+                documentation: None,
+                version: None,
+                description_file: None,
+                depends_on_healthy: vec![],
+                mounts: Default::default(),
             }
         }
 
@@ -486,6 +1789,110 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_find_program() {
+        let search_path = vec!["/non-existent".into(), "/bin".into()];
+        assert_eq!(find_program("sh", &search_path), Some("/bin/sh".into()));
+        assert_eq!(find_program("/bin/sh", &[]), Some("/bin/sh".into()));
+        assert_eq!(find_program("sh", &["/non-existent".into()]), None);
+    }
+
+    #[test]
+    fn test_validate_command_search_paths() {
+        let mut service = Service::from_command("horust-non-existent --flag".into());
+        service.command_search_paths = vec!["/non-existent".into()];
+        validate(vec![service.clone()]).unwrap_err();
+        // The exec search path is used as well:
+        service.command = "sh -c true".into();
+        validate(vec![service.clone()]).expect("Validation failed");
+        service.exec_search_path = vec!["/usr".into()];
+        validate(vec![service]).unwrap_err();
+    }
+
+    #[test]
+    fn test_validate_joined_namespaces() {
+        let mut server = Service::from_command("/bin/true".into());
+        server.name = "server".into();
+        let mut client = Service::start_after("client", vec!["server"]);
+        client.ipc_namespace = Namespace::Service("server".into());
+        validate(vec![server.clone(), client.clone()]).unwrap_err();
+        server.ipc_namespace = Namespace::Private;
+        validate(vec![server.clone(), client.clone()]).expect("Validation failed");
+        client.start_after.clear();
+        validate(vec![server.clone(), client.clone()]).unwrap_err();
+
+        client.start_after = vec!["server".into()];
+        client.network_namespace = Namespace::Service("server".into());
+        validate(vec![server.clone(), client.clone()]).unwrap_err();
+        server.private_network = true;
+        validate(vec![server.clone(), client.clone()]).expect("Validation failed");
+        client.private_network = true;
+        validate(vec![server.clone(), client.clone()]).unwrap_err();
+
+        client.private_network = false;
+        client.pid_namespace = Namespace::Service("server".into());
+        validate(vec![server.clone(), client.clone()]).unwrap_err();
+        server.pid_namespace = Namespace::Private;
+        validate(vec![server, client]).expect("Validation failed");
+
+        let namespace: Namespace = toml::from_str::<Service>(
+            "command = \"/bin/true\"\nipc-namespace = \"service:server.toml\"",
+        )
+        .unwrap()
+        .ipc_namespace;
+        assert_eq!(namespace, Namespace::Service("server.toml".into()));
+        toml::from_str::<Service>("command = \"/bin/true\"\nipc-namespace = \"service:\"")
+            .unwrap_err();
+    }
+
+    #[test]
+    fn test_validate_capabilities_bounding_set() {
+        let mut service = Service::from_command("/bin/true".into());
+        service.capabilities_bounding_set = Some(vec!["CAP_NET_ADMIN".into()]);
+        validate(vec![service.clone()]).expect("Validation failed");
+        service.capabilities_bounding_set = Some(vec!["NET_ADMIN".into()]);
+        validate(vec![service]).unwrap_err();
+    }
+
+    #[test]
+    fn test_validate_coredump_filter() {
+        assert_eq!(parse_coredump_filter("0x33"), Some(0x33));
+        assert_eq!(parse_coredump_filter("1ff"), Some(0x1ff));
+        assert_eq!(parse_coredump_filter("0x"), None);
+        assert_eq!(parse_coredump_filter("+33"), None);
+        assert_eq!(parse_coredump_filter("0x100000000"), None);
+        let mut service = Service::from_command("/bin/true".into());
+        service.coredump_filter = Some("0x33".into());
+        validate(vec![service.clone()]).expect("Validation failed");
+        service.coredump_filter = Some("all".into());
+        validate(vec![service]).unwrap_err();
+    }
+
+    #[test]
+    fn test_restart_flags() {
+        let mut restart = Restart::default();
+        assert!(!restart.restarts_on_success());
+        assert!(!restart.restarts_on_failure());
+        restart.strategy = RestartStrategy::OnFailure;
+        assert!(!restart.restarts_on_success());
+        assert!(restart.restarts_on_failure());
+        assert!(restart.restarts_on_abnormal());
+        restart.strategy = RestartStrategy::OnAbnormal;
+        assert!(!restart.restarts_on_success());
+        assert!(!restart.restarts_on_failure());
+        assert!(restart.restarts_on_abnormal());
+        assert_eq!(
+            RestartStrategy::from("on-abnormal"),
+            RestartStrategy::OnAbnormal
+        );
+        // The flags take precedence over the strategy:
+        restart.on_success = Some(true);
+        restart.on_failure = Some(false);
+        assert!(restart.restarts_on_success());
+        assert!(!restart.restarts_on_failure());
+        assert!(!restart.restarts_on_abnormal());
+    }
+
     #[test]
     fn test_should_correctly_deserialize_sample() {
         let expected = Service {
@@ -493,6 +1900,12 @@ mod test {
             command: "/bin/bash -c \'echo hello world\'".to_string(),
             user: Name("root".into()),
             environment: Some(Environment {
+                inherit_env_from: Some(InheritEnvFrom {
+                    service: "another.toml".into(),
+                    vars: vec!["PORT".into()],
+                }),
+                env_file: Some("/etc/myservice/env".into()),
+                env_file_format: EnvFileFormat::Shell,
                 key_val: vec![("key", "value"), ("DB_PASS", "MyPassword")]
                     .into_iter()
                     .map(|(v, k)| (v.to_string(), k.to_string()))
@@ -503,29 +1916,194 @@ mod test {
             start_after: vec!["another.toml".into(), "second.toml".into()],
             restart: Restart {
                 strategy: RestartStrategy::Never,
+                on_success: Some(false),
+                on_failure: Some(false),
                 backoff: Duration::from_millis(0),
                 attempts: 0,
+                min_uptime_for_reset: Duration::from_secs(10),
+                start_limit_interval: Duration::from_secs(10),
+                start_limit_burst: 5,
+                start_limit_action: StartLimitAction::Restart,
+                ignore_exit_codes: vec![2],
             },
             healthiness: Some(Healthness {
-                http_endpoint: Some("http://localhost:8080/healthcheck".into()),
+                http_endpoint: Some("http://%h:%p/healthcheck".into()),
                 file_path: Some("/var/myservice/up".into()),
+                post_healthcheck_command: Some("/usr/bin/register-service".into()),
+                post_healthcheck_on_change_only: true,
+            }),
+            startup_probe: Some(StartupProbe {
+                http_endpoint: None,
+                file_path: Some("/var/myservice/initialized".into()),
+                initial_delay: Duration::from_secs(5),
+                period: Duration::from_secs(2),
+                failure_threshold: 30,
             }),
             signal_rewrite: None,
             last_mtime_sec: 0,
+            description_file_content: None,
             failure: Failure {
                 successful_exit_code: vec![0, 1, 255],
                 strategy: FailureStrategy::Ignore,
             },
             termination: Termination {
                 signal: TERM,
-                wait: Duration::from_secs(10),
+                wait: Some(Duration::from_secs(10)),
                 die_if_failed: vec!["db.toml".into()],
+                sequence: vec![],
+                stop_command: Some("/usr/bin/myservice-ctl stop".into()),
+                stop_timeout_action: StopTimeoutAction::ForceKill,
+            },
+            service_type: ServiceType::Simple,
+            readiness_timeout: Duration::from_secs(30),
+            exec_wait_pid_file: Some("/run/myservice.pid".into()),
+            signal_process_group: false,
+            kill_mode: Default::default(),
+            new_mount_namespace: false,
+            tmpfs_paths: vec![],
+            read_only_paths: vec![],
+            inaccessible_paths: vec![],
+            private_tmp: false,
+            private_run: false,
+            private_network: false,
+            private_users: false,
+            new_cgroup_namespace: false,
+            ipc_namespace: Default::default(),
+            network_namespace: Default::default(),
+            pid_namespace: Default::default(),
+            protect_home: false,
+            protect_system: ProtectSystem::No,
+            capabilities_bounding_set: Some(vec!["CAP_NET_BIND_SERVICE".into()]),
+            coredump_filter: Some("0x33".into()),
+            stdin_from_service: None,
+            close_extra_fds: true,
+            exec_search_path: vec![],
+            command_search_paths: vec![],
+            exec_as_shell: false,
+            shell: Some("/bin/bash".into()),
+            argv0: Some("hello-world".into()),
+            pre_exec_command: Some("/bin/mkdir -p /tmp/hello".into()),
+            pre_exec_timeout: Duration::from_secs(30),
+            priority: 50,
+            forward_signals: vec![TerminationSignal::USR1],
+            inherit_signals: false,
+            inherit_systemd_activation: false,
+            condition_hostname: Some("web-[0-9]+".into()),
+            wait_for_port: Some(WaitForPort {
+                host: "db".into(),
+                port: 5432,
+                timeout: Duration::from_secs(30),
+            }),
+            wait_for_file: Some(WaitForFile {
+                path: "/run/db.ready".into(),
+                timeout: Duration::from_secs(60),
+            }),
+            ports: vec![8080, 8443],
+            description: Some("Says hello to the world.".into()),
+            documentation: Some(Documentation::Urls(vec![
+                "https://github.com/FedericoPonzi/Horust".into(),
+                "man:bash(1)".into(),
+            ])),
+            version: Some("1.0.0".into()),
+            description_file: Some("hello-world.md".into()),
+            depends_on_healthy: vec!["db.toml".into()],
+            mounts: Mounts {
+                bind: vec![BindMount {
+                    source: "/etc/myservice/secrets".into(),
+                    destination: "/run/secrets".into(),
+                    read_only: true,
+                }],
             },
         };
         let service = Service::from_str(get_sample_service().as_str())
             .expect("error on deserializing the manifest");
         assert_eq!(expected, service);
     }
+    #[test]
+    fn test_describe() {
+        let mut service = Service::start_after("a", vec!["b"]);
+        service.command = "sleep 1".into();
+        service.description = Some("Sleeps.".into());
+        service.documentation = Some(Documentation::Url("https://example.com".into()));
+        service.version = Some("1.2".into());
+        assert_eq!(
+            service.describe(),
+            "Service: a (version: 1.2)\nDescription: Sleeps.\nDocumentation:\n  https://example.com\nCommand: sleep 1\nStart after: b\n"
+        );
+    }
+
+    #[test]
+    fn test_describe_file() -> std::io::Result<()> {
+        let tempdir = TempDir::new("description-file")?;
+        std::fs::write(tempdir.path().join("a.md"), "# Runbook\nRestart it.")?;
+        let mut service = Service::from_command("sleep 1".into());
+        service.name = "a".into();
+        service.description_file = Some("a.md".into());
+        service.load_description_file(tempdir.path());
+        assert_eq!(
+            service.describe(),
+            "Service: a\nCommand: sleep 1\n\n# Runbook\nRestart it.\n"
+        );
+
+        // It's just skipped, if it's missing:
+        service.description_file = Some("missing.md".into());
+        service.description_file_content = None;
+        service.load_description_file(tempdir.path());
+        assert_eq!(service.description_file_content, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_runs_on_host() {
+        let mut service = Service::from_name("a");
+        assert!(service.runs_on_host("any-host"));
+        service.condition_hostname = Some("db-server-01".into());
+        assert!(service.runs_on_host("db-server-01"));
+        assert!(!service.runs_on_host("db-server-010"));
+        service.condition_hostname = Some("web-[0-9]+|proxy".into());
+        assert!(service.runs_on_host("web-12"));
+        assert!(service.runs_on_host("proxy"));
+        assert!(!service.runs_on_host("my-web-12"));
+    }
+
+    #[test]
+    fn test_command_args() {
+        let mut service = Service::from_command("echo 'a b' | wc -c".into());
+        assert_eq!(service.command_args(), vec!["echo", "a b", "|", "wc", "-c"]);
+        service.exec_as_shell = true;
+        assert_eq!(
+            service.command_args(),
+            vec!["/bin/sh", "-c", "echo 'a b' | wc -c"]
+        );
+        service.shell = Some("/bin/bash".into());
+        assert_eq!(service.command_args()[0], "/bin/bash");
+    }
+
+    #[test]
+    fn test_http_endpoint() {
+        let service = Service::builder()
+            .name("a")
+            .command("/bin/true")
+            .env("SERVICE_HOST", "web-1")
+            .ports(vec![8080, 8443])
+            .healthcheck_http("http://%h:%p/health")
+            .build()
+            .unwrap();
+        assert_eq!(
+            service.http_endpoint(),
+            Some("http://web-1:8080/health".into())
+        );
+        assert_eq!(Service::from_name("a").http_endpoint(), None);
+    }
+
+    #[test]
+    fn test_validate_added() {
+        let known = vec!["a".to_string()].into_iter().collect();
+        validate_added(&Service::start_after("b", vec!["a"]), &known).unwrap();
+        let errors = validate_added(&Service::start_after("a", vec!["c"]), &known).unwrap_err();
+        assert_eq!(errors.len(), 2);
+    }
+
     #[test]
     fn test_validate() {
         // Service does not exists:
@@ -539,4 +2117,217 @@ mod test {
         ];
         validate(services).expect("Validation failed");
     }
+
+    #[test]
+    fn test_validate_mount_paths() {
+        // tmpfs-paths require a new mount namespace:
+        let mut service = Service::from_name("a");
+        service.tmpfs_paths = vec!["/tmp".into()];
+        let errors = validate(vec![service.clone()]).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        // Which is implied by private-tmp:
+        service.private_tmp = true;
+        validate(vec![service.clone()]).expect("Validation failed");
+        service.private_tmp = false;
+        service.new_mount_namespace = true;
+        validate(vec![service.clone()]).expect("Validation failed");
+
+        // Read only paths should exist, and be absolute:
+        service.read_only_paths = vec!["/etc".into(), "etc".into(), "/non-existent".into()];
+        assert_eq!(validate(vec![service.clone()]).unwrap_err().len(), 2);
+        service.read_only_paths = vec!["/etc".into()];
+        validate(vec![service.clone()]).expect("Validation failed");
+        service.inaccessible_paths = vec!["/non-existent".into()];
+        assert_eq!(validate(vec![service.clone()]).unwrap_err().len(), 1);
+        service.inaccessible_paths = vec!["/etc/passwd".into()];
+        validate(vec![service.clone()]).expect("Validation failed");
+
+        // Bind mounts' sources should exist:
+        service.mounts.bind = vec![BindMount {
+            source: "/non-existent/source".into(),
+            destination: "/tmp".into(),
+            read_only: false,
+        }];
+        let errors = validate(vec![service.clone()]).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        service.mounts.bind[0].source = "/".into();
+        validate(vec![service]).expect("Validation failed");
+    }
+
+    #[test]
+    fn test_validate_documentation() {
+        // Documentation should be made of urls:
+        let mut service = Service::from_name("a");
+        service.documentation = Some(Documentation::Urls(vec![
+            "https://example.com".into(),
+            "not an url".into(),
+        ]));
+        assert_eq!(validate(vec![service]).unwrap_err().len(), 1);
+    }
+
+    #[test]
+    fn test_validate_depends_on_healthy() {
+        // depends-on-healthy requires some healthchecks:
+        let mut dependency = Service::from_name("db");
+        let mut service = Service::from_name("a");
+        service.depends_on_healthy = vec!["db".into()];
+        let errors = validate(vec![dependency.clone(), service.clone()]).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        dependency.healthiness = Some(Healthness {
+            http_endpoint: None,
+            file_path: Some("/tmp/db-up".into()),
+            post_healthcheck_command: None,
+            post_healthcheck_on_change_only: true,
+        });
+        validate(vec![dependency, service]).expect("Validation failed");
+    }
+
+    #[test]
+    fn test_validate_exec_search_path() {
+        // exec-search-path directories should exist:
+        let mut service = Service::from_name("a");
+        service.exec_search_path = vec!["/".into(), "/non-existent/bin".into()];
+        assert_eq!(validate(vec![service]).unwrap_err().len(), 1);
+    }
+
+    #[test]
+    fn test_validate_template() {
+        // Templates can't be run:
+        let mut service = Service::from_name("worker@.toml");
+        service.service_type = ServiceType::Template;
+        validate(vec![service]).unwrap_err();
+    }
+
+    #[test]
+    fn test_validate_priority() {
+        // Priority is at most 100:
+        let mut service = Service::from_name("a");
+        service.priority = 101;
+        validate(vec![service]).unwrap_err();
+    }
+
+    #[test]
+    fn test_validate_termination_sequence() {
+        // The termination sequence should end with a kill:
+        let mut service = Service::from_name("a");
+        let step = |signal, wait| TerminationStep {
+            signal,
+            wait: Duration::from_secs(wait),
+        };
+        service.termination.sequence = vec![
+            step(TerminationSignal::TERM, 5),
+            step(TerminationSignal::KILL, 1),
+        ];
+        validate(vec![service.clone()]).unwrap();
+        service.termination.sequence = vec![step(TerminationSignal::QUIT, 0)];
+        validate(vec![service.clone()]).unwrap();
+        service.termination.sequence = vec![step(TerminationSignal::TERM, 5)];
+        validate(vec![service]).unwrap_err();
+    }
+
+    #[test]
+    fn test_validate_forward_signals() {
+        // Only USR1 and USR2 can be forwarded:
+        let mut service = Service::from_name("a");
+        service.forward_signals = vec![TerminationSignal::USR2];
+        validate(vec![service.clone()]).unwrap();
+        service.forward_signals = vec![TerminationSignal::TERM];
+        validate(vec![service]).unwrap_err();
+    }
+
+    #[test]
+    fn test_validate_shell() {
+        // The shell should exist:
+        let mut service = Service::from_command("echo $HOME".into());
+        service.exec_as_shell = true;
+        validate(vec![service.clone()]).expect("Validation failed");
+        service.shell = Some("/non-existent/sh".into());
+        validate(vec![service]).unwrap_err();
+    }
+
+    #[test]
+    fn test_validate_argv0() {
+        // argv0 should be a plain name:
+        let mut service = Service::from_name("a");
+        service.argv0 = Some("bin/worker".into());
+        validate(vec![service.clone()]).unwrap_err();
+        service.argv0 = Some("".into());
+        validate(vec![service.clone()]).unwrap_err();
+        service.argv0 = Some("worker".into());
+        validate(vec![service]).expect("Validation failed");
+    }
+
+    #[test]
+    fn test_validate_condition_hostname() {
+        // condition-hostname should be a valid regex:
+        let mut service = Service::from_name("a");
+        service.condition_hostname = Some("web-(".into());
+        validate(vec![service]).unwrap_err();
+    }
+
+    #[test]
+    fn test_validate_http_endpoint_port() {
+        // %p in the http-endpoint needs a port:
+        let mut service = Service::from_name("a");
+        service.healthiness = Some(Healthness {
+            http_endpoint: Some("http://localhost:%p/health".into()),
+            file_path: None,
+            post_healthcheck_command: None,
+            post_healthcheck_on_change_only: true,
+        });
+        validate(vec![service.clone()]).unwrap_err();
+        service.ports = vec![8080];
+        validate(vec![service]).expect("Validation failed");
+    }
+
+    #[test]
+    fn test_validate_commands() {
+        // post-healthcheck-command, pre-exec-command and stop-command should be valid commands:
+        let mut service = Service::from_name("a");
+        service.healthiness = Some(Healthness {
+            http_endpoint: None,
+            file_path: None,
+            post_healthcheck_command: Some("echo 'unterminated".into()),
+            post_healthcheck_on_change_only: true,
+        });
+        validate(vec![service]).unwrap_err();
+        let mut service = Service::from_name("a");
+        service.pre_exec_command = Some("".into());
+        validate(vec![service]).unwrap_err();
+        let mut service = Service::from_name("a");
+        service.termination.stop_command = Some("pg_ctl 'stop".into());
+        validate(vec![service]).unwrap_err();
+    }
+
+    #[test]
+    fn test_validate_inherit_env_from() {
+        // inherit-env-from should be a dependency:
+        let mut service = Service::builder()
+            .name("b")
+            .command("/bin/true")
+            .env("A", "B")
+            .build()
+            .unwrap();
+        service.environment.as_mut().unwrap().inherit_env_from = Some(InheritEnvFrom {
+            service: "a".into(),
+            vars: vec!["PORT".into()],
+        });
+        validate(vec![Service::from_name("a"), service.clone()]).unwrap_err();
+        service.start_after = vec!["a".into()];
+        validate(vec![Service::from_name("a"), service]).expect("Validation failed");
+    }
+
+    #[test]
+    fn test_validate_stdin_from_service() {
+        // stdin-from-service: the producer should exist, and have a single consumer:
+        let mut consumer = Service::from_name("consumer");
+        consumer.stdin_from_service = Some("producer".into());
+        validate(vec![consumer.clone()]).unwrap_err();
+        let services = vec![Service::from_name("producer"), consumer.clone()];
+        validate(services.clone()).expect("Validation failed");
+        let mut second = consumer.clone();
+        second.name = "second".into();
+        let errors = validate([services, vec![second]].concat()).unwrap_err();
+        assert_eq!(errors.len(), 1);
+    }
 }
@@ -0,0 +1,485 @@
+use crate::horust::error::{ValidationError, ValidationErrorKind};
+use crate::horust::formats::{
+    validate_options, BindMount, Documentation, Environment, FailureStrategy, Healthness, KillMode,
+    Namespace, ProtectSystem, RestartStrategy, Service, ServiceName, ServiceType, StartLimitAction,
+    StartupProbe, StopTimeoutAction, TerminationSignal, TerminationStep, User, WaitForFile,
+    WaitForPort,
+};
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Builds a `Service` programmatically. Every option not set keeps its default value.
+/// ```
+/// use horust::{RestartStrategy, Service};
+/// let service = Service::builder()
+///     .name("web")
+///     .command("nginx -g 'daemon off;'")
+///     .start_after(vec!["db"])
+///     .restart_strategy(RestartStrategy::OnFailure)
+///     .build()
+///     .unwrap();
+/// assert_eq!(service.start_after, vec!["db".to_string()]);
+/// ```
+#[derive(Debug, Clone)]
+pub struct ServiceBuilder {
+    service: Service,
+}
+
+impl Default for ServiceBuilder {
+    fn default() -> Self {
+        Self {
+            service: Service::from_command(String::new()),
+        }
+    }
+}
+
+impl ServiceBuilder {
+    pub fn name(mut self, name: &str) -> Self {
+        self.service.name = name.to_string();
+        self
+    }
+
+    pub fn command(mut self, command: &str) -> Self {
+        self.service.command = command.to_string();
+        self
+    }
+
+    pub fn description(mut self, description: &str) -> Self {
+        self.service.description = Some(description.to_string());
+        self
+    }
+
+    pub fn documentation(mut self, urls: Vec<&str>) -> Self {
+        self.service.documentation = Some(Documentation::Urls(
+            urls.into_iter().map(String::from).collect(),
+        ));
+        self
+    }
+
+    pub fn version(mut self, version: &str) -> Self {
+        self.service.version = Some(version.to_string());
+        self
+    }
+
+    pub fn description_file<P: Into<PathBuf>>(mut self, description_file: P) -> Self {
+        self.service.description_file = Some(description_file.into());
+        self
+    }
+
+    pub fn user(mut self, user: User) -> Self {
+        self.service.user = user;
+        self
+    }
+
+    pub fn env(mut self, key: &str, value: &str) -> Self {
+        self.service
+            .environment
+            .get_or_insert_with(|| Environment {
+                inherit_env_from: None,
+                env_file: None,
+                env_file_format: Default::default(),
+                key_val: Default::default(),
+            })
+            .key_val
+            .insert(key.to_string(), value.to_string());
+        self
+    }
+
+    pub fn working_directory<P: Into<PathBuf>>(mut self, working_directory: P) -> Self {
+        self.service.working_directory = Some(working_directory.into());
+        self
+    }
+
+    pub fn start_delay(mut self, start_delay: Duration) -> Self {
+        self.service.start_delay = start_delay;
+        self
+    }
+
+    pub fn start_after<S: Into<ServiceName>>(mut self, start_after: Vec<S>) -> Self {
+        self.service.start_after = start_after.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn depends_on_healthy<S: Into<ServiceName>>(mut self, dependencies: Vec<S>) -> Self {
+        self.service.depends_on_healthy = dependencies.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn service_type(mut self, service_type: ServiceType) -> Self {
+        self.service.service_type = service_type;
+        self
+    }
+
+    pub fn healthcheck_http(mut self, endpoint: &str) -> Self {
+        self.healthiness().http_endpoint = Some(endpoint.to_string());
+        self
+    }
+
+    pub fn healthcheck_file<P: Into<PathBuf>>(mut self, file_path: P) -> Self {
+        self.healthiness().file_path = Some(file_path.into());
+        self
+    }
+
+    pub fn post_healthcheck_command(mut self, command: &str, on_change_only: bool) -> Self {
+        let healthiness = self.healthiness();
+        healthiness.post_healthcheck_command = Some(command.to_string());
+        healthiness.post_healthcheck_on_change_only = on_change_only;
+        self
+    }
+
+    pub fn startup_probe(mut self, startup_probe: StartupProbe) -> Self {
+        self.service.startup_probe = Some(startup_probe);
+        self
+    }
+
+    pub fn restart_strategy(mut self, strategy: RestartStrategy) -> Self {
+        self.service.restart.strategy = strategy;
+        self
+    }
+
+    pub fn restart_on_success(mut self, on_success: bool) -> Self {
+        self.service.restart.on_success = Some(on_success);
+        self
+    }
+
+    pub fn restart_on_failure(mut self, on_failure: bool) -> Self {
+        self.service.restart.on_failure = Some(on_failure);
+        self
+    }
+
+    pub fn restart_backoff(mut self, backoff: Duration) -> Self {
+        self.service.restart.backoff = backoff;
+        self
+    }
+
+    pub fn restart_attempts(mut self, attempts: u32) -> Self {
+        self.service.restart.attempts = attempts;
+        self
+    }
+
+    pub fn restart_min_uptime_for_reset(mut self, min_uptime: Duration) -> Self {
+        self.service.restart.min_uptime_for_reset = min_uptime;
+        self
+    }
+
+    pub fn restart_ignore_exit_codes(mut self, exit_codes: Vec<i32>) -> Self {
+        self.service.restart.ignore_exit_codes = exit_codes;
+        self
+    }
+
+    /// Allow at most `burst` starts within `interval`.
+    pub fn start_limit(mut self, burst: u32, interval: Duration, action: StartLimitAction) -> Self {
+        let restart = &mut self.service.restart;
+        restart.start_limit_burst = burst;
+        restart.start_limit_interval = interval;
+        restart.start_limit_action = action;
+        self
+    }
+
+    pub fn failure_strategy(mut self, strategy: FailureStrategy) -> Self {
+        self.service.failure.strategy = strategy;
+        self
+    }
+
+    pub fn successful_exit_codes(mut self, exit_codes: Vec<i32>) -> Self {
+        self.service.failure.successful_exit_code = exit_codes;
+        self
+    }
+
+    pub fn termination_signal(mut self, signal: TerminationSignal) -> Self {
+        self.service.termination.signal = signal;
+        self
+    }
+
+    /// Appends a step to the termination sequence.
+    pub fn termination_step(mut self, signal: TerminationSignal, wait: Duration) -> Self {
+        self.service
+            .termination
+            .sequence
+            .push(TerminationStep { signal, wait });
+        self
+    }
+
+    pub fn termination_wait(mut self, wait: Duration) -> Self {
+        self.service.termination.wait = Some(wait);
+        self
+    }
+
+    pub fn stop_timeout_action(mut self, action: StopTimeoutAction) -> Self {
+        self.service.termination.stop_timeout_action = action;
+        self
+    }
+
+    pub fn new_mount_namespace(mut self, new_mount_namespace: bool) -> Self {
+        self.service.new_mount_namespace = new_mount_namespace;
+        self
+    }
+
+    pub fn tmpfs_paths<P: Into<PathBuf>>(mut self, tmpfs_paths: Vec<P>) -> Self {
+        self.service.tmpfs_paths = tmpfs_paths.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn read_only_paths<P: Into<PathBuf>>(mut self, read_only_paths: Vec<P>) -> Self {
+        self.service.read_only_paths = read_only_paths.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn inaccessible_paths<P: Into<PathBuf>>(mut self, inaccessible_paths: Vec<P>) -> Self {
+        self.service.inaccessible_paths = inaccessible_paths.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn private_tmp(mut self, private_tmp: bool) -> Self {
+        self.service.private_tmp = private_tmp;
+        self
+    }
+
+    pub fn private_run(mut self, private_run: bool) -> Self {
+        self.service.private_run = private_run;
+        self
+    }
+
+    pub fn private_network(mut self, private_network: bool) -> Self {
+        self.service.private_network = private_network;
+        self
+    }
+
+    pub fn private_users(mut self, private_users: bool) -> Self {
+        self.service.private_users = private_users;
+        self
+    }
+
+    pub fn new_cgroup_namespace(mut self, new_cgroup_namespace: bool) -> Self {
+        self.service.new_cgroup_namespace = new_cgroup_namespace;
+        self
+    }
+
+    pub fn ipc_namespace(mut self, ipc_namespace: Namespace) -> Self {
+        self.service.ipc_namespace = ipc_namespace;
+        self
+    }
+
+    pub fn network_namespace(mut self, network_namespace: Namespace) -> Self {
+        self.service.network_namespace = network_namespace;
+        self
+    }
+
+    pub fn pid_namespace(mut self, pid_namespace: Namespace) -> Self {
+        self.service.pid_namespace = pid_namespace;
+        self
+    }
+
+    pub fn protect_home(mut self, protect_home: bool) -> Self {
+        self.service.protect_home = protect_home;
+        self
+    }
+
+    pub fn protect_system(mut self, protect_system: ProtectSystem) -> Self {
+        self.service.protect_system = protect_system;
+        self
+    }
+
+    pub fn capabilities_bounding_set(mut self, capabilities: Vec<String>) -> Self {
+        self.service.capabilities_bounding_set = Some(capabilities);
+        self
+    }
+
+    pub fn coredump_filter(mut self, coredump_filter: &str) -> Self {
+        self.service.coredump_filter = Some(coredump_filter.to_string());
+        self
+    }
+
+    pub fn stdin_from_service(mut self, producer: &str) -> Self {
+        self.service.stdin_from_service = Some(producer.to_string());
+        self
+    }
+
+    pub fn close_extra_fds(mut self, close_extra_fds: bool) -> Self {
+        self.service.close_extra_fds = close_extra_fds;
+        self
+    }
+
+    pub fn exec_search_path<P: Into<PathBuf>>(mut self, exec_search_path: Vec<P>) -> Self {
+        self.service.exec_search_path = exec_search_path.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn kill_mode(mut self, kill_mode: KillMode) -> Self {
+        self.service.kill_mode = kill_mode;
+        self
+    }
+
+    pub fn command_search_paths<P: Into<PathBuf>>(mut self, command_search_paths: Vec<P>) -> Self {
+        self.service.command_search_paths =
+            command_search_paths.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn exec_as_shell(mut self, exec_as_shell: bool) -> Self {
+        self.service.exec_as_shell = exec_as_shell;
+        self
+    }
+
+    pub fn shell<P: Into<PathBuf>>(mut self, shell: P) -> Self {
+        self.service.shell = Some(shell.into());
+        self
+    }
+
+    pub fn pre_exec_command(mut self, command: &str) -> Self {
+        self.service.pre_exec_command = Some(command.to_string());
+        self
+    }
+
+    pub fn pre_exec_timeout(mut self, timeout: Duration) -> Self {
+        self.service.pre_exec_timeout = timeout;
+        self
+    }
+
+    pub fn priority(mut self, priority: u8) -> Self {
+        self.service.priority = priority;
+        self
+    }
+
+    pub fn forward_signal(mut self, signal: TerminationSignal) -> Self {
+        self.service.forward_signals.push(signal);
+        self
+    }
+
+    pub fn inherit_systemd_activation(mut self, inherit_systemd_activation: bool) -> Self {
+        self.service.inherit_systemd_activation = inherit_systemd_activation;
+        self
+    }
+
+    pub fn exec_wait_pid_file<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.service.exec_wait_pid_file = Some(path.into());
+        self
+    }
+
+    pub fn inherit_signals(mut self, inherit_signals: bool) -> Self {
+        self.service.inherit_signals = inherit_signals;
+        self
+    }
+
+    pub fn condition_hostname(mut self, pattern: &str) -> Self {
+        self.service.condition_hostname = Some(pattern.to_string());
+        self
+    }
+
+    pub fn wait_for_file<P: Into<PathBuf>>(mut self, path: P, timeout: Duration) -> Self {
+        self.service.wait_for_file = Some(WaitForFile {
+            path: path.into(),
+            timeout,
+        });
+        self
+    }
+
+    pub fn wait_for_port(mut self, host: &str, port: u16, timeout: Duration) -> Self {
+        self.service.wait_for_port = Some(WaitForPort {
+            host: host.to_string(),
+            port,
+            timeout,
+        });
+        self
+    }
+
+    pub fn ports(mut self, ports: Vec<u16>) -> Self {
+        self.service.ports = ports;
+        self
+    }
+
+    pub fn argv0(mut self, argv0: &str) -> Self {
+        self.service.argv0 = Some(argv0.to_string());
+        self
+    }
+
+    pub fn bind_mount<P: Into<PathBuf>>(
+        mut self,
+        source: P,
+        destination: P,
+        read_only: bool,
+    ) -> Self {
+        self.service.mounts.bind.push(BindMount {
+            source: source.into(),
+            destination: destination.into(),
+            read_only,
+        });
+        self
+    }
+
+    fn healthiness(&mut self) -> &mut Healthness {
+        self.service.healthiness.get_or_insert(Healthness {
+            http_endpoint: None,
+            file_path: None,
+            post_healthcheck_command: None,
+            post_healthcheck_on_change_only: true,
+        })
+    }
+
+    /// Checks the options which can be validated without the other services: `name` and `command` are required.
+    /// Dependencies are checked by `validate`, once all the services are known.
+    pub fn build(self) -> Result<Service, Vec<ValidationError>> {
+        let mut errors = vec![];
+        if self.service.name.is_empty() {
+            errors.push(ValidationError::new(
+                "Service's name is missing.",
+                ValidationErrorKind::MissingValue,
+            ));
+        }
+        if self.service.command.trim().is_empty() {
+            let err = format!("Service '{}' has no command.", self.service.name);
+            errors.push(ValidationError::new(
+                err.as_str(),
+                ValidationErrorKind::MissingValue,
+            ));
+        }
+        errors.extend(validate_options(&self.service));
+        if errors.is_empty() {
+            Ok(self.service)
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::horust::formats::{Healthness, RestartStrategy, Service};
+    use std::time::Duration;
+
+    #[test]
+    fn test_builder() {
+        let service = Service::builder()
+            .name("web")
+            .command("nginx -g 'daemon off;'")
+            .start_after(vec!["db"])
+            .healthcheck_http("http://localhost/health")
+            .restart_strategy(RestartStrategy::OnFailure)
+            .termination_wait(Duration::from_secs(3))
+            .env("KEY", "value")
+            .build()
+            .unwrap();
+        assert_eq!(service.name, "web");
+        assert_eq!(service.command, "nginx -g 'daemon off;'");
+        assert_eq!(service.start_after, vec!["db".to_string()]);
+        assert_eq!(
+            service.healthiness,
+            Some(Healthness {
+                http_endpoint: Some("http://localhost/health".into()),
+                file_path: None,
+                post_healthcheck_command: None,
+                post_healthcheck_on_change_only: true,
+            })
+        );
+        assert_eq!(service.restart.strategy, RestartStrategy::OnFailure);
+        assert_eq!(service.termination.wait, Some(Duration::from_secs(3)));
+        assert_eq!(
+            service.environment.unwrap().key_val.get("KEY"),
+            Some(&"value".to_string())
+        );
+
+        let errors = Service::builder().build().unwrap_err();
+        assert_eq!(errors.len(), 2);
+        Service::builder().name("a").build().unwrap_err();
+    }
+}
@@ -0,0 +1,535 @@
+use crate::horust::formats::{
+    Documentation, Environment, Failure, Healthness, KillMode, Mounts, Namespace, ProtectSystem,
+    Restart, Service, ServiceName, ServiceType, StartupProbe, Termination, TerminationSignal, User,
+    WaitForFile, WaitForPort,
+};
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// A field which differs between two configurations of a service, with its old and new value.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum ServiceFieldChange {
+    Name(ServiceName, ServiceName),
+    Command(String, String),
+    Description(Option<String>, Option<String>),
+    Documentation(Option<Documentation>, Option<Documentation>),
+    Version(Option<String>, Option<String>),
+    DescriptionFile(Option<PathBuf>, Option<PathBuf>),
+    User(User, User),
+    Environment(Option<Environment>, Option<Environment>),
+    WorkingDirectory(Option<PathBuf>, Option<PathBuf>),
+    StartDelay(Duration, Duration),
+    StartAfter(Vec<ServiceName>, Vec<ServiceName>),
+    DependsOnHealthy(Vec<ServiceName>, Vec<ServiceName>),
+    ServiceType(ServiceType, ServiceType),
+    ReadinessTimeout(Duration, Duration),
+    ExecWaitPidFile(Option<PathBuf>, Option<PathBuf>),
+    SignalProcessGroup(bool, bool),
+    KillMode(KillMode, KillMode),
+    NewMountNamespace(bool, bool),
+    TmpfsPaths(Vec<PathBuf>, Vec<PathBuf>),
+    ReadOnlyPaths(Vec<PathBuf>, Vec<PathBuf>),
+    InaccessiblePaths(Vec<PathBuf>, Vec<PathBuf>),
+    PrivateTmp(bool, bool),
+    PrivateRun(bool, bool),
+    PrivateNetwork(bool, bool),
+    PrivateUsers(bool, bool),
+    NewCgroupNamespace(bool, bool),
+    IpcNamespace(Namespace, Namespace),
+    NetworkNamespace(Namespace, Namespace),
+    PidNamespace(Namespace, Namespace),
+    ProtectHome(bool, bool),
+    ProtectSystem(ProtectSystem, ProtectSystem),
+    CapabilitiesBoundingSet(Option<Vec<String>>, Option<Vec<String>>),
+    CoredumpFilter(Option<String>, Option<String>),
+    StdinFromService(Option<ServiceName>, Option<ServiceName>),
+    CloseExtraFds(bool, bool),
+    ExecSearchPath(Vec<PathBuf>, Vec<PathBuf>),
+    CommandSearchPaths(Vec<PathBuf>, Vec<PathBuf>),
+    ExecAsShell(bool, bool),
+    Shell(Option<PathBuf>, Option<PathBuf>),
+    Argv0(Option<String>, Option<String>),
+    PreExecCommand(Option<String>, Option<String>),
+    PreExecTimeout(Duration, Duration),
+    Priority(u8, u8),
+    ForwardSignals(Vec<TerminationSignal>, Vec<TerminationSignal>),
+    InheritSignals(bool, bool),
+    InheritSystemdActivation(bool, bool),
+    ConditionHostname(Option<String>, Option<String>),
+    WaitForPort(Option<WaitForPort>, Option<WaitForPort>),
+    WaitForFile(Option<WaitForFile>, Option<WaitForFile>),
+    Ports(Vec<u16>, Vec<u16>),
+    Restart(Restart, Restart),
+    Healthiness(Option<Healthness>, Option<Healthness>),
+    StartupProbe(Option<StartupProbe>, Option<StartupProbe>),
+    SignalRewrite(Option<String>, Option<String>),
+    Failure(Failure, Failure),
+    Termination(Termination, Termination),
+    Mounts(Mounts, Mounts),
+}
+
+/// What's needed for applying a change to a service.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+pub enum ChangeImpact {
+    /// Only informative fields have changed.
+    None,
+    /// The change can be applied to the running service, e.g. its healthchecks.
+    Reconfigure,
+    /// The change affects how the process is spawned, so the service has to be restarted.
+    Restart,
+}
+
+impl ServiceFieldChange {
+    pub fn impact(&self) -> ChangeImpact {
+        use ServiceFieldChange::*;
+        match self {
+            Description(..) | Documentation(..) | Version(..) | DescriptionFile(..) => {
+                ChangeImpact::None
+            }
+            StartDelay(..)
+            | StartAfter(..)
+            | DependsOnHealthy(..)
+            | ReadinessTimeout(..)
+            | ExecWaitPidFile(..)
+            | SignalProcessGroup(..)
+            | KillMode(..)
+            | Priority(..)
+            | ForwardSignals(..)
+            | Restart(..)
+            | Healthiness(..)
+            | StartupProbe(..)
+            | SignalRewrite(..)
+            | Failure(..)
+            | Ports(..)
+            | Termination(..) => ChangeImpact::Reconfigure,
+            Name(..)
+            | Command(..)
+            | User(..)
+            | Environment(..)
+            | WorkingDirectory(..)
+            | ServiceType(..)
+            | NewMountNamespace(..)
+            | TmpfsPaths(..)
+            | ReadOnlyPaths(..)
+            | InaccessiblePaths(..)
+            | PrivateTmp(..)
+            | PrivateRun(..)
+            | PrivateNetwork(..)
+            | PrivateUsers(..)
+            | NewCgroupNamespace(..)
+            | IpcNamespace(..)
+            | NetworkNamespace(..)
+            | PidNamespace(..)
+            | ProtectHome(..)
+            | ProtectSystem(..)
+            | CapabilitiesBoundingSet(..)
+            | CoredumpFilter(..)
+            | StdinFromService(..)
+            | CloseExtraFds(..)
+            | ExecSearchPath(..)
+            | CommandSearchPaths(..)
+            | ExecAsShell(..)
+            | Shell(..)
+            | Argv0(..)
+            | PreExecCommand(..)
+            | PreExecTimeout(..)
+            | InheritSignals(..)
+            | InheritSystemdActivation(..)
+            | ConditionHostname(..)
+            | WaitForPort(..)
+            | WaitForFile(..)
+            | Mounts(..) => ChangeImpact::Restart,
+        }
+    }
+}
+
+/// The strongest impact among `changes`.
+pub fn changes_impact(changes: &[ServiceFieldChange]) -> ChangeImpact {
+    changes
+        .iter()
+        .map(ServiceFieldChange::impact)
+        .max()
+        .unwrap_or(ChangeImpact::None)
+}
+
+impl Service {
+    /// Lists the fields which differ between `old` and `new`.
+    pub fn diff(old: &Service, new: &Service) -> Vec<ServiceFieldChange> {
+        // Destructured, so that a new field won't compile until it's handled here:
+        let Service {
+            name,
+            command,
+            description,
+            documentation,
+            version,
+            description_file,
+            user,
+            environment,
+            working_directory,
+            start_delay,
+            start_after,
+            depends_on_healthy,
+            service_type,
+            readiness_timeout,
+            exec_wait_pid_file,
+            signal_process_group,
+            kill_mode,
+            new_mount_namespace,
+            tmpfs_paths,
+            read_only_paths,
+            inaccessible_paths,
+            private_tmp,
+            private_run,
+            private_network,
+            private_users,
+            new_cgroup_namespace,
+            ipc_namespace,
+            network_namespace,
+            pid_namespace,
+            protect_home,
+            protect_system,
+            capabilities_bounding_set,
+            coredump_filter,
+            stdin_from_service,
+            close_extra_fds,
+            exec_search_path,
+            command_search_paths,
+            exec_as_shell,
+            shell,
+            argv0,
+            pre_exec_command,
+            pre_exec_timeout,
+            priority,
+            forward_signals,
+            inherit_signals,
+            inherit_systemd_activation,
+            condition_hostname,
+            wait_for_port,
+            wait_for_file,
+            ports,
+            restart,
+            healthiness,
+            startup_probe,
+            signal_rewrite,
+            last_mtime_sec: _,
+            description_file_content: _,
+            failure,
+            termination,
+            mounts,
+        } = old;
+        let mut changes = vec![];
+        macro_rules! compare {
+            ($($field:ident => $variant:ident),* $(,)?) => {
+                $(
+                    if *$field != new.$field {
+                        changes.push(ServiceFieldChange::$variant(
+                            $field.clone(),
+                            new.$field.clone(),
+                        ));
+                    }
+                )*
+            };
+        }
+        compare!(
+            name => Name,
+            command => Command,
+            description => Description,
+            documentation => Documentation,
+            version => Version,
+            description_file => DescriptionFile,
+            user => User,
+            environment => Environment,
+            working_directory => WorkingDirectory,
+            start_delay => StartDelay,
+            start_after => StartAfter,
+            depends_on_healthy => DependsOnHealthy,
+            service_type => ServiceType,
+            readiness_timeout => ReadinessTimeout,
+            exec_wait_pid_file => ExecWaitPidFile,
+            signal_process_group => SignalProcessGroup,
+            kill_mode => KillMode,
+            new_mount_namespace => NewMountNamespace,
+            tmpfs_paths => TmpfsPaths,
+            read_only_paths => ReadOnlyPaths,
+            inaccessible_paths => InaccessiblePaths,
+            private_tmp => PrivateTmp,
+            private_run => PrivateRun,
+            private_network => PrivateNetwork,
+            private_users => PrivateUsers,
+            new_cgroup_namespace => NewCgroupNamespace,
+            ipc_namespace => IpcNamespace,
+            network_namespace => NetworkNamespace,
+            pid_namespace => PidNamespace,
+            protect_home => ProtectHome,
+            protect_system => ProtectSystem,
+            capabilities_bounding_set => CapabilitiesBoundingSet,
+            coredump_filter => CoredumpFilter,
+            stdin_from_service => StdinFromService,
+            close_extra_fds => CloseExtraFds,
+            exec_search_path => ExecSearchPath,
+            command_search_paths => CommandSearchPaths,
+            exec_as_shell => ExecAsShell,
+            shell => Shell,
+            argv0 => Argv0,
+            pre_exec_command => PreExecCommand,
+            pre_exec_timeout => PreExecTimeout,
+            priority => Priority,
+            forward_signals => ForwardSignals,
+            inherit_signals => InheritSignals,
+            inherit_systemd_activation => InheritSystemdActivation,
+            condition_hostname => ConditionHostname,
+            wait_for_port => WaitForPort,
+            wait_for_file => WaitForFile,
+            ports => Ports,
+            restart => Restart,
+            healthiness => Healthiness,
+            startup_probe => StartupProbe,
+            signal_rewrite => SignalRewrite,
+            failure => Failure,
+            termination => Termination,
+            mounts => Mounts,
+        );
+        changes
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::horust::formats::{
+        changes_impact, BindMount, ChangeImpact, Documentation, Environment, FailureStrategy,
+        Healthness, KillMode, Namespace, ProtectSystem, RestartStrategy, Service, ServiceType,
+        TerminationSignal, User, WaitForFile, WaitForPort,
+    };
+    use std::time::Duration;
+
+    /// Changes a single field, and the expected impact of the change.
+    type Update = (fn(&mut Service), ChangeImpact);
+
+    #[test]
+    fn test_diff() {
+        let old = Service::from_name("a");
+        assert!(Service::diff(&old, &old.clone()).is_empty());
+        let updates: Vec<Update> = vec![
+            (|s| s.name = "b".into(), ChangeImpact::Restart),
+            (|s| s.command = "sleep 1".into(), ChangeImpact::Restart),
+            (|s| s.description = Some("A".into()), ChangeImpact::None),
+            (
+                |s| s.documentation = Some(Documentation::Url("https://a".into())),
+                ChangeImpact::None,
+            ),
+            (|s| s.version = Some("1".into()), ChangeImpact::None),
+            (
+                |s| s.description_file = Some("README.md".into()),
+                ChangeImpact::None,
+            ),
+            (|s| s.user = User::Uid(12345), ChangeImpact::Restart),
+            (
+                |s| {
+                    s.environment = Some(Environment {
+                        inherit_env_from: None,
+                        env_file: None,
+                        env_file_format: Default::default(),
+                        key_val: Default::default(),
+                    })
+                },
+                ChangeImpact::Restart,
+            ),
+            (
+                |s| s.working_directory = Some("/tmp".into()),
+                ChangeImpact::Restart,
+            ),
+            (
+                |s| s.start_delay = Duration::from_secs(1),
+                ChangeImpact::Reconfigure,
+            ),
+            (
+                |s| s.start_after = vec!["b".into()],
+                ChangeImpact::Reconfigure,
+            ),
+            (
+                |s| s.depends_on_healthy = vec!["b".into()],
+                ChangeImpact::Reconfigure,
+            ),
+            (
+                |s| s.service_type = ServiceType::Notify,
+                ChangeImpact::Restart,
+            ),
+            (
+                |s| s.readiness_timeout = Duration::from_secs(1),
+                ChangeImpact::Reconfigure,
+            ),
+            (|s| s.signal_process_group = true, ChangeImpact::Reconfigure),
+            (|s| s.kill_mode = KillMode::None, ChangeImpact::Reconfigure),
+            (|s| s.new_mount_namespace = true, ChangeImpact::Restart),
+            (|s| s.private_tmp = true, ChangeImpact::Restart),
+            (|s| s.private_run = true, ChangeImpact::Restart),
+            (|s| s.private_network = true, ChangeImpact::Restart),
+            (|s| s.private_users = true, ChangeImpact::Restart),
+            (|s| s.new_cgroup_namespace = true, ChangeImpact::Restart),
+            (
+                |s| s.ipc_namespace = Namespace::Private,
+                ChangeImpact::Restart,
+            ),
+            (
+                |s| s.network_namespace = Namespace::Service("a".into()),
+                ChangeImpact::Restart,
+            ),
+            (
+                |s| s.pid_namespace = Namespace::Private,
+                ChangeImpact::Restart,
+            ),
+            (|s| s.protect_home = true, ChangeImpact::Restart),
+            (
+                |s| s.protect_system = ProtectSystem::Strict,
+                ChangeImpact::Restart,
+            ),
+            (
+                |s| s.capabilities_bounding_set = Some(vec![]),
+                ChangeImpact::Restart,
+            ),
+            (
+                |s| s.coredump_filter = Some("0x33".into()),
+                ChangeImpact::Restart,
+            ),
+            (
+                |s| s.tmpfs_paths = vec!["/tmp".into()],
+                ChangeImpact::Restart,
+            ),
+            (
+                |s| s.read_only_paths = vec!["/etc".into()],
+                ChangeImpact::Restart,
+            ),
+            (
+                |s| s.inaccessible_paths = vec!["/etc".into()],
+                ChangeImpact::Restart,
+            ),
+            (
+                |s| s.stdin_from_service = Some("b".into()),
+                ChangeImpact::Restart,
+            ),
+            (|s| s.close_extra_fds = false, ChangeImpact::Restart),
+            (
+                |s| s.exec_search_path = vec!["/bin".into()],
+                ChangeImpact::Restart,
+            ),
+            (
+                |s| s.command_search_paths = vec!["/opt/b/bin".into()],
+                ChangeImpact::Restart,
+            ),
+            (|s| s.exec_as_shell = true, ChangeImpact::Restart),
+            (
+                |s| s.shell = Some("/bin/bash".into()),
+                ChangeImpact::Restart,
+            ),
+            (|s| s.argv0 = Some("b".into()), ChangeImpact::Restart),
+            (
+                |s| s.pre_exec_command = Some("/bin/true".into()),
+                ChangeImpact::Restart,
+            ),
+            (
+                |s| s.pre_exec_timeout = Duration::from_secs(1),
+                ChangeImpact::Restart,
+            ),
+            (|s| s.priority = 90, ChangeImpact::Reconfigure),
+            (
+                |s| s.forward_signals = vec![TerminationSignal::USR1],
+                ChangeImpact::Reconfigure,
+            ),
+            (|s| s.inherit_signals = true, ChangeImpact::Restart),
+            (
+                |s| s.exec_wait_pid_file = Some("/run/inner.pid".into()),
+                ChangeImpact::Reconfigure,
+            ),
+            (
+                |s| s.inherit_systemd_activation = true,
+                ChangeImpact::Restart,
+            ),
+            (
+                |s| {
+                    s.wait_for_port = Some(WaitForPort {
+                        host: "db".into(),
+                        port: 5432,
+                        timeout: Duration::from_secs(1),
+                    })
+                },
+                ChangeImpact::Restart,
+            ),
+            (
+                |s| {
+                    s.wait_for_file = Some(WaitForFile {
+                        path: "/run/db.ready".into(),
+                        timeout: Duration::from_secs(1),
+                    })
+                },
+                ChangeImpact::Restart,
+            ),
+            (
+                |s| s.condition_hostname = Some("db".into()),
+                ChangeImpact::Restart,
+            ),
+            (|s| s.ports = vec![8080], ChangeImpact::Reconfigure),
+            (
+                |s| s.restart.strategy = RestartStrategy::Always,
+                ChangeImpact::Reconfigure,
+            ),
+            (
+                |s| {
+                    s.healthiness = Some(Healthness {
+                        http_endpoint: None,
+                        file_path: Some("/tmp/up".into()),
+                        post_healthcheck_command: None,
+                        post_healthcheck_on_change_only: true,
+                    })
+                },
+                ChangeImpact::Reconfigure,
+            ),
+            (
+                |s| s.startup_probe = Some(Default::default()),
+                ChangeImpact::Reconfigure,
+            ),
+            (
+                |s| s.signal_rewrite = Some("TERM".into()),
+                ChangeImpact::Reconfigure,
+            ),
+            (
+                |s| s.failure.strategy = FailureStrategy::Shutdown,
+                ChangeImpact::Reconfigure,
+            ),
+            (
+                |s| s.termination.signal = TerminationSignal::INT,
+                ChangeImpact::Reconfigure,
+            ),
+            (
+                |s| {
+                    s.mounts.bind = vec![BindMount {
+                        source: "/a".into(),
+                        destination: "/b".into(),
+                        read_only: false,
+                    }]
+                },
+                ChangeImpact::Restart,
+            ),
+        ];
+        updates.into_iter().for_each(|(update, impact)| {
+            let mut new = old.clone();
+            update(&mut new);
+            let changes = Service::diff(&old, &new);
+            assert_eq!(changes.len(), 1, "{:?}", changes);
+            assert_eq!(changes[0].impact(), impact, "{:?}", changes);
+        });
+
+        // The strongest impact wins:
+        let mut new = old.clone();
+        new.description = Some("A".into());
+        assert_eq!(
+            changes_impact(&Service::diff(&old, &new)),
+            ChangeImpact::None
+        );
+        new.healthiness = None;
+        new.command = "sleep 1".into();
+        assert_eq!(
+            changes_impact(&Service::diff(&old, &new)),
+            ChangeImpact::Restart
+        );
+    }
+}
@@ -1,6 +1,7 @@
-use crate::horust::formats::{Service, ServiceName, ServiceStatus};
+use crate::horust::formats::{ExitStatus, Service, ServiceName, ServiceStatus, StopTimeoutAction};
 use nix::unistd::Pid;
-use std::time::Instant;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct ServiceHandler {
@@ -10,6 +11,20 @@ pub struct ServiceHandler {
     pub(crate) restart_attempts: u32,
     /// Instant representing at which time we received a shutdown request. Will be used for comparing Service.termination.wait
     pub(crate) shutting_down_start: Option<Instant>,
+    /// True once the healthchecks have passed at least once.
+    pub(crate) has_been_healthy: bool,
+    /// How many steps of the termination sequence have been started.
+    pub(crate) termination_step: usize,
+    /// When the last `start-limit-burst` starts have happened, oldest first.
+    pub(crate) start_times: VecDeque<Instant>,
+    /// How the last run has terminated, if it has since it was started.
+    pub(crate) last_exit_status: Option<ExitStatus>,
+    /// When its process has been spawned the last time.
+    pub(crate) last_spawn: Option<Instant>,
+    /// True once the pid read from `exec-wait-pid-file` is tracked, instead of the wrapper's.
+    pub(crate) is_tracking_pid_file: bool,
+    /// True once the termination wait is over, and the `stop-timeout-action` has been applied.
+    pub(crate) has_stop_timed_out: bool,
 }
 
 impl From<Service> for ServiceHandler {
@@ -20,6 +35,13 @@ impl From<Service> for ServiceHandler {
             pid: None,
             shutting_down_start: None,
             restart_attempts: 0,
+            has_been_healthy: false,
+            termination_step: 0,
+            start_times: VecDeque::new(),
+            last_exit_status: None,
+            last_spawn: None,
+            is_tracking_pid_file: false,
+            has_stop_timed_out: false,
         }
     }
 }
@@ -35,6 +57,10 @@ impl ServiceHandler {
         self.service.start_after.as_ref()
     }
 
+    pub fn depends_on_healthy(&self) -> &Vec<String> {
+        self.service.depends_on_healthy.as_ref()
+    }
+
     pub fn service(&self) -> &Service {
         &self.service
     }
@@ -50,6 +76,39 @@ impl ServiceHandler {
     pub fn set_pid(&mut self, pid: Pid) {
         self.status = ServiceStatus::Starting;
         self.pid = Some(pid);
+        self.last_exit_status = None;
+        self.last_spawn = Some(Instant::now());
+    }
+
+    /// With `exec-wait-pid-file`, once the wrapper has exited successfully: the pid read from the
+    /// file, which is tracked from now on. None if it can't be read.
+    pub(crate) fn take_pid_from_file(&mut self, exit_status: ExitStatus) -> Option<Pid> {
+        let path = self.service.exec_wait_pid_file.as_ref()?;
+        if self.is_tracking_pid_file || exit_status != ExitStatus::Exited(0) {
+            return None;
+        }
+        let pid = std::fs::read_to_string(path)
+            .map_err(|error| error.to_string())
+            .and_then(|content| content.trim().parse::<i32>().map_err(|e| e.to_string()))
+            .and_then(|pid| match pid {
+                pid if pid > 0 => Ok(Pid::from_raw(pid)),
+                _ => Err(format!("invalid pid: {}", pid)),
+            });
+        match pid {
+            Ok(pid) => {
+                self.is_tracking_pid_file = true;
+                Some(pid)
+            }
+            Err(error) => {
+                error!(
+                    "Service: {}, failed reading the pid from {}: {}",
+                    self.name(),
+                    path.display(),
+                    error
+                );
+                None
+            }
+        }
     }
 
     pub fn set_status(&mut self, status: ServiceStatus) {
@@ -75,6 +134,51 @@ impl ServiceHandler {
         self.restart_attempts > self.service.restart.attempts
     }
 
+    /// True if the attempts are counted by uptime (`min-uptime-for-reset`), instead of being
+    /// reset as soon as the service has started.
+    pub fn has_min_uptime(&self) -> bool {
+        self.service.restart.min_uptime_for_reset > Duration::from_secs(0)
+    }
+
+    /// True if the process has exited before running for `min-uptime-for-reset`.
+    pub fn is_fast_exit(&self) -> bool {
+        self.last_spawn
+            .is_some_and(|spawn| spawn.elapsed() < self.service.restart.min_uptime_for_reset)
+    }
+
+    /// Keeps track of a start, for the start limit.
+    pub fn started_at(&mut self, instant: Instant) {
+        let restart = &self.service.restart;
+        if !restart.has_start_limit() {
+            return;
+        }
+        self.start_times.push_back(instant);
+        while self.start_times.len() > restart.start_limit_burst as usize {
+            self.start_times.pop_front();
+        }
+    }
+
+    /// True if the service has been started `start-limit-burst` times within `start-limit-interval`:
+    /// it shouldn't be restarted right now.
+    pub fn is_start_limit_hit(&self) -> bool {
+        let restart = &self.service.restart;
+        restart.has_start_limit()
+            && self.start_times.len() >= restart.start_limit_burst as usize
+            && self
+                .start_times
+                .front()
+                .map(|first| first.elapsed() < restart.start_limit_interval)
+                .unwrap_or(false)
+    }
+
+    /// True once `start-limit-interval` has passed since the last start.
+    pub fn is_start_limit_over(&self) -> bool {
+        self.start_times
+            .back()
+            .map(|last| last.elapsed() >= self.service.restart.start_limit_interval)
+            .unwrap_or(true)
+    }
+
     pub fn is_finished_failed(&self) -> bool {
         self.status == ServiceStatus::FinishedFailed
     }
@@ -99,6 +203,29 @@ impl ServiceHandler {
         ServiceStatus::Finished == self.status
     }
 
+    /// Finished, and its last exit code (if any) is one of the `successful-exit-code`: a service
+    /// finished after one of the restart's `ignore-exit-codes`, or killed by a signal, is not.
+    pub fn is_finished_successfully(&self) -> bool {
+        self.is_finished()
+            && self
+                .last_exit_status
+                .is_none_or(|exit_status| match exit_status {
+                    ExitStatus::Exited(exit_code) => self
+                        .service
+                        .failure
+                        .successful_exit_code
+                        .contains(&exit_code),
+                    ExitStatus::Signaled(..) => false,
+                })
+    }
+
+    /// With `stop-timeout-action = "warn"`, once the termination wait is over: Horust just waits
+    /// for it to exit.
+    pub(crate) fn is_stop_timeout_ignored(&self) -> bool {
+        self.has_stop_timed_out
+            && self.service.termination.stop_timeout_action == StopTimeoutAction::Warn
+    }
+
     pub fn shutting_down_started(&mut self) {
         self.shutting_down_start = Some(Instant::now());
         self.status = ServiceStatus::InKilling;
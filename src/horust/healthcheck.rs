@@ -1,17 +1,48 @@
 use crate::horust::bus::BusConnector;
-use crate::horust::formats::{Event, Service, ServiceHandler, ServiceName, ServiceStatus};
+use crate::horust::error::Result;
+use crate::horust::formats::{
+    notify_sockets_dir, Event, Service, ServiceHandler, ServiceName, ServiceStatus, ServiceType,
+    StartupProbe,
+};
+use crate::horust::runtime::service_log_target;
 #[cfg(feature = "http-healthcheck")]
 use reqwest::blocking::Client;
-use std::collections::HashMap;
-use std::time::Duration;
+use std::collections::{HashMap, HashSet};
+use std::os::unix::net::UnixDatagram;
+use std::path::PathBuf;
+use std::process::Command;
+use std::time::{Duration, Instant};
 
 // TODO:
 // * Tunable healthchecks in horust's config
 // * If there are no checks to run, just exit the thread. or go sleep until an "service created" event is received.
-pub fn spawn(bus: BusConnector, services: Vec<Service>) {
+/// The notify sockets are created before returning, so they are ready before any service is started.
+pub fn spawn(bus: BusConnector, services: Vec<Service>) -> Result<()> {
+    let repo = Repo::new(bus, services)?;
     std::thread::spawn(move || {
-        run(bus, services);
+        run(repo);
     });
+    Ok(())
+}
+
+/// The events needed by the healthchecks.
+pub(crate) fn filter(ev: &Event) -> bool {
+    matches!(
+        ev,
+        Event::AddService(_)
+            | Event::StatusChanged(_, ServiceStatus::Starting)
+            | Event::StatusChanged(_, ServiceStatus::Running)
+    )
+}
+
+/// Removes the notify sockets.
+pub fn cleanup() {
+    let dir = notify_sockets_dir();
+    if dir.exists() {
+        if let Err(error) = std::fs::remove_dir_all(&dir) {
+            error!("Failed removing {}: {}", dir.display(), error);
+        }
+    }
 }
 
 struct Repo {
@@ -19,24 +50,76 @@ struct Repo {
     services: HashMap<ServiceName, Service>,
     starting: HashMap<ServiceName, Service>,
     running: HashMap<ServiceName, Service>,
+    /// Sockets used by `notify` services for sending their readiness.
+    notify_sockets: HashMap<ServiceName, UnixDatagram>,
+    /// Starting `notify` services which haven't sent `READY=1` yet, with their starting time.
+    not_ready: HashMap<ServiceName, Instant>,
+    /// Services which have passed their last checks, used by the post healthcheck commands.
+    healthy: HashSet<ServiceName>,
+    /// Starting services which haven't passed their startup probe yet.
+    probing: HashMap<ServiceName, Probing>,
+}
+
+/// The progress of a service's startup probe.
+struct Probing {
+    starting_time: Instant,
+    last_check: Option<Instant>,
+    failures: u32,
+}
+
+impl Probing {
+    fn new() -> Self {
+        Probing {
+            starting_time: Instant::now(),
+            last_check: None,
+            failures: 0,
+        }
+    }
+
+    fn is_due(&self, probe: &StartupProbe) -> bool {
+        self.starting_time.elapsed() >= probe.initial_delay
+            && self
+                .last_check
+                .is_none_or(|last_check| last_check.elapsed() >= probe.period)
+    }
 }
 
 impl Repo {
     fn ingest(&mut self) {
         self.bus.try_get_events().into_iter().for_each(|ev| {
-            if let Event::StatusChanged(service_name, status) = ev {
+            if let Event::AddService(service) = ev {
+                self.add_service(*service);
+            } else if let Event::StatusChanged(service_name, status) = ev {
                 let svc = self.services.get(&service_name).unwrap();
                 if status == ServiceStatus::Starting {
+                    self.running.remove(&service_name);
+                    self.healthy.remove(&service_name);
+                    if let Some(socket) = self.notify_sockets.get(&service_name) {
+                        // Discard leftovers from a previous run:
+                        is_ready_notified(socket);
+                        self.not_ready.insert(service_name.clone(), Instant::now());
+                    }
+                    if svc.startup_probe.is_some() {
+                        self.probing.insert(service_name.clone(), Probing::new());
+                    }
                     self.starting.insert(svc.name.clone(), svc.clone());
                 } else if status == ServiceStatus::Running {
-                    let svc = self.starting.remove(&service_name);
-                    self.running.insert(service_name, svc.unwrap());
+                    // The running event might be received more than once:
+                    if let Some(svc) = self.starting.remove(&service_name) {
+                        self.running.insert(service_name, svc);
+                    }
                 }
             }
         });
     }
-    fn new(bus: BusConnector, services: Vec<Service>) -> Self {
-        Self {
+
+    fn new(bus: BusConnector, services: Vec<Service>) -> Result<Self> {
+        let notify_sockets = services
+            .iter()
+            .filter(|service| service.service_type == ServiceType::Notify)
+            .map(|service| Ok((service.name.clone(), create_notify_socket(service)?)))
+            .collect::<Result<_>>()?;
+        Ok(Self {
             bus,
             services: services
                 .into_iter()
@@ -44,11 +127,157 @@ impl Repo {
                 .collect(),
             starting: Default::default(),
             running: Default::default(),
+            notify_sockets,
+            not_ready: Default::default(),
+            healthy: Default::default(),
+            probing: Default::default(),
+        })
+    }
+
+    fn add_service(&mut self, service: Service) {
+        if service.service_type == ServiceType::Notify {
+            match create_notify_socket(&service) {
+                Ok(socket) => {
+                    self.notify_sockets.insert(service.name.clone(), socket);
+                }
+                Err(error) => error!(
+                    target: service_log_target(&service.name).as_str(),
+                    "Failed creating the notify socket for {}: {}",
+                    service.name, error
+                ),
+            }
         }
+        self.services.insert(service.name.clone(), service);
     }
+
     fn send_ev(&mut self, ev: Event) {
         self.bus.send_event(ev)
     }
+
+    /// Checks the notify sockets of the services which are not ready yet.
+    /// Returns a `ToBeKilled` event for every service which didn't notify its readiness in time.
+    fn check_readiness(&mut self) -> Vec<Event> {
+        let mut evs = vec![];
+        let services = &self.services;
+        let notify_sockets = &self.notify_sockets;
+        self.not_ready.retain(|service_name, starting_time| {
+            if is_ready_notified(notify_sockets.get(service_name).unwrap()) {
+                debug!(
+                    target: service_log_target(service_name).as_str(),
+                    "Service {} has notified its readiness.",
+                    service_name
+                );
+                return false;
+            }
+            let timeout = services.get(service_name).unwrap().readiness_timeout;
+            if timeout != Duration::from_secs(0) && starting_time.elapsed() > timeout {
+                error!(
+                    target: service_log_target(service_name).as_str(),
+                    "Service {} didn't notify its readiness in time, going to kill it.",
+                    service_name
+                );
+                evs.push(Event::new_status_changed(
+                    service_name,
+                    ServiceStatus::ToBeKilled,
+                ));
+                return false;
+            }
+            true
+        });
+        for ev in &evs {
+            if let Event::StatusChanged(service_name, _) = ev {
+                self.starting.remove(service_name);
+            }
+        }
+        evs
+    }
+
+    /// Runs the startup probes which are due, of the services which are ready. A service which has
+    /// passed its probe goes on with its healthiness checks.
+    /// Returns a `ToBeKilled` event for every service which has reached its probe's failure threshold.
+    fn check_startup_probes(&mut self) -> Vec<Event> {
+        let mut evs = vec![];
+        let services = &self.services;
+        let not_ready = &self.not_ready;
+        self.probing.retain(|service_name, probing| {
+            let service = services.get(service_name).unwrap();
+            let probe = match service.startup_probe.as_ref() {
+                Some(probe) => probe,
+                None => return false,
+            };
+            if not_ready.contains_key(service_name) || !probing.is_due(probe) {
+                return true;
+            }
+            probing.last_check = Some(Instant::now());
+            if startup_probe_checks(service, probe) {
+                debug!(
+                    target: service_log_target(service_name).as_str(),
+                    "Service {} has passed its startup probe.",
+                    service_name
+                );
+                return false;
+            }
+            probing.failures += 1;
+            if probe.failure_threshold != 0 && probing.failures >= probe.failure_threshold {
+                error!(
+                    target: service_log_target(service_name).as_str(),
+                    "Service {} has failed its startup probe {} times, going to kill it.",
+                    service_name, probing.failures
+                );
+                evs.push(Event::new_status_changed(
+                    service_name,
+                    ServiceStatus::ToBeKilled,
+                ));
+                return false;
+            }
+            true
+        });
+        for ev in &evs {
+            if let Event::StatusChanged(service_name, _) = ev {
+                self.starting.remove(service_name);
+            }
+        }
+        evs
+    }
+
+    /// Starting services which are allowed to pass to the running state.
+    fn ready_starting(&self) -> HashMap<ServiceName, Service> {
+        self.starting
+            .iter()
+            .filter(|(service_name, _service)| {
+                !self.not_ready.contains_key(*service_name)
+                    && !self.probing.contains_key(*service_name)
+            })
+            .map(|(service_name, service)| (service_name.clone(), service.clone()))
+            .collect()
+    }
+}
+
+fn create_notify_socket(service: &Service) -> std::io::Result<UnixDatagram> {
+    let path = service.notify_socket_path();
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    if path.exists() {
+        std::fs::remove_file(&path)?;
+    }
+    let socket = UnixDatagram::bind(&path)?;
+    socket.set_nonblocking(true)?;
+    Ok(socket)
+}
+
+/// Consumes all the pending messages on the socket, returns true if any of them contains `READY=1`.
+/// Messages are made of newline separated assignments, as in sd_notify(3).
+fn is_ready_notified(socket: &UnixDatagram) -> bool {
+    let mut buf = [0; 4096];
+    let mut is_ready = false;
+    while let Ok(size) = socket.recv(&mut buf) {
+        is_ready = is_ready
+            || String::from_utf8_lossy(&buf[..size])
+                .lines()
+                .any(|line| line.trim() == "READY=1");
+    }
+    is_ready
 }
 
 #[cfg(feature = "http-healthcheck")]
@@ -60,58 +289,97 @@ fn check_http_endpoint(endpoint: &str) -> bool {
 
 fn healthchecks(service: &Service) -> bool {
     match service.healthiness.as_ref() {
-        Some(healthiness) => {
-            // Count of required checks:
-            let mut checks = 0;
-            // Count of passed checks:
-            let mut checks_res = 0;
-            if let Some(file_path) = healthiness.file_path.as_ref() {
-                checks += 1;
-                checks_res += if file_path.exists() {
-                    1
-                } else {
-                    debug!("Healthcheck: File: {:?}, doesn't exists yet.", file_path);
-                    0
-                };
-            }
-            if let Some(endpoint) = healthiness.http_endpoint.as_ref() {
-                let check_feature = |endpoint: &String| {
-                    #[cfg(not(feature = "http-healthcheck"))]
-                    {
-                        error!("There is an http based healthcheck for {}, requesting: {} , but horust was built without the http-healthcheck feature (thus it will never pass these checks).", sh.name(), endpoint);
-                        return (1, 0);
-                    }
-                    #[cfg(feature = "http-healthcheck")]
-                    return (1, if check_http_endpoint(endpoint) { 1 } else { 0 });
-                };
-                let (check, res) = check_feature(endpoint);
-                checks += check;
-                checks_res += res
-            }
-            /*
-                Edge case: [healthcheck] header section is defined, but then it's empty. This should pass.
-            */
-            let res = checks <= checks_res;
-            let empty_section =
-                healthiness.file_path.is_some() || healthiness.http_endpoint.is_some();
-            res || !empty_section
-        }
+        Some(healthiness) => run_checks(
+            service,
+            healthiness.file_path.as_ref(),
+            service.http_endpoint(),
+        ),
         None => true,
     }
 }
 
-// Run the healthcheck, produce the event changes
+fn startup_probe_checks(service: &Service, probe: &StartupProbe) -> bool {
+    run_checks(
+        service,
+        probe.file_path.as_ref(),
+        service.startup_probe_http_endpoint(),
+    )
+}
+
+/// True if all the given checks pass.
+/// Edge case: the section is defined, but then it's empty. This should pass.
+fn run_checks(service: &Service, file_path: Option<&PathBuf>, endpoint: Option<String>) -> bool {
+    // Count of required checks:
+    let mut checks = 0;
+    // Count of passed checks:
+    let mut checks_res = 0;
+    if let Some(file_path) = file_path {
+        checks += 1;
+        checks_res += if file_path.exists() {
+            1
+        } else {
+            debug!(
+                target: service_log_target(&service.name).as_str(),
+                "Healthcheck: Service: {}, file: {:?}, doesn't exists yet.",
+                service.name, file_path
+            );
+            0
+        };
+    }
+    if let Some(endpoint) = endpoint.as_ref() {
+        let check_feature = |endpoint: &String| {
+            #[cfg(not(feature = "http-healthcheck"))]
+            {
+                error!(
+                    target: service_log_target(&service.name).as_str(),
+                    "There is an http based healthcheck for {}, requesting: {} , but horust was built without the http-healthcheck feature (thus it will never pass these checks).",
+                    service.name, endpoint
+                );
+                return (1, 0);
+            }
+            #[cfg(feature = "http-healthcheck")]
+            return (1, if check_http_endpoint(endpoint) { 1 } else { 0 });
+        };
+        let (check, res) = check_feature(endpoint);
+        checks += check;
+        checks_res += res
+    }
+    checks <= checks_res
+}
+
+/// Runs the checks of the running and starting services, returns the names of the healthy ones.
+fn check_all(
+    running: &HashMap<ServiceName, Service>,
+    starting: &HashMap<ServiceName, Service>,
+) -> HashSet<ServiceName> {
+    running
+        .iter()
+        .chain(starting.iter())
+        .filter(|(_s_name, service)| healthchecks(service))
+        .map(|(s_name, _service)| s_name.clone())
+        .collect()
+}
+
+// Produce the event changes, from the result of the healthchecks
 fn next(
     running: &HashMap<ServiceName, Service>,
     starting: &HashMap<ServiceName, Service>,
+    healthy: &HashSet<ServiceName>,
 ) -> Vec<Event> {
     let evs_starting = starting
         .iter()
-        .filter(|(_s_name, service)| healthchecks(service))
-        .map(|(s_name, _service)| Event::new_status_changed(s_name, ServiceStatus::Running));
+        .filter(|(s_name, _service)| healthy.contains(*s_name))
+        .flat_map(|(s_name, service)| {
+            let running = Event::new_status_changed(s_name, ServiceStatus::Running);
+            if service.has_healthchecks() {
+                vec![Event::new_service_healthy(s_name), running]
+            } else {
+                vec![running]
+            }
+        });
     running
         .iter()
-        .filter(|(_s_name, service)| !healthchecks(service))
+        .filter(|(s_name, _service)| !healthy.contains(*s_name))
         .map(|(service_name, _service)| {
             // TODO: change to ToBeKilled. If the healthcheck fails, maybe it's a transient failure and process might be still running.
             Event::new_status_changed(service_name, ServiceStatus::Failed)
@@ -119,11 +387,62 @@ fn next(
         .chain(evs_starting)
         .collect()
 }
-fn run(bus: BusConnector, services: Vec<Service>) {
-    let mut repo = Repo::new(bus, services);
+
+/// Runs the post healthcheck commands of the `checked` services, and updates `was_healthy`.
+/// With `post-healthcheck-on-change-only`, the command is run only if the service wasn't healthy before.
+fn post_healthcheck<'a>(
+    checked: impl Iterator<Item = &'a Service>,
+    healthy: &HashSet<ServiceName>,
+    was_healthy: &mut HashSet<ServiceName>,
+) {
+    checked.for_each(|service| {
+        if !healthy.contains(&service.name) {
+            was_healthy.remove(&service.name);
+            return;
+        }
+        let has_changed = was_healthy.insert(service.name.clone());
+        if let Some(healthiness) = service.healthiness.as_ref() {
+            if let Some(command) = healthiness.post_healthcheck_command.as_ref() {
+                if has_changed || !healthiness.post_healthcheck_on_change_only {
+                    spawn_post_healthcheck_command(&service.name, command);
+                }
+            }
+        }
+    });
+}
+
+/// The command is not waited for: as every other orphan, it's reaped by the reaper.
+fn spawn_post_healthcheck_command(service_name: &str, command: &str) {
+    debug!(
+        target: service_log_target(service_name).as_str(),
+        "Service: {}, running post healthcheck command: {}",
+        service_name, command
+    );
+    let chunks = shlex::split(command).unwrap_or_default();
+    if let Some((program, args)) = chunks.split_first() {
+        if let Err(error) = Command::new(program).args(args).spawn() {
+            error!(
+                target: service_log_target(service_name).as_str(),
+                "Service: {}, failed running the post healthcheck command: {}",
+                service_name, error
+            );
+        }
+    }
+}
+
+fn run(mut repo: Repo) {
     loop {
         repo.ingest();
-        let events = next(&repo.starting, &repo.running);
+        let mut events = repo.check_readiness();
+        events.extend(repo.check_startup_probes());
+        let starting = repo.ready_starting();
+        let healthy = check_all(&repo.running, &starting);
+        events.extend(next(&repo.running, &starting, &healthy));
+        post_healthcheck(
+            repo.running.values().chain(starting.values()),
+            &healthy,
+            &mut repo.healthy,
+        );
         for ev in events {
             repo.send_ev(ev);
         }
@@ -132,10 +451,22 @@ fn run(bus: BusConnector, services: Vec<Service>) {
 }
 
 /// Setup require for the service, before running the healthchecks and starting the service.
-pub fn prepare_service(service_handler: &ServiceHandler) -> Result<(), std::io::Error> {
-    if let Some(healthiness) = &service_handler.service().healthiness {
-        if let Some(file_path) = &healthiness.file_path {
-            std::fs::remove_file(file_path)?;
+pub fn prepare_service(service_handler: &ServiceHandler) -> std::io::Result<()> {
+    let service = service_handler.service();
+    let healthcheck_file = service
+        .healthiness
+        .as_ref()
+        .and_then(|healthiness| healthiness.file_path.as_ref());
+    let startup_probe_file = service
+        .startup_probe
+        .as_ref()
+        .and_then(|probe| probe.file_path.as_ref());
+    for file_path in healthcheck_file.into_iter().chain(startup_probe_file) {
+        // The file might not have been created yet:
+        if let Err(error) = std::fs::remove_file(file_path) {
+            if error.kind() != std::io::ErrorKind::NotFound {
+                return Err(error);
+            }
         }
     }
     Ok(())
@@ -143,11 +474,18 @@ pub fn prepare_service(service_handler: &ServiceHandler) -> Result<(), std::io::
 
 #[cfg(test)]
 mod test {
+    use crate::horust::bus::Bus;
     use crate::horust::error::Result;
-    use crate::horust::formats::{Event, Service, ServiceName, ServiceStatus};
+    use crate::horust::formats::{
+        Event, Healthness, Service, ServiceName, ServiceStatus, StartupProbe,
+    };
     use crate::horust::healthcheck;
-    use crate::horust::healthcheck::healthchecks;
-    use std::collections::HashMap;
+    use crate::horust::healthcheck::{
+        create_notify_socket, healthchecks, is_ready_notified, post_healthcheck, Probing, Repo,
+    };
+    use std::collections::{HashMap, HashSet};
+    use std::os::unix::net::UnixDatagram;
+    use std::time::Duration;
     use tempdir::TempDir;
 
     #[test]
@@ -165,14 +503,55 @@ file-path = "{}""#,
         let starting: HashMap<ServiceName, Service> = vec![(service.name.clone(), service.clone())]
             .into_iter()
             .collect();
-        let events: Vec<Event> = healthcheck::next(&HashMap::new(), &starting);
+        let healthy = healthcheck::check_all(&HashMap::new(), &starting);
+        let events: Vec<Event> = healthcheck::next(&HashMap::new(), &starting, &healthy);
         println!("{:?}", events);
         assert!(events.contains(&Event::StatusChanged(
             service.name.clone(),
             ServiceStatus::Running
         )));
+        assert!(events.contains(&Event::ServiceHealthy(service.name.clone())));
         Ok(())
     }
+    #[test]
+    fn test_post_healthcheck() -> Result<()> {
+        let tempdir = TempDir::new("health")?;
+        let output = tempdir.path().join("output");
+        let mut service = Service::from_name("a");
+        service.healthiness = Some(Healthness {
+            http_endpoint: None,
+            file_path: None,
+            post_healthcheck_command: Some(format!(
+                "/bin/sh -c 'echo run >> {}'",
+                output.display()
+            )),
+            post_healthcheck_on_change_only: true,
+        });
+        let healthy: HashSet<ServiceName> = vec![service.name.clone()].into_iter().collect();
+        let mut was_healthy = HashSet::new();
+        let runs = |service: &Service, was_healthy: &mut HashSet<ServiceName>| {
+            [&healthy, &healthy, &HashSet::new(), &healthy]
+                .iter()
+                .for_each(|healthy| {
+                    post_healthcheck(vec![service].into_iter(), healthy, was_healthy)
+                });
+            std::thread::sleep(Duration::from_millis(300));
+            let runs = std::fs::read_to_string(&output).unwrap().lines().count();
+            std::fs::remove_file(&output).unwrap();
+            runs
+        };
+        // Healthy -> unhealthy -> healthy:
+        assert_eq!(runs(&service, &mut was_healthy), 2);
+        was_healthy.clear();
+        service
+            .healthiness
+            .as_mut()
+            .unwrap()
+            .post_healthcheck_on_change_only = false;
+        assert_eq!(runs(&service, &mut was_healthy), 3);
+        Ok(())
+    }
+
     #[test]
     fn test_healthiness_checks() -> Result<()> {
         // _no_checks_needed
@@ -197,4 +576,65 @@ file-path = "{}""#,
         assert!(healthchecks(&service));
         Ok(())
     }
+
+    #[test]
+    fn test_startup_probe() -> Result<()> {
+        let tempdir = TempDir::new("health")?;
+        let file_path = tempdir.path().join("initialized");
+        let mut service = Service::from_name("a");
+        service.startup_probe = Some(StartupProbe {
+            file_path: Some(file_path.clone()),
+            period: Duration::from_secs(0),
+            failure_threshold: 2,
+            ..Default::default()
+        });
+        let mut repo = Repo::new(Bus::new().join_bus(), vec![service.clone()])?;
+        let start = |repo: &mut Repo| {
+            repo.starting.insert(service.name.clone(), service.clone());
+            repo.probing.insert(service.name.clone(), Probing::new());
+        };
+        start(&mut repo);
+        assert!(repo.check_startup_probes().is_empty());
+        // The healthiness checks don't run until the probe passes:
+        assert!(repo.ready_starting().is_empty());
+        std::fs::write(&file_path, "")?;
+        assert!(repo.check_startup_probes().is_empty());
+        assert!(repo.ready_starting().contains_key(&service.name));
+
+        // Killed once it reaches the failure threshold:
+        std::fs::remove_file(&file_path)?;
+        start(&mut repo);
+        assert!(repo.check_startup_probes().is_empty());
+        assert_eq!(
+            repo.check_startup_probes(),
+            vec![Event::new_status_changed(
+                &service.name,
+                ServiceStatus::ToBeKilled
+            )]
+        );
+        assert!(repo.starting.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_ready_notified() -> Result<()> {
+        let service: Service = toml::from_str(
+            r#"name = "notify-test"
+command = "not relevant"
+service-type = "notify""#,
+        )?;
+        let socket = create_notify_socket(&service)?;
+        assert!(!is_ready_notified(&socket));
+        let client = UnixDatagram::unbound()?;
+        let path = service.notify_socket_path();
+        client.send_to(b"STATUS=Starting", &path)?;
+        assert!(!is_ready_notified(&socket));
+        client.send_to(b"STATUS=Started\nREADY=1\n", &path)?;
+        assert!(is_ready_notified(&socket));
+        // Messages are consumed:
+        assert!(!is_ready_notified(&socket));
+        healthcheck::cleanup();
+        assert!(!path.exists());
+        Ok(())
+    }
 }
@@ -0,0 +1,79 @@
+use crate::horust::error::Result;
+use crate::horust::formats::HorustConfig;
+use log::{Log, Metadata, Record};
+use std::sync::RwLock;
+
+/// Replaced when Horust's config is reloaded.
+static LOGGER: RwLock<Option<env_logger::Logger>> = RwLock::new(None);
+
+/// Forwards to the current `LOGGER`.
+struct ReloadableLogger;
+
+impl Log for ReloadableLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        let logger = LOGGER.read().unwrap();
+        logger
+            .as_ref()
+            .is_some_and(|logger| logger.enabled(metadata))
+    }
+
+    fn log(&self, record: &Record) {
+        if let Some(logger) = LOGGER.read().unwrap().as_ref() {
+            logger.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(logger) = LOGGER.read().unwrap().as_ref() {
+            logger.flush();
+        }
+    }
+}
+
+/// Sets up Horust's logger, from `log-levels`, `log-timestamps` and the HORUST_LOG and
+/// HORUST_LOG_STYLE environment variables.
+pub fn init_logging(horust_config: &HorustConfig) -> Result<()> {
+    set_logger(build(horust_config)?);
+    log::set_logger(&ReloadableLogger).map_err(std::io::Error::other)?;
+    Ok(())
+}
+
+/// Replaces the logger set up by `init_logging` (if any) after a config reload.
+pub(crate) fn reload(horust_config: &HorustConfig) -> Result<()> {
+    let logger = build(horust_config)?;
+    if LOGGER.read().unwrap().is_some() {
+        set_logger(logger);
+    }
+    Ok(())
+}
+
+fn set_logger(logger: env_logger::Logger) {
+    log::set_max_level(logger.filter());
+    *LOGGER.write().unwrap() = Some(logger);
+}
+
+fn build(horust_config: &HorustConfig) -> Result<env_logger::Logger> {
+    let mut builder = env_logger::Builder::new();
+    builder.filter_level(log::LevelFilter::Error);
+    // Before HORUST_LOG's directives, which take precedence:
+    for (module, level) in &horust_config.log_levels {
+        let level = level.parse().map_err(|_| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Invalid log level '{}' for '{}'.", level, module),
+            )
+        })?;
+        // Hyphens are not valid in module paths, e.g. in the services' names:
+        builder.filter_module(module.replace('-', "_").as_str(), level);
+    }
+    if let Ok(filters) = std::env::var("HORUST_LOG") {
+        builder.parse_filters(filters.as_str());
+    }
+    if let Ok(write_style) = std::env::var("HORUST_LOG_STYLE") {
+        builder.parse_write_style(write_style.as_str());
+    }
+    if !horust_config.log_timestamps.unwrap_or(true) {
+        builder.format_timestamp(None);
+    }
+    Ok(builder.build())
+}
@@ -1,39 +1,157 @@
 mod bus;
+mod capabilities;
 mod error;
+mod events_fifo;
 mod formats;
 mod healthcheck;
+mod logging;
+mod pid_file;
+mod pipes;
 mod reaper;
 mod runtime;
+mod services_status;
 mod signal_handling;
+mod state_file;
+mod systemd_activation;
 
 pub use self::error::HorustError;
-pub use self::formats::get_sample_service;
+pub use self::formats::{
+    changes_impact, get_sample_service, ChangeImpact, Documentation, EnvFileFormat,
+    FailureStrategy, HorustConfig, KillMode, Namespace, ProtectSystem, RestartStrategy, Service,
+    ServiceBuilder, ServiceFieldChange, ServiceStatus, ServiceType, StartLimitAction,
+    StopTimeoutAction, TerminationSignal, TerminationStep, User,
+};
+pub use self::logging::init_logging;
 use crate::horust::bus::Bus;
-use crate::horust::error::Result;
-use crate::horust::formats::{validate, Service};
-pub use formats::Event;
+use crate::horust::error::{Result, ValidationError, ValidationErrorKind};
+use crate::horust::formats::{validate, validate_added, ConfigSource, ServiceName};
+use crate::horust::services_status::ServicesStatus;
+use crossbeam::channel::Sender;
+pub use formats::{Event, EventPriority};
 use libc::{prctl, PR_SET_CHILD_SUBREAPER};
+use std::collections::HashSet;
 use std::ffi::OsStr;
 use std::fmt::Debug;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Prefix of the environment variables defining a service, see `services_from_env`.
+const SERVICE_ENV_PREFIX: &str = "HORUST_SERVICE_";
 
 #[derive(Debug)]
 pub struct Horust {
     pub services: Vec<Service>,
-    services_dir: Option<PathBuf>,
+    horust_config: HorustConfig,
+    /// Re-read on the `horust-config-reload-signal`.
+    config_source: Option<ConfigSource>,
+    /// Taken by `run()`.
+    bus: Mutex<Option<Bus>>,
+    /// Used for sending events from the outside, e.g. `add_service`.
+    publisher: Sender<Event>,
+    /// Names of the services, including the ones added at runtime.
+    known_services: Mutex<HashSet<ServiceName>>,
+    services_status: Arc<ServicesStatus>,
+    /// Horust exits with the exit code of this service (see `from_test_service`).
+    exit_code_of: Option<ServiceName>,
 }
 
 impl Horust {
-    fn new(services: Vec<Service>, services_dir: Option<PathBuf>) -> Self {
+    fn new(services: Vec<Service>) -> Self {
+        let bus = Bus::new();
+        let publisher = bus.publisher();
+        let known_services = services.iter().map(|s| s.name.clone()).collect();
         Horust {
             services,
-            services_dir,
+            horust_config: Default::default(),
+            config_source: None,
+            bus: Mutex::new(Some(bus)),
+            publisher,
+            known_services: Mutex::new(known_services),
+            services_status: Default::default(),
+            exit_code_of: None,
+        }
+    }
+
+    /// Adds a new service, and starts it (also if `run()` has already been called).
+    /// Its dependencies must be already known by this instance.
+    /// It's ignored if it doesn't run on this host (see `condition-hostname`).
+    pub fn add_service(&self, service: Service) -> Result<()> {
+        let mut known_services = self.known_services.lock().unwrap();
+        validate_added(&service, &known_services)?;
+        if !service.runs_on_host(&hostname()?) {
+            info!(
+                "Service: {} doesn't run on this host, ignoring it.",
+                service.name
+            );
+            return Ok(());
         }
+        known_services.insert(service.name.clone());
+        self.publisher
+            .send(Event::new_add_service(service))
+            .expect("Failed sending add service event!");
+        Ok(())
+    }
+
+    /// Stops the service gracefully, and removes it. It won't be restarted.
+    /// With a `state-file`, it won't be started after Horust's restarts either, until it's added back.
+    /// Services starting after it are not stopped, but they won't start anymore if they haven't yet.
+    pub fn remove_service(&self, name: &str) -> Result<()> {
+        let mut known_services = self.known_services.lock().unwrap();
+        if !known_services.remove(name) {
+            let err = format!("There is no service named '{}'.", name);
+            return Err(vec![ValidationError::new(
+                err.as_str(),
+                ValidationErrorKind::UnknownService,
+            )]
+            .into());
+        }
+        self.publisher
+            .send(Event::RemoveService(name.to_string()))
+            .expect("Failed sending remove service event!");
+        Ok(())
+    }
+
+    /// Use `horust_config` instead of the default configuration.
+    pub fn set_horust_config(&mut self, horust_config: HorustConfig) {
+        self.horust_config = horust_config;
+    }
+
+    /// Re-read the config in `path` on the `horust-config-reload-signal`, merged with `cli_config`
+    /// as in `HorustConfig::load_and_merge`.
+    pub fn set_config_file(&mut self, path: PathBuf, cli_config: HorustConfig) {
+        self.config_source = Some(ConfigSource { path, cli_config });
     }
 
     pub fn from_command(command: String) -> Self {
-        Self::new(vec![Service::from_command(command)], None)
+        Self::new(vec![Service::from_command(command)])
+    }
+
+    /// Runs only the service in `path`, once and without its dependencies, regardless of its restart policy.
+    /// Horust will exit with the service's exit code.
+    pub fn from_test_service(path: &Path) -> Result<Self> {
+        let mut service = Service::from_file(&path.to_path_buf())?;
+        if service.name.is_empty() {
+            service.name = path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned();
+        }
+        service.restart.on_success = Some(false);
+        service.restart.on_failure = Some(false);
+        service.restart.attempts = 0;
+        service.start_after.clear();
+        service.depends_on_healthy.clear();
+        service.stdin_from_service = None;
+        service.termination.die_if_failed.clear();
+        let service_name = service.name.clone();
+        let mut horust = validate(vec![service])
+            .map_err(HorustError::from)
+            .map(Horust::new)?;
+        horust.exit_code_of = Some(service_name);
+        Ok(horust)
     }
 
     /// Create a new horust instance from a path of services.
@@ -42,28 +160,202 @@ impl Horust {
         P: AsRef<Path> + ?Sized + AsRef<OsStr> + Debug,
     {
         let services = fetch_services(&path)?;
-        validate(services)
-            .map_err(Into::into)
-            .map(|services| Horust::new(services, Some(PathBuf::from(path))))
+        validate(services).map_err(Into::into).map(Horust::new)
+    }
+
+    /// Create a new horust instance from a path of services, and the services defined in `horust_config`.
+    /// The inline services take precedence over the services in `path` with the same name.
+    pub fn from_services_dir_and_config<P>(path: &P, horust_config: HorustConfig) -> Result<Self>
+    where
+        P: AsRef<Path> + ?Sized + AsRef<OsStr> + Debug,
+    {
+        let services = merge_inline_services(fetch_services(&path)?, &horust_config.services)?;
+        Self::from_services_and_config(services, horust_config)
     }
 
-    pub fn run(&mut self) {
+    /// Create a new horust instance with all the options of `horust_config`, running only its
+    /// `[[services]]`: nothing is read from the filesystem.
+    pub fn from_horust_config(horust_config: HorustConfig) -> Result<Self> {
+        let services = merge_inline_services(vec![], &horust_config.services)?;
+        Self::from_services_and_config(services, horust_config)
+    }
+
+    fn from_services_and_config(
+        services: Vec<Service>,
+        horust_config: HorustConfig,
+    ) -> Result<Self> {
+        let mut horust = validate(services)
+            .map_err(HorustError::from)
+            .map(Horust::new)?;
+        horust.horust_config = horust_config;
+        Ok(horust)
+    }
+
+    /// Blocks until the service is Running, or it has finished (Finished or FinishedFailed status).
+    /// Returns the reached status, or an error if it didn't happen within `timeout`.
+    pub fn wait_for_service(&self, name: &str, timeout: Duration) -> Result<ServiceStatus> {
+        if !self.known_services.lock().unwrap().contains(name) {
+            let err = format!("There is no service named '{}'.", name);
+            return Err(vec![ValidationError::new(
+                err.as_str(),
+                ValidationErrorKind::UnknownService,
+            )]
+            .into());
+        }
+        let is_reached = |status: &ServiceStatus| {
+            [
+                ServiceStatus::Running,
+                ServiceStatus::Finished,
+                ServiceStatus::FinishedFailed,
+            ]
+            .contains(status)
+        };
+        self.services_status
+            .wait_for(name, timeout, is_reached)
+            .ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    format!("Timeout waiting for service '{}'.", name),
+                )
+                .into()
+            })
+    }
+
+    /// Stops all the services gracefully, as on SIGTERM, and blocks until Horust exits.
+    /// Since `run()` exits the process once all the services have finished, this doesn't return
+    /// while Horust is running. If `run()` hasn't been called yet, it returns immediately,
+    /// and `run()` will stop the services as soon as they're started.
+    pub fn shutdown(&self) {
+        self.publisher
+            .send(Event::ShuttingDownInitiated)
+            .expect("Failed sending shutting down event!");
+        if self.bus.lock().unwrap().is_some() {
+            return;
+        }
+        loop {
+            std::thread::park();
+        }
+    }
+
+    /// Blocking. Horust will exit the process once all the services have finished, either
+    /// on its own or after a SIGTERM or a `shutdown()`: it only returns if it fails to start.
+    pub fn run(&self) -> Result<()> {
+        let mut dispatcher = self
+            .bus
+            .lock()
+            .unwrap()
+            .take()
+            .ok_or_else(|| std::io::Error::other("Horust is already running."))?;
         unsafe {
             prctl(PR_SET_CHILD_SUBREAPER, 1, 0, 0, 0);
         }
-        signal_handling::init();
+        signal_handling::init(self.horust_config.reload_signal()?);
+        systemd_activation::init();
+        dispatcher.set_history_size(self.horust_config.event_history_size.unwrap_or_default());
+        dispatcher.set_dedup_window(self.horust_config.bus_dedup_window.unwrap_or_default());
+        if let Some(capacity) = self.horust_config.bus_subscriber_capacity {
+            dispatcher.set_subscriber_capacity(capacity);
+        }
+        if let Some(send_timeout) = self.horust_config.bus_subscriber_send_timeout {
+            dispatcher.set_send_timeout(send_timeout);
+        }
+        if !self.horust_config.bus_latency_buckets.is_empty() {
+            let buckets = &self.horust_config.bus_latency_buckets;
+            dispatcher.set_latency_buckets(buckets.iter().map(|bucket| **bucket).collect());
+        }
+        if let Some(path) = self.horust_config.pid_file.as_ref() {
+            pid_file::write(path, self.horust_config.pid_file_force)?;
+        }
+
+        let mut services = self.services.clone();
+        let hostname = hostname()?;
+        services.retain(|service| {
+            let runs_on_host = service.runs_on_host(&hostname);
+            if !runs_on_host {
+                info!(
+                    "Service: {} doesn't run on host: {}, skipping it.",
+                    service.name, hostname
+                );
+                self.known_services.lock().unwrap().remove(&service.name);
+            }
+            runs_on_host
+        });
+        if let Some(path) = self.horust_config.state_file.as_ref() {
+            let state = state_file::load(path)?;
+            let mut known_services = self.known_services.lock().unwrap();
+            services.retain(|service| {
+                let is_stopped = state.stopped.contains(&service.name);
+                if is_stopped {
+                    info!(
+                        "Service: {} has been stopped, not starting it.",
+                        service.name
+                    );
+                    known_services.remove(&service.name);
+                }
+                !is_stopped
+            });
+            let bus = dispatcher.join_bus_auxiliary(state_file::filter);
+            state_file::spawn(bus, path.clone(), state);
+        }
 
-        let mut dispatcher = Bus::new();
-        debug!("Services: {:?}", self.services);
+        debug!("Services: {:?}", services);
+        if let Some(path) = self.horust_config.events_fifo.as_ref() {
+            events_fifo::spawn(dispatcher.join_bus_auxiliary(|_| true), path.clone())?;
+        }
         // Spawn helper threads:
         debug!("Going to spawn threads:, going to start running services now!");
-        runtime::spawn(dispatcher.join_bus(), self.services.clone());
-        reaper::spawn(dispatcher.join_bus());
-        healthcheck::spawn(dispatcher.join_bus(), self.services.clone());
-        dispatcher.run();
+        // Healthcheck first: it sets up the notify sockets before any service is started.
+        healthcheck::spawn(
+            dispatcher.join_bus_filtered(healthcheck::filter),
+            services.clone(),
+        )?;
+        runtime::spawn(
+            dispatcher.join_bus(),
+            services,
+            self.horust_config.clone(),
+            self.config_source.clone(),
+            self.exit_code_of.clone(),
+        );
+        reaper::spawn(dispatcher.join_bus_filtered(reaper::filter));
+        services_status::spawn(
+            dispatcher.join_bus_auxiliary(services_status::filter),
+            Arc::clone(&self.services_status),
+        );
+        let exit_code = dispatcher.run();
+        std::process::exit(exit_code);
     }
 }
 
+/// Adds the `inline` services to `services`, replacing the ones with the same name.
+fn merge_inline_services(mut services: Vec<Service>, inline: &[Service]) -> Result<Vec<Service>> {
+    if inline.iter().any(|service| service.name.is_empty()) {
+        return Err(vec![ValidationError::new(
+            "The services defined in Horust's config need a name.",
+            ValidationErrorKind::MissingValue,
+        )]
+        .into());
+    }
+    services.retain(|service| {
+        let is_replaced = inline.iter().any(|s| s.name == service.name);
+        if is_replaced {
+            info!(
+                "Service: {} is also defined in Horust's config, using that one.",
+                service.name
+            );
+        }
+        !is_replaced
+    });
+    services.extend_from_slice(inline);
+    Ok(services)
+}
+
+/// The name of this host, used by `condition-hostname`.
+fn hostname() -> Result<String> {
+    let mut buf = [0u8; 256];
+    let hostname = nix::unistd::gethostname(&mut buf)?;
+    Ok(hostname.to_string_lossy().into_owned())
+}
+
 /// List files in p, filtering out directories.
 fn list_files<P: AsRef<Path>>(path: P) -> std::io::Result<Vec<PathBuf>> {
     fs::read_dir(path)?
@@ -98,14 +390,14 @@ where
     let dir = fs::read_dir(path)?;
 
     //TODO: option to decide to not start if the deserialization of any service failed.
-    let services = dir
+    let mut services = dir
         .filter_map(std::result::Result::ok)
         .map(|dir_entry| dir_entry.path())
         .filter(is_toml_file)
-        .map(|file| {
+        .flat_map(|file| {
             let res = Service::from_file(&file);
             res.map(|mut service| {
-                if service.name == "" {
+                if service.name.is_empty() {
                     let filename = file.file_name().unwrap().to_str().unwrap().to_owned();
                     service.name = filename;
                 }
@@ -116,19 +408,93 @@ where
                 error
             })
         })
-        .filter(Result::is_ok)
-        .map(Result::unwrap)
         .collect::<Vec<Service>>();
+    services.extend(services_from_env(std::env::vars()));
+    let mut services = instantiate_templates(services);
+    services
+        .iter_mut()
+        .for_each(|service| service.load_description_file(path.as_ref()));
     if services.is_empty() {
         println!("Horust: No services found in: {:?}.", path);
     }
     Ok(services)
 }
 
+/// The services defined in the `HORUST_SERVICE_<N>` environment variables, ordered by `N`.
+/// The value is a service's toml or, if it isn't valid toml, a percent-encoded one (e.g. for env
+/// files, which can't contain newlines). A service without a name is named `horust-service-<N>.toml`.
+fn services_from_env<I>(vars: I) -> Vec<Service>
+where
+    I: Iterator<Item = (String, String)>,
+{
+    let mut vars: Vec<(usize, String)> = vars
+        .filter_map(|(key, value)| {
+            let index = key
+                .strip_prefix(SERVICE_ENV_PREFIX)?
+                .parse::<usize>()
+                .ok()?;
+            Some((index, value))
+        })
+        .collect();
+    vars.sort();
+    vars.into_iter()
+        .filter_map(|(index, value)| {
+            let res = value.parse::<Service>().or_else(|error| {
+                let decoded = percent_encoding::percent_decode_str(value.as_str()).decode_utf8();
+                match decoded {
+                    Ok(decoded) if decoded != value => decoded.parse::<Service>(),
+                    _ => Err(error),
+                }
+            });
+            match res {
+                Ok(mut service) => {
+                    if service.name.is_empty() {
+                        service.name = format!("horust-service-{}.toml", index);
+                    }
+                    Some(service)
+                }
+                Err(error) => {
+                    error!("Error loading {}{}: {}", SERVICE_ENV_PREFIX, index, error);
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+/// The instances of the templates (`service-type = "template"`, e.g. a `worker@1.toml` symlink to
+/// `worker@.toml`) become simple services, with `%i` replaced by their instance in the command.
+/// The templates themselves are not run.
+fn instantiate_templates(services: Vec<Service>) -> Vec<Service> {
+    services
+        .into_iter()
+        .filter_map(|mut service| {
+            if service.service_type != ServiceType::Template {
+                return Some(service);
+            }
+            let instance = match service.template_instance() {
+                Some(instance) => instance.to_string(),
+                None => {
+                    debug!(
+                        "Service: {} is a template, it won't be started.",
+                        service.name
+                    );
+                    return None;
+                }
+            };
+            service.command = service.command.replace("%i", instance.as_str());
+            service.service_type = ServiceType::Simple;
+            Some(service)
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod test {
-    use crate::horust::fetch_services;
-    use crate::horust::formats::Service;
+    use crate::horust::formats::{HorustConfig, Service, ServiceType};
+    use crate::horust::{
+        fetch_services, instantiate_templates, merge_inline_services, services_from_env, Horust,
+    };
     use std::io;
     use tempdir::TempDir;
 
@@ -155,4 +521,98 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn test_services_from_env() {
+        let vars = vec![
+            (
+                "HORUST_SERVICE_1",
+                "command = \"/bin/echo 100%\"\n[restart]\nstrategy = \"always\"",
+            ),
+            (
+                "HORUST_SERVICE_0",
+                "name%20%3D%20%22a.toml%22%0Acommand%20%3D%20%22%2Fbin%2Ftrue%22",
+            ),
+            ("HORUST_SERVICE_2", "not a service"),
+            ("HORUST_SERVICE_X", "command = \"/bin/true\""),
+            ("PATH", "/bin"),
+        ];
+        let vars = vars
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()));
+        let services = services_from_env(vars);
+        assert_eq!(services.len(), 2);
+        assert_eq!(services[0].name, "a.toml");
+        assert_eq!(services[0].command, "/bin/true");
+        assert_eq!(services[1].name, "horust-service-1.toml");
+        assert_eq!(services[1].command, "/bin/echo 100%");
+    }
+
+    #[test]
+    fn test_instantiate_templates() {
+        let template = |name: &str| {
+            let mut service = Service::from_command("/bin/echo worker %i".into());
+            service.name = name.into();
+            service.service_type = ServiceType::Template;
+            service
+        };
+        let services = vec![
+            template("worker@.toml"),
+            template("worker@eu-1.toml"),
+            Service::from_name("a"),
+        ];
+        let services = instantiate_templates(services);
+        assert_eq!(services.len(), 2);
+        assert_eq!(services[0].name, "worker@eu-1.toml");
+        assert_eq!(services[0].command, "/bin/echo worker eu-1");
+        assert_eq!(services[0].service_type, ServiceType::Simple);
+        assert_eq!(services[1], Service::from_name("a"));
+    }
+
+    #[test]
+    fn test_merge_inline_services() {
+        let mut inline = Service::from_name("b");
+        inline.command = "inline".into();
+        let services = vec![Service::from_name("a"), Service::from_name("b")];
+        let merged = merge_inline_services(services.clone(), &[inline.clone()]).unwrap();
+        assert_eq!(merged, vec![Service::from_name("a"), inline]);
+
+        // Inline services need a name:
+        merge_inline_services(services, &[Service::from_name("")]).unwrap_err();
+    }
+
+    #[test]
+    fn test_from_horust_config() {
+        let config: HorustConfig = r#"
+fail-fast = true
+[[services]]
+name = "a"
+command = "/bin/true"
+[[services]]
+name = "b"
+command = "/bin/true"
+start-after = ["a"]"#
+            .parse()
+            .unwrap();
+        let horust = Horust::from_horust_config(config.clone()).unwrap();
+        assert_eq!(horust.services, config.services);
+        assert_eq!(horust.horust_config, config);
+
+        // The services are validated:
+        let config: HorustConfig = r#"
+[[services]]
+name = "b"
+command = "/bin/true"
+start-after = ["a"]"#
+            .parse()
+            .unwrap();
+        Horust::from_horust_config(config).unwrap_err();
+    }
+
+    #[test]
+    fn test_shutdown_before_run() {
+        let horust = Horust::from_command("/bin/true".into());
+        // Not running, so it doesn't block:
+        horust.shutdown();
+    }
 }
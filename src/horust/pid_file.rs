@@ -0,0 +1,90 @@
+use crate::horust::error::Result;
+use nix::errno::Errno;
+use nix::sys::signal::kill;
+use nix::unistd::{getpid, Pid};
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
+/// Writes Horust's pid in `path`. The file is written in a temporary file and then renamed, so
+/// readers never see a partial pid.
+/// Fails if the file belongs to another running process, unless `force` is true.
+pub(crate) fn write(path: &Path, force: bool) -> Result<()> {
+    if let Some(pid) = read_pid(path) {
+        if !force && pid != getpid() && is_alive(pid) {
+            return Err(Error::new(
+                ErrorKind::AlreadyExists,
+                format!(
+                    "Pid file {} belongs to a running process: {}",
+                    path.display(),
+                    pid
+                ),
+            )
+            .into());
+        }
+    }
+    let tmp_path = path.with_file_name(format!(
+        ".{}.tmp",
+        path.file_name().unwrap_or_default().to_string_lossy()
+    ));
+    std::fs::write(&tmp_path, format!("{}\n", getpid()))?;
+    std::fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+/// Removes the pid file, if it still contains Horust's pid.
+pub(crate) fn remove(path: &Path) {
+    if read_pid(path) == Some(getpid()) {
+        if let Err(error) = std::fs::remove_file(path) {
+            error!("Failed removing pid file {}: {}", path.display(), error);
+        }
+    }
+}
+
+fn read_pid(path: &Path) -> Option<Pid> {
+    let content = std::fs::read_to_string(path).ok()?;
+    content.trim().parse().ok().map(Pid::from_raw)
+}
+
+/// Signal 0 only checks if the process exists. EPERM means it's owned by someone else.
+fn is_alive(pid: Pid) -> bool {
+    match kill(pid, None) {
+        Ok(()) => true,
+        Err(error) => error.as_errno() == Some(Errno::EPERM),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::horust::error::Result;
+    use crate::horust::pid_file::{read_pid, remove, write};
+    use nix::unistd::{getpid, getppid};
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_write_and_remove() -> Result<()> {
+        let tempdir = TempDir::new("pid-file")?;
+        let path = tempdir.path().join("horust.pid");
+        write(&path, false)?;
+        assert_eq!(read_pid(&path), Some(getpid()));
+        // Can be overwritten by the same process:
+        write(&path, false)?;
+        remove(&path);
+        assert!(!path.exists());
+
+        // Stale pid file:
+        std::fs::write(&path, "999999999")?;
+        write(&path, false)?;
+        assert_eq!(read_pid(&path), Some(getpid()));
+
+        // Another process is running:
+        std::fs::write(&path, getppid().to_string())?;
+        assert!(write(&path, false).is_err());
+        // It's not ours, so it's not removed:
+        remove(&path);
+        assert!(path.exists());
+        write(&path, true)?;
+        assert_eq!(read_pid(&path), Some(getpid()));
+        assert_eq!(std::fs::read_dir(tempdir.path())?.count(), 1);
+        Ok(())
+    }
+}
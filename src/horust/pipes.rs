@@ -0,0 +1,164 @@
+use crate::horust::error::Result;
+use crate::horust::formats::{Service, ServiceName};
+use nix::fcntl::OFlag;
+use nix::unistd::{close, pipe2};
+use std::collections::HashMap;
+use std::os::unix::io::RawFd;
+use std::sync::{Arc, Mutex};
+
+/// Pipes connecting a producer's stdout to a consumer's stdin (`stdin-from-service`).
+/// Horust keeps an end only until the service using it has been spawned: afterwards the child
+/// has the only copy, so if either service exits, the other one gets an EOF (or EPIPE).
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Pipes {
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    /// Producer's name -> consumer's name.
+    consumers: HashMap<ServiceName, ServiceName>,
+    /// The ends not handed to a service yet, by consumer's name.
+    ends: HashMap<ServiceName, PipeEnds>,
+}
+
+#[derive(Debug, Default)]
+struct PipeEnds {
+    read: Option<RawFd>,
+    write: Option<RawFd>,
+}
+
+impl PipeEnds {
+    fn close(&mut self) {
+        self.read.take().into_iter().for_each(close_fd);
+        self.write.take().into_iter().for_each(close_fd);
+    }
+}
+
+fn close_fd(fd: RawFd) {
+    if let Err(error) = close(fd) {
+        error!("Failed closing pipe's fd {}: {}", fd, error);
+    }
+}
+
+/// The fds to be used as stdin and stdout by the child. `None` keeps Horust's one.
+#[derive(Debug, Default)]
+pub(crate) struct Stdio {
+    pub stdin: Option<RawFd>,
+    pub stdout: Option<RawFd>,
+}
+
+impl Stdio {
+    /// Closes Horust's copy of the fds, once they have been inherited by the child.
+    pub(crate) fn close(self) {
+        self.stdin.into_iter().for_each(close_fd);
+        self.stdout.into_iter().for_each(close_fd);
+    }
+}
+
+impl Pipes {
+    pub(crate) fn new(services: &[Service]) -> Self {
+        let pipes = Self::default();
+        services.iter().for_each(|service| pipes.add(service));
+        pipes
+    }
+
+    pub(crate) fn add(&self, service: &Service) {
+        if let Some(producer) = &service.stdin_from_service {
+            let mut inner = self.inner.lock().unwrap();
+            if let Some(consumer) = inner.consumers.get(producer) {
+                warn!(
+                    "Service: {} already reads from: {}, ignoring stdin-from-service of: {}",
+                    consumer, producer, service.name
+                );
+                return;
+            }
+            inner
+                .consumers
+                .insert(producer.clone(), service.name.clone());
+        }
+    }
+
+    /// Forgets about the pipes used by this service, closing the ends which are still open.
+    pub(crate) fn remove(&self, service_name: &ServiceName) {
+        let mut inner = self.inner.lock().unwrap();
+        inner
+            .consumers
+            .retain(|producer, consumer| producer != service_name && consumer != service_name);
+        if let Some(mut ends) = inner.ends.remove(service_name) {
+            ends.close();
+        }
+    }
+
+    /// Takes the ends of the pipes to be used by the service. A new pipe is created,
+    /// if the end has already been used by a previous run of the service.
+    pub(crate) fn take_stdio(&self, service: &Service) -> Result<Stdio> {
+        let mut inner = self.inner.lock().unwrap();
+        let mut stdio = Stdio::default();
+        let consumer = inner.consumers.get(&service.name).cloned();
+        if let Some(consumer) = consumer {
+            let ends = inner.ends.entry(consumer).or_default();
+            if ends.write.is_none() {
+                renew(ends)?;
+            }
+            stdio.stdout = ends.write.take();
+        }
+        if inner.consumers.values().any(|name| *name == service.name) {
+            let ends = inner.ends.entry(service.name.clone()).or_default();
+            if ends.read.is_none() {
+                renew(ends)?;
+            }
+            stdio.stdin = ends.read.take();
+        }
+        Ok(stdio)
+    }
+}
+
+/// Replaces the ends with a new pipe. They are closed on exec, so only the child which has
+/// them as stdin or stdout will keep them.
+fn renew(ends: &mut PipeEnds) -> Result<()> {
+    ends.close();
+    let (read, write) = pipe2(OFlag::O_CLOEXEC)?;
+    ends.read = Some(read);
+    ends.write = Some(write);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::horust::error::Result;
+    use crate::horust::formats::Service;
+    use crate::horust::pipes::Pipes;
+    use nix::unistd::{read, write};
+
+    #[test]
+    fn test_take_stdio() -> Result<()> {
+        let producer = Service::from_name("producer");
+        let mut consumer = Service::from_name("consumer");
+        consumer.stdin_from_service = Some("producer".into());
+        let pipes = Pipes::new(&[producer.clone(), consumer.clone()]);
+
+        let producer_stdio = pipes.take_stdio(&producer)?;
+        assert!(producer_stdio.stdin.is_none());
+        let stdout = producer_stdio.stdout.unwrap();
+        write(stdout, b"hello")?;
+
+        let consumer_stdio = pipes.take_stdio(&consumer)?;
+        assert!(consumer_stdio.stdout.is_none());
+        let stdin = consumer_stdio.stdin.unwrap();
+        let mut buf = [0; 5];
+        read(stdin, &mut buf)?;
+        assert_eq!(&buf, b"hello");
+        producer_stdio.close();
+        // The only writer has been closed:
+        assert_eq!(read(stdin, &mut buf)?, 0);
+        consumer_stdio.close();
+
+        // A restarted producer gets a new pipe:
+        let producer_stdio = pipes.take_stdio(&producer)?;
+        assert!(producer_stdio.stdout.is_some());
+        producer_stdio.close();
+        pipes.remove(&consumer.name);
+        Ok(())
+    }
+}
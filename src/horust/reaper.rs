@@ -1,10 +1,15 @@
 use crate::horust::bus::BusConnector;
-use crate::horust::formats::{Event, ServiceName, ServiceStatus};
-use nix::sys::wait::{waitpid, WaitStatus};
+use crate::horust::formats::{Event, ExitStatus, ServiceName, ServiceStatus};
+use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
 use nix::unistd::Pid;
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::time::Duration;
 
+/// The events needed for matching the exited pids with their services.
+pub(crate) fn filter(ev: &Event) -> bool {
+    matches!(ev, Event::PidChanged(..) | Event::StatusChanged(..))
+}
+
 pub(crate) fn spawn(bus: BusConnector) {
     std::thread::spawn(move || {
         supervisor_thread(bus);
@@ -17,13 +22,24 @@ struct Repo {
     possibly_running: HashSet<ServiceName>,
     bus: BusConnector,
     pids_map: HashMap<Pid, ServiceName>,
+    /// All the processes spawned by the services (directly or not), mapped to their service.
+    /// Orphans are re-parented to Horust (it's a subreaper), so this is used for reaping them as well.
+    descendants: HashMap<Pid, ServiceName>,
+    /// The last reaped descendants with their exit status, in case their pid is announced afterwards
+    /// (e.g. read from an `exec-wait-pid-file`).
+    reaped_descendants: VecDeque<(Pid, ExitStatus)>,
 }
 
+/// How many of the last reaped descendants are kept.
+const REAPED_DESCENDANTS_SIZE: usize = 64;
+
 impl Repo {
     fn new(bus: BusConnector) -> Self {
         Repo {
             possibly_running: HashSet::new(),
             pids_map: HashMap::new(),
+            descendants: HashMap::new(),
+            reaped_descendants: VecDeque::new(),
             bus,
         }
     }
@@ -31,10 +47,21 @@ impl Repo {
     fn consume(&mut self, ev: Event) {
         match ev {
             Event::PidChanged(service_name, pid) => {
-                self.pids_map.insert(pid, service_name);
+                let reaped = self
+                    .reaped_descendants
+                    .iter()
+                    .position(|(reaped, _exit_status)| *reaped == pid);
+                match reaped.and_then(|index| self.reaped_descendants.remove(index)) {
+                    Some((_pid, exit_status)) => self
+                        .bus
+                        .send_event(Event::new_service_exited(service_name, exit_status)),
+                    None => {
+                        self.pids_map.insert(pid, service_name);
+                    }
+                }
             }
             Event::StatusChanged(service_name, status) => {
-                if vec![ServiceStatus::ToBeRun, ServiceStatus::Initial].contains(&status) {
+                if [ServiceStatus::ToBeRun, ServiceStatus::Initial].contains(&status) {
                     self.possibly_running.insert(service_name);
                 } else {
                     self.possibly_running.remove(&service_name);
@@ -43,11 +70,55 @@ impl Repo {
             _ => (),
         }
     }
-    fn send_pid_exited(&mut self, pid: Pid, exit_code: i32) {
+    fn send_pid_exited(&mut self, pid: Pid, exit_status: ExitStatus) {
         if self.pids_map.contains_key(&pid) {
             let service_name = self.pids_map.remove(&pid).unwrap();
             self.bus
-                .send_event(Event::new_service_exited(service_name, exit_code));
+                .send_event(Event::new_service_exited(service_name, exit_status));
+        }
+    }
+
+    /// Follows the ppid lineage of `pid` until it finds a process belonging to a service.
+    /// Previous attributions are kept: once orphaned, the parent of a process is Horust.
+    fn find_service(&self, pid: Pid, ppids: &HashMap<Pid, Pid>) -> Option<ServiceName> {
+        let mut current = pid;
+        // Bounded, in case of inconsistent data:
+        for _ in 0..=ppids.len() {
+            if let Some(service_name) = self
+                .pids_map
+                .get(&current)
+                .or_else(|| self.descendants.get(&current))
+            {
+                return Some(service_name.clone());
+            }
+            current = *ppids.get(&current)?;
+        }
+        None
+    }
+
+    /// Rebuilds the descendants map, using a snapshot of process -> parent pids.
+    fn update_descendants(&mut self, ppids: &HashMap<Pid, Pid>) {
+        self.descendants = ppids
+            .keys()
+            .filter(|pid| !self.pids_map.contains_key(pid))
+            .filter_map(|pid| Some((*pid, self.find_service(*pid, ppids)?)))
+            .collect();
+    }
+
+    /// Returns true if `pid` is a descendant of a service, and stops tracking it.
+    fn reap_descendant(&mut self, pid: &Pid, exit_status: ExitStatus) -> bool {
+        if let Some(service_name) = self.descendants.remove(pid) {
+            debug!(
+                "Reaped pid: {} of service: {}, {}",
+                pid, service_name, exit_status
+            );
+            if self.reaped_descendants.len() == REAPED_DESCENDANTS_SIZE {
+                self.reaped_descendants.pop_front();
+            }
+            self.reaped_descendants.push_back((*pid, exit_status));
+            true
+        } else {
+            false
         }
     }
 
@@ -65,31 +136,131 @@ pub(crate) fn supervisor_thread(bus: BusConnector) {
 
     loop {
         repo.ingest();
-        match waitpid(Pid::from_raw(-1), None) {
-            Ok(wait_status) => {
-                if let WaitStatus::Exited(pid, exit_code) = wait_status {
+        repo.update_descendants(&read_ppids());
+        // Non blocking, otherwise the retained pids below would wait for another child to exit:
+        loop {
+            match waitpid(Pid::from_raw(-1), Some(WaitPidFlag::WNOHANG)) {
+                Ok(WaitStatus::StillAlive) => break,
+                Ok(WaitStatus::Exited(pid, exit_code)) => {
                     debug!("Pid has exited: {} with exitcode: {}", pid, exit_code);
-                    reapable.insert(pid, exit_code);
+                    reapable.insert(pid, ExitStatus::Exited(exit_code));
                 }
-            }
-            Err(err) => {
-                if !err.to_string().contains("ECHILD") {
-                    error!("Error waitpid(): {}", err);
+                Ok(WaitStatus::Signaled(pid, signal, core_dumped)) => {
+                    debug!("Pid has been killed: {} by signal: {}", pid, signal);
+                    reapable.insert(pid, ExitStatus::Signaled(signal as i32, core_dumped));
+                }
+                // Stopped or continued, it's still alive:
+                Ok(_) => (),
+                Err(err) => {
+                    if !err.to_string().contains("ECHILD") {
+                        error!("Error waitpid(): {}", err);
+                    }
+                    break;
                 }
             }
         }
         // It might happen that before supervised was updated, the process was already started, executed,
         // and exited. Thus we're trying to reaping it, but there is still no map Pid -> Service.
-        reapable.retain(|pid, exit_code| {
+        reapable.retain(|pid, exit_status| {
             if repo.pids_map.contains_key(pid) {
-                repo.send_pid_exited(*pid, *exit_code);
-                true
+                repo.send_pid_exited(*pid, *exit_status);
+                false
+            } else if repo.reap_descendant(pid, *exit_status) {
+                false
             } else {
-                // If is a grandchildren, we don't care about it:
-                // is grandchildren =
-                repo.possibly_running.is_empty()
+                // Keep it while its pid might still be announced, otherwise is a grandchildren
+                // and we don't care about it:
+                !repo.possibly_running.is_empty()
             }
         });
         std::thread::sleep(Duration::from_millis(500))
     }
 }
+
+/// Snapshot of every process' parent pid, read from /proc.
+fn read_ppids() -> HashMap<Pid, Pid> {
+    let entries = match std::fs::read_dir("/proc") {
+        Ok(entries) => entries,
+        Err(error) => {
+            error!("Error reading /proc: {}", error);
+            return HashMap::new();
+        }
+    };
+    entries
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse::<i32>().ok())
+        .filter_map(|pid| {
+            let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+            Some((Pid::from_raw(pid), parse_ppid(stat.as_str())?))
+        })
+        .collect()
+}
+
+/// Parses the parent pid from the content of /proc/<pid>/stat.
+/// The format is: "pid (comm) state ppid ...", where comm might contain spaces and parenthesis.
+fn parse_ppid(stat: &str) -> Option<Pid> {
+    let after_comm = &stat[stat.rfind(')')? + 1..];
+    after_comm
+        .split_whitespace()
+        .nth(1)?
+        .parse::<i32>()
+        .ok()
+        .map(Pid::from_raw)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::horust::bus::Bus;
+    use crate::horust::formats::{Event, ExitStatus};
+    use crate::horust::reaper::{parse_ppid, read_ppids, Repo};
+    use nix::unistd::{getpid, getppid, Pid};
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    #[test]
+    fn test_parse_ppid() {
+        assert_eq!(
+            parse_ppid("1234 (sleep) S 1000 1234 1234 0 -1"),
+            Some(Pid::from_raw(1000))
+        );
+        assert_eq!(
+            parse_ppid("1234 (my (weird) proc) S 42 1234 1234 0 -1"),
+            Some(Pid::from_raw(42))
+        );
+        assert_eq!(parse_ppid("garbage"), None);
+        assert_eq!(read_ppids().get(&getpid()), Some(&getppid()));
+    }
+
+    #[test]
+    fn test_update_descendants() {
+        let mut bus = Bus::new();
+        let mut repo = Repo::new(bus.join_bus());
+        let pid = Pid::from_raw;
+        repo.pids_map.insert(pid(10), "a".into());
+        // 10 -> 11 -> 12; 20 is unrelated.
+        let mut ppids: HashMap<Pid, Pid> = vec![(10, 1), (11, 10), (12, 11), (20, 1)]
+            .into_iter()
+            .map(|(p, pp)| (pid(p), pid(pp)))
+            .collect();
+        repo.update_descendants(&ppids);
+        assert_eq!(repo.descendants.len(), 2);
+        assert_eq!(repo.descendants.get(&pid(12)), Some(&"a".to_string()));
+
+        // 11 exits, so 12 is re-parented to horust:
+        ppids.remove(&pid(11));
+        ppids.insert(pid(12), pid(1));
+        repo.update_descendants(&ppids);
+        assert_eq!(repo.descendants.len(), 1);
+        assert!(repo.reap_descendant(&pid(12), ExitStatus::Exited(3)));
+        assert!(!repo.reap_descendant(&pid(20), ExitStatus::Exited(0)));
+
+        // Announced once it's been reaped, e.g. read from an exec-wait-pid-file:
+        let observer = bus.join_bus();
+        repo.consume(Event::new_pid_changed("a".into(), pid(12)));
+        bus.drain(Duration::from_secs(1));
+        assert_eq!(
+            observer.try_get_events(),
+            vec![Event::new_service_exited("a".into(), ExitStatus::Exited(3))]
+        );
+        assert!(!repo.pids_map.contains_key(&pid(12)));
+    }
+}
@@ -1,17 +1,33 @@
 use crate::horust::bus::BusConnector;
 use crate::horust::error::Result;
 use crate::horust::formats::{
-    Event, FailureStrategy, RestartStrategy, Service, ServiceHandler, ServiceName, ServiceStatus,
+    changes_impact, find_program, parse_coredump_filter, read_env_file, ChangeImpact, ConfigSource,
+    Event, ExitStatus, FailureStrategy, HorustConfig, KillMode, Namespace, Service, ServiceHandler,
+    ServiceName, ServiceStatus, ServiceType, StartLimitAction, StopTimeoutAction,
+    TerminationSignal,
 };
-use crate::horust::{healthcheck, signal_handling};
-use nix::sys::signal::{self, Signal};
+use crate::horust::pipes::{Pipes, Stdio};
+use crate::horust::systemd_activation::Activation;
+use crate::horust::{capabilities, healthcheck, logging, pid_file, signal_handling};
+use nix::errno::Errno;
+use nix::fcntl::OFlag;
+use nix::mount::{mount, MsFlags};
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::sched::{unshare, CloneFlags};
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify};
+use nix::sys::signal::{self, SigHandler, SigSet, SigmaskHow, Signal};
 use nix::unistd::{fork, getppid, ForkResult};
 use nix::unistd::{getpid, Pid};
 use shlex;
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
 use std::ffi::{CStr, CString};
 use std::fmt::Debug;
+use std::net::{TcpStream, ToSocketAddrs};
 use std::ops::{Add, Mul};
-use std::path::PathBuf;
+use std::os::unix::ffi::OsStringExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
 use std::thread;
 use std::time::{Duration, Instant};
 
@@ -19,6 +35,20 @@ use std::time::{Duration, Instant};
 pub struct Runtime {
     is_shutting_down: bool,
     repo: Repo,
+    horust_config: HorustConfig,
+    /// Where `horust_config` is reloaded from, on the `horust-config-reload-signal`.
+    config_source: Option<ConfigSource>,
+    /// True once every service has been started, or won't ever be. Used for the fail-fast check.
+    is_startup_over: bool,
+    /// True once the non idle services have been started: from then on, the idle ones can be started too.
+    can_start_idle: bool,
+    /// Horust's exit code.
+    exit_code: i32,
+    /// The service whose exit code is used as Horust's exit code.
+    exit_code_of: Option<ServiceName>,
+    /// Services which are going to be removed, once they have finished.
+    to_be_removed: HashSet<ServiceName>,
+    pipes: Pipes,
 }
 
 #[derive(Debug, Clone)]
@@ -37,7 +67,7 @@ impl Repo {
     /// Returns true, if the repository is in a state for which fuhrer state transitions can be triggered
     /// only by external events.
     fn should_block(&self) -> bool {
-        let triggering_states = vec![
+        let triggering_states = [
             ServiceStatus::Running,
             ServiceStatus::Finished,
             ServiceStatus::FinishedFailed,
@@ -53,9 +83,9 @@ impl Repo {
         self.bus.try_get_events()
     }
 
-    /// Blocking
+    /// Blocking, but it wakes up every second: signals are not delivered through the bus.
     fn get_events_blocking(&mut self) -> Vec<Event> {
-        vec![self.bus.get_events_blocking()]
+        self.bus.get_events_timeout(Duration::from_secs(1))
     }
 
     pub fn all_finished(&self) -> bool {
@@ -64,6 +94,10 @@ impl Repo {
             .all(|sh| sh.is_finished() || sh.is_finished_failed())
     }
 
+    fn contains(&self, service_name: &ServiceName) -> bool {
+        self.services.iter().any(|sh| sh.name() == service_name)
+    }
+
     pub fn get_mut_service(&mut self, service_name: &ServiceName) -> &mut ServiceHandler {
         self.services
             .iter_mut()
@@ -71,11 +105,15 @@ impl Repo {
             .last()
             .unwrap()
     }
-    /// Get all the services that have specifed "start-after = [`service_name`]" in their config
+    /// Get all the services that have specifed "start-after = [`service_name`]"
+    /// (or "depends-on-healthy") in their config
     fn get_dependents(&self, service_name: &ServiceName) -> Vec<ServiceName> {
         self.services
             .iter()
-            .filter(|sh| sh.service().start_after.contains(service_name))
+            .filter(|sh| {
+                sh.start_after().contains(service_name)
+                    || sh.depends_on_healthy().contains(service_name)
+            })
             .map(|sh| sh.name())
             .cloned()
             .collect()
@@ -104,37 +142,254 @@ impl Repo {
         }
         let is_started = |service_name: &ServiceName| {
             self.services.iter().any(|service| {
-                service.name() == service_name && (service.is_running() || service.is_finished())
+                service.name() == service_name
+                    && (service.is_running() || service.is_finished_successfully())
             })
         };
-        sh.start_after().iter().all(is_started)
+        let is_healthy = |service_name: &ServiceName| {
+            self.services.iter().any(|service| {
+                service.name() == service_name
+                    && service.has_been_healthy
+                    && (service.is_running() || service.is_finished_successfully())
+            })
+        };
+        sh.start_after().iter().all(is_started) && sh.depends_on_healthy().iter().all(is_healthy)
+    }
+
+    /// A service is blocked if it's waiting on a service which won't ever be started.
+    /// `depth` guards against dependency cycles.
+    fn is_blocked(&self, sh: &ServiceHandler, depth: usize) -> bool {
+        if !sh.is_initial() || depth > self.services.len() {
+            return false;
+        }
+        // A removed dependency won't be started either.
+        let is_dep_blocked = |service_name: &ServiceName, needs_healthy: bool| match self
+            .services
+            .iter()
+            .find(|dep| dep.name() == service_name)
+        {
+            Some(dep) => {
+                dep.is_finished_failed()
+                    || (dep.is_finished() && !dep.is_finished_successfully())
+                    || (needs_healthy && dep.is_finished() && !dep.has_been_healthy)
+                    || self.is_blocked(dep, depth + 1)
+            }
+            None => true,
+        };
+        sh.start_after()
+            .iter()
+            .any(|service_name| is_dep_blocked(service_name, false))
+            || sh
+                .depends_on_healthy()
+                .iter()
+                .any(|service_name| is_dep_blocked(service_name, true))
+    }
+
+    /// True once every non idle service has been started, or is waiting for its dependencies
+    /// (which could be idle services themselves).
+    fn is_non_idle_startup_over(&self) -> bool {
+        self.services
+            .iter()
+            .filter(|sh| sh.service().service_type != ServiceType::Idle)
+            .all(|sh| {
+                sh.is_running()
+                    || sh.is_finished()
+                    || sh.is_finished_failed()
+                    || (sh.is_initial() && !self.is_service_runnable(sh))
+            })
+    }
+
+    /// The startup phase is over once every service is either running, in a final state, or blocked.
+    fn is_startup_over(&self) -> bool {
+        self.services.iter().all(|sh| {
+            sh.is_running() || sh.is_finished() || sh.is_finished_failed() || self.is_blocked(sh, 0)
+        })
     }
 }
 
 // Spawns and runs this component in a new thread.
-pub fn spawn(bus: BusConnector, services: Vec<Service>) {
-    thread::spawn(move || Runtime::new(bus, services).run());
+/// With `exit_code_of`, Horust exits with the exit code of that service.
+pub fn spawn(
+    bus: BusConnector,
+    services: Vec<Service>,
+    horust_config: HorustConfig,
+    config_source: Option<ConfigSource>,
+    exit_code_of: Option<ServiceName>,
+) {
+    thread::spawn(move || {
+        let mut runtime = Runtime::new(bus, services, horust_config);
+        runtime.config_source = config_source;
+        if let Some(service_name) = exit_code_of {
+            runtime.exit_with_code_of(service_name);
+        }
+        runtime.run()
+    });
 }
 
 impl Runtime {
-    fn new(bus: BusConnector, services: Vec<Service>) -> Self {
+    fn new(bus: BusConnector, services: Vec<Service>, horust_config: HorustConfig) -> Self {
+        let pipes = Pipes::new(&services);
         let repo = Repo::new(bus, services);
         Self {
+            pipes,
             repo,
             is_shutting_down: false,
+            horust_config,
+            config_source: None,
+            is_startup_over: false,
+            can_start_idle: false,
+            exit_code: 0,
+            to_be_removed: HashSet::new(),
+            exit_code_of: None,
+        }
+    }
+
+    /// Exit with the exit code of this service, or with 1 if it doesn't exit on its own.
+    fn exit_with_code_of(&mut self, service_name: ServiceName) {
+        self.exit_code = 1;
+        self.exit_code_of = Some(service_name);
+    }
+
+    /// Re-reads Horust's config, and applies the options which can change at runtime.
+    fn reload_config(&mut self) {
+        let source = match self.config_source.as_ref() {
+            Some(source) => source,
+            None => {
+                warn!("Horust's config isn't read from a file, there is nothing to reload.");
+                return;
+            }
+        };
+        let reloaded = match source.load() {
+            Ok(reloaded) => reloaded,
+            Err(error) => {
+                error!(
+                    "Failed reloading Horust's config from {}, keeping the current one: {}",
+                    source.path.display(),
+                    error
+                );
+                return;
+            }
+        };
+        if let Err(error) = logging::reload(&reloaded) {
+            error!(
+                "Failed reloading Horust's config, keeping the current one: {}",
+                error
+            );
+            return;
         }
+        // Only the informative fields can be ignored, for the others it's worth a warning:
+        changed_services(&self.horust_config.services, &reloaded.services)
+            .into_iter()
+            .for_each(|(service_name, impact)| {
+                let needs = match impact {
+                    ChangeImpact::None => return,
+                    ChangeImpact::Reconfigure => "reconfiguring",
+                    ChangeImpact::Restart => "restarting",
+                };
+                warn!(
+                    target: service_log_target(&service_name).as_str(),
+                    "Service: {} has changed and needs {} it, ignored until Horust is restarted.",
+                    service_name, needs
+                )
+            });
+        let deferred = self.horust_config.reload(reloaded);
+        if !deferred.is_empty() {
+            warn!(
+                "Changed options which require a restart of Horust, ignored until then: {}",
+                deferred.join(", ")
+            );
+        }
+        info!("Reloaded Horust's config from {}.", source.path.display());
+    }
+
+    /// Checked until the end of the startup phase: returns true if at that point,
+    /// any service has reached the FinishedFailed state.
+    fn has_startup_failed(&mut self) -> bool {
+        if self.is_startup_over || !self.repo.is_startup_over() {
+            return false;
+        }
+        self.is_startup_over = true;
+        let failed: Vec<&ServiceName> = self
+            .repo
+            .services
+            .iter()
+            .filter(|sh| sh.is_finished_failed())
+            .map(|sh| sh.name())
+            .collect();
+        failed.iter().for_each(|service_name| {
+            error!(
+                target: service_log_target(service_name).as_str(),
+                "Service: {} has failed to start.",
+                service_name
+            )
+        });
+        !failed.is_empty()
+    }
+
+    /// The idle services wait only for the first startup pass: they aren't held by later restarts.
+    fn update_can_start_idle(&mut self) {
+        if !self.can_start_idle && self.repo.is_non_idle_startup_over() {
+            debug!("The startup of the non idle services is over.");
+            self.can_start_idle = true;
+        }
+    }
+
+    /// Forgets about the removed services which have finished.
+    fn remove_finished_services(&mut self) {
+        let to_be_removed = &mut self.to_be_removed;
+        let pipes = &self.pipes;
+        self.repo.services.retain(|sh| {
+            let is_removed =
+                to_be_removed.contains(sh.name()) && (sh.is_finished() || sh.is_finished_failed());
+            if is_removed {
+                info!(
+                    target: service_log_target(sh.name()).as_str(),
+                    "Service: {} has been removed.",
+                    sh.name()
+                );
+                to_be_removed.remove(sh.name());
+                pipes.remove(sh.name());
+            }
+            !is_removed
+        });
     }
 
     // Apply side effects
     fn apply_event(&mut self, ev: Event) {
+        match &ev {
+            Event::PidChanged(service_name, _)
+            | Event::StatusChanged(service_name, _)
+            | Event::ServiceExited(service_name, _)
+            | Event::ForceKill(service_name)
+            | Event::TerminationStep(service_name, _)
+            | Event::ServiceHealthy(service_name)
+            | Event::RemoveService(service_name) => {
+                if !self.repo.contains(service_name) {
+                    debug!("Ignoring event for an unknown service: {:?}", ev);
+                    return;
+                }
+                debug!(
+                    target: service_log_target(service_name).as_str(),
+                    "Applying event: {:?}",
+                    ev
+                );
+            }
+            _ => (),
+        }
         match ev {
             Event::StatusChanged(service_name, status) => {
                 let service_handler = self.repo.get_mut_service(&service_name);
                 match status {
                     ServiceStatus::ToBeKilled => {
-                        if service_handler.status == ServiceStatus::Initial {
+                        if [ServiceStatus::Initial, ServiceStatus::StartLimitHit]
+                            .contains(&service_handler.status)
+                        {
                             service_handler.status = ServiceStatus::Finished;
-                        } else if vec![
+                            self.repo.send_ev(Event::new_status_changed(
+                                &service_name,
+                                ServiceStatus::Finished,
+                            ));
+                        } else if [
                             ServiceStatus::Running,
                             ServiceStatus::Starting,
                             ServiceStatus::ToBeRun,
@@ -143,12 +398,30 @@ impl Runtime {
                         {
                             service_handler.status = ServiceStatus::ToBeKilled;
                             service_handler.shutting_down_start = Some(Instant::now());
-                            kill(
-                                service_handler,
-                                service_handler.service().termination.signal.as_signal(),
+                            let termination = &service_handler.service().termination;
+                            let signal = match termination.sequence.first() {
+                                Some(step) => step.signal.as_signal(),
+                                None => termination.signal.as_signal(),
+                            };
+                            service_handler.termination_step = 1;
+                            service_handler.has_stop_timed_out = false;
+                            match &service_handler.service().termination.stop_command {
+                                Some(command) => spawn_stop_command(service_handler, command),
+                                None => kill(service_handler, signal),
+                            }
+                        } else if [ServiceStatus::ToBeKilled, ServiceStatus::InKilling]
+                            .contains(&service_handler.status)
+                        {
+                            // Sent again before the first one was applied: it's already stopping,
+                            // and the stop command shouldn't run twice.
+                            debug!(
+                                target: service_log_target(&service_name).as_str(),
+                                "Service: {} is already being killed.",
+                                service_name
                             );
                         } else {
                             error!(
+                                target: service_log_target(&service_name).as_str(),
                                 "Service ToBeKilled was in status: {}",
                                 service_handler.status
                             );
@@ -157,35 +430,52 @@ impl Runtime {
                     ServiceStatus::ToBeRun => {
                         if service_handler.status == ServiceStatus::Initial {
                             service_handler.status = ServiceStatus::ToBeRun;
+                            service_handler.started_at(Instant::now());
                             healthcheck::prepare_service(service_handler).unwrap();
                             let backoff = service_handler
                                 .service()
                                 .restart
                                 .backoff
-                                .mul(service_handler.restart_attempts.clone());
+                                .mul(service_handler.restart_attempts);
                             run_spawning_thread(
                                 service_handler.service().clone(),
                                 backoff,
                                 self.repo.clone(),
+                                self.pipes.clone(),
                             );
                         } else {
-                            debug!("{}: Ignoring ToBeRun event", service_name);
+                            debug!(
+                                target: service_log_target(&service_name).as_str(),
+                                "{}: Ignoring ToBeRun event",
+                                service_name
+                            );
                         }
                     }
                     ServiceStatus::Running => {
                         if service_handler.status == ServiceStatus::Starting {
                             service_handler.status = ServiceStatus::Running;
-                            service_handler.restart_attempts = 0;
+                            if !service_handler.has_min_uptime() {
+                                service_handler.restart_attempts = 0;
+                            }
                         }
                     }
                     ServiceStatus::Starting => {
                         if service_handler.status != ServiceStatus::InKilling {
                             service_handler.status = ServiceStatus::Starting;
-                            service_handler.restart_attempts = 0;
+                            if !service_handler.has_min_uptime() {
+                                service_handler.restart_attempts = 0;
+                            }
+                        }
+                    }
+                    ServiceStatus::InKilling => {
+                        // The service might have exited in the meanwhile:
+                        if service_handler.status == ServiceStatus::ToBeKilled {
+                            service_handler.status = ServiceStatus::InKilling;
                         }
                     }
                     unhandled_status => {
                         debug!(
+                            target: service_log_target(&service_name).as_str(),
                             "Unhandled status, setting: {}, {}",
                             service_name, unhandled_status
                         );
@@ -193,28 +483,105 @@ impl Runtime {
                     }
                 }
             }
-            Event::ServiceExited(service_name, exit_code) => {
+            Event::ServiceExited(service_name, exit_status) => {
+                let service_handler = self.repo.get_mut_service(&service_name);
+                if let Some(pid) = service_handler.take_pid_from_file(exit_status) {
+                    info!(
+                        target: service_log_target(&service_name).as_str(),
+                        "Service: {}, the wrapper has exited: tracking pid {} instead.",
+                        service_name, pid
+                    );
+                    self.repo.send_ev(Event::new_pid_changed(service_name, pid));
+                    return;
+                }
+                if self.exit_code_of.as_ref() == Some(&service_name) {
+                    eprintln!(
+                        "Service: {} exited with code: {}",
+                        service_name,
+                        exit_status.code()
+                    );
+                    self.exit_code = exit_status.code();
+                }
+                let is_shutting_down = self.is_shutting_down;
                 let service_handler = self.repo.get_mut_service(&service_name);
+                if service_handler.status == ServiceStatus::FinishedFailed {
+                    // It has been force killed, and it's already over:
+                    service_handler.pid = None;
+                    service_handler.last_exit_status = Some(exit_status);
+                    return;
+                }
+                let is_stopping = [ServiceStatus::ToBeKilled, ServiceStatus::InKilling]
+                    .contains(&service_handler.status);
+                if is_stopping && exit_status.is_signaled() {
+                    service_handler.last_exit_status = Some(exit_status);
+                    if service_handler.has_stop_timed_out {
+                        // Aborted, or killed while Horust was waiting for it: it's over as if
+                        // it had been force killed.
+                        if let Some(pid) = service_handler.pid.take() {
+                            kill_process_group(pid, Signal::SIGKILL);
+                        }
+                        service_handler.status = ServiceStatus::FinishedFailed;
+                        self.repo.send_ev(Event::new_status_changed(
+                            &service_name,
+                            ServiceStatus::FinishedFailed,
+                        ));
+                    } else {
+                        // Killed by the termination signal: the rest of its process group (if
+                        // any) is still killed once the termination wait is over.
+                        debug!(
+                            target: service_log_target(&service_name).as_str(),
+                            "Service: {} has been stopped, {}.",
+                            service_name, exit_status
+                        );
+                    }
+                    return;
+                }
+                // While shutting down, don't leave around any of its children.
+                if let (true, Some(pid)) = (is_shutting_down, service_handler.pid) {
+                    kill_process_group(pid, Signal::SIGKILL);
+                }
                 service_handler.shutting_down_start = None;
                 service_handler.pid = None;
+                service_handler.is_tracking_pid_file = false;
+                service_handler.last_exit_status = Some(exit_status);
 
-                let has_failed = !service_handler
-                    .service()
-                    .failure
-                    .successful_exit_code
-                    .contains(&exit_code);
-                if has_failed {
+                let service = service_handler.service();
+                let (has_failed, is_ignored) = match exit_status {
+                    ExitStatus::Exited(exit_code) => (
+                        !service.failure.successful_exit_code.contains(&exit_code),
+                        service.restart.ignore_exit_codes.contains(&exit_code),
+                    ),
+                    ExitStatus::Signaled(..) => (true, false),
+                };
+                // With min-uptime-for-reset, the attempts are reset once it has run long enough:
+                if service_handler.has_min_uptime() && !service_handler.is_fast_exit() {
+                    service_handler.restart_attempts = 0;
+                }
+                if has_failed && is_ignored {
+                    info!(
+                        target: service_log_target(service_handler.name()).as_str(),
+                        "Service: {} exited with {}, which is ignored.",
+                        service_handler.name(),
+                        exit_status
+                    );
+                    service_handler.status = ServiceStatus::Success;
+                } else if has_failed {
                     error!(
-                        "Service: {} has failed, exit code: {}",
+                        target: service_log_target(service_handler.name()).as_str(),
+                        "Service: {} has failed, {}",
                         service_handler.name(),
-                        exit_code
+                        exit_status
                     );
 
-                    // If it has failed too quickly, increase service_handler's restart attempts
-                    // and check if it has more attempts left.
-                    if vec![ServiceStatus::Starting, ServiceStatus::Initial]
-                        .contains(&service_handler.status)
-                    {
+                    // If it has failed too quickly (while starting, or before min-uptime-for-reset),
+                    // increase service_handler's restart attempts and check if it has more attempts left.
+                    let is_too_quick = if service_handler.has_min_uptime() {
+                        service_handler.is_fast_exit()
+                    } else {
+                        [ServiceStatus::Starting, ServiceStatus::Initial]
+                            .contains(&service_handler.status)
+                    };
+                    if is_too_quick {
                         service_handler.restart_attempts += 1;
                         if service_handler.restart_attempts_are_over() {
                             service_handler.status = ServiceStatus::Failed;
@@ -227,30 +594,158 @@ impl Runtime {
                     }
                 } else {
                     info!(
-                        "Service: {} successfully exited with: {}.",
+                        target: service_log_target(service_handler.name()).as_str(),
+                        "Service: {} successfully exited with {}.",
                         service_handler.name(),
-                        exit_code
+                        exit_status
                     );
                     service_handler.status = ServiceStatus::Success;
                 }
-                debug!("New state for exited service: {:?}", service_handler.status);
+                debug!(
+                    target: service_log_target(service_handler.name()).as_str(),
+                    "New state for exited service: {:?}",
+                    service_handler.status
+                );
+            }
+            Event::ServiceHealthy(service_name) => {
+                self.repo.get_mut_service(&service_name).has_been_healthy = true;
+            }
+            Event::TerminationStep(service_name, step) => {
+                let service_handler = self.repo.get_mut_service(&service_name);
+                let signal = service_handler
+                    .service()
+                    .termination
+                    .sequence
+                    .get(step)
+                    .map(|step| step.signal.as_signal());
+                if let (ServiceStatus::InKilling, Some(signal)) = (&service_handler.status, signal)
+                {
+                    if service_handler.termination_step <= step {
+                        service_handler.termination_step = step + 1;
+                        kill(service_handler, signal);
+                    }
+                }
             }
             Event::ForceKill(service_name) => {
                 let service_handler = self.repo.get_mut_service(&service_name);
-                kill(&service_handler, Signal::SIGKILL);
-                service_handler.status = ServiceStatus::FinishedFailed;
+                let action = service_handler.service().termination.stop_timeout_action;
+                match (action, service_handler.has_stop_timed_out) {
+                    (StopTimeoutAction::Abort, false) => {
+                        warn!(
+                            target: service_log_target(&service_name).as_str(),
+                            "Service: {} hasn't stopped in time, aborting it.",
+                            service_name
+                        );
+                        kill(service_handler, Signal::SIGABRT);
+                        service_handler.has_stop_timed_out = true;
+                        // Force killed if it's still running after another termination wait:
+                        service_handler.shutting_down_start = Some(Instant::now());
+                    }
+                    (StopTimeoutAction::Warn, false) => {
+                        warn!(
+                            target: service_log_target(&service_name).as_str(),
+                            "Service: {} hasn't stopped in time, still waiting for it to exit.",
+                            service_name
+                        );
+                        service_handler.has_stop_timed_out = true;
+                    }
+                    (StopTimeoutAction::Warn, true) => (),
+                    _ => {
+                        if let Some(pid) = service_handler.pid {
+                            kill_process_group(pid, Signal::SIGKILL);
+                        }
+                        service_handler.status = ServiceStatus::FinishedFailed;
+                        // Set directly, so it's announced for the ones tracking the statuses:
+                        self.repo.send_ev(Event::new_status_changed(
+                            &service_name,
+                            ServiceStatus::FinishedFailed,
+                        ));
+                    }
+                }
             }
             Event::PidChanged(service_name, pid) => {
                 let service_handler = self.repo.get_mut_service(&service_name);
                 service_handler.pid = Some(pid);
+                service_handler.last_exit_status = None;
+                service_handler.last_spawn = Some(Instant::now());
             }
             Event::ShuttingDownInitiated => self.is_shutting_down = true,
+            // Sent by the runtime itself, as its last event:
+            Event::Exiting(_) => (),
+            Event::SignalReceived(signal) => {
+                let targets: Vec<&ServiceHandler> = self
+                    .repo
+                    .services
+                    .iter()
+                    .filter(|sh| sh.service().forward_signals.contains(&signal))
+                    .filter(|sh| {
+                        [ServiceStatus::Starting, ServiceStatus::Running].contains(&sh.status)
+                    })
+                    .collect();
+                if targets.is_empty() {
+                    debug!("No service to forward the signal {:?} to.", signal);
+                }
+                targets
+                    .into_iter()
+                    .for_each(|sh| kill(sh, signal.as_signal()));
+            }
+            Event::AddService(service) => {
+                if self.repo.contains(&service.name) {
+                    error!(
+                        target: service_log_target(&service.name).as_str(),
+                        "Service: {} already exists, not adding it.",
+                        service.name
+                    );
+                } else {
+                    info!(
+                        target: service_log_target(&service.name).as_str(),
+                        "Adding service: {}",
+                        service.name
+                    );
+                    self.pipes.add(&service);
+                    self.repo.services.push((*service).into());
+                }
+            }
+            Event::RemoveService(service_name) => {
+                info!(
+                    target: service_log_target(&service_name).as_str(),
+                    "Removing service: {}",
+                    service_name
+                );
+                self.repo
+                    .get_dependents(&service_name)
+                    .iter()
+                    .for_each(|dependent| {
+                        warn!(
+                            target: service_log_target(dependent).as_str(),
+                            "Service: {} should start after {}, which is being removed.",
+                            dependent, service_name
+                        )
+                    });
+                self.to_be_removed.insert(service_name.clone());
+                let status = self.repo.get_mut_service(&service_name).status.clone();
+                if [
+                    ServiceStatus::Initial,
+                    ServiceStatus::ToBeRun,
+                    ServiceStatus::Starting,
+                    ServiceStatus::Running,
+                ]
+                .contains(&status)
+                {
+                    self.apply_event(Event::new_status_changed(
+                        &service_name,
+                        ServiceStatus::ToBeKilled,
+                    ));
+                }
+            }
         }
     }
 
     /// Compute next state for each sh
     pub fn next(&self, service_handler: &ServiceHandler) -> Vec<Event> {
-        if self.repo.is_service_runnable(&service_handler) {
+        let is_held =
+            service_handler.service().service_type == ServiceType::Idle && !self.can_start_idle;
+        if self.repo.is_service_runnable(service_handler) && !is_held {
             if self.is_shutting_down {
                 vec![Event::new_status_changed(
                     service_handler.name(),
@@ -275,15 +770,16 @@ impl Runtime {
                     }
                 }
                 ServiceStatus::Success => {
-                    let service_ev = handle_restart_strategy(service_handler, false);
+                    let service_ev = if self.to_be_removed.contains(service_handler.name()) {
+                        Event::new_status_changed(service_handler.name(), ServiceStatus::Finished)
+                    } else {
+                        handle_restart_strategy(service_handler, false)
+                    };
                     vec![service_ev]
                 }
                 ServiceStatus::Failed => {
-                    let attempts_are_over = service_handler.restart_attempts
-                        > service_handler.service().restart.attempts;
-
                     let mut failure_evs = handle_failure_strategy(
-                        self.repo.get_dependents(service_handler.name().into()),
+                        self.repo.get_dependents(service_handler.name()),
                         service_handler,
                     );
                     let other_services_termination = self
@@ -291,10 +787,12 @@ impl Runtime {
                         .get_die_if_failed(service_handler.name())
                         .into_iter()
                         .map(|sh_name| {
-                            Event::new_status_changed(&sh_name, ServiceStatus::ToBeKilled)
+                            Event::new_status_changed(sh_name, ServiceStatus::ToBeKilled)
                         });
 
-                    let service_ev = if !attempts_are_over {
+                    let service_ev = if service_handler.restart_attempts_are_over()
+                        || self.to_be_removed.contains(service_handler.name())
+                    {
                         Event::new_status_changed(
                             service_handler.name(),
                             ServiceStatus::FinishedFailed,
@@ -307,8 +805,37 @@ impl Runtime {
                     failure_evs.extend(other_services_termination);
                     failure_evs
                 }
+                ServiceStatus::StartLimitHit => {
+                    if self.is_shutting_down {
+                        vec![Event::new_status_changed(
+                            service_handler.name(),
+                            ServiceStatus::Finished,
+                        )]
+                    } else if service_handler.is_start_limit_over() {
+                        info!(
+                            target: service_log_target(service_handler.name()).as_str(),
+                            "Service: {} start limit interval is over, restarting it.",
+                            service_handler.name()
+                        );
+                        vec![Event::new_status_changed(
+                            service_handler.name(),
+                            ServiceStatus::Initial,
+                        )]
+                    } else {
+                        vec![]
+                    }
+                }
+                ServiceStatus::InKilling if service_handler.is_stop_timeout_ignored() => vec![],
+                ServiceStatus::InKilling
+                    if !service_handler.service().termination.sequence.is_empty() =>
+                {
+                    next_termination_step(service_handler).into_iter().collect()
+                }
                 ServiceStatus::InKilling => {
-                    if should_force_kill(service_handler) {
+                    if should_force_kill(
+                        service_handler,
+                        self.horust_config.default_termination_wait,
+                    ) {
                         vec![Event::new_force_kill(service_handler.name())]
                     } else {
                         vec![]
@@ -336,6 +863,51 @@ impl Runtime {
         }
     }
 
+    /// Moves the ToBeRun events at the end, in starting order: higher priority first,
+    /// then fewer dependencies first, then by name.
+    fn prioritize(&self, events: Vec<Event>) -> Vec<Event> {
+        let (mut to_be_run, mut events): (Vec<Event>, Vec<Event>) =
+            events.into_iter().partition(is_to_be_run);
+        let start_order = |ev: &Event| match ev {
+            Event::StatusChanged(service_name, _) => self
+                .repo
+                .services
+                .iter()
+                .find(|sh| sh.name() == service_name)
+                .map(|sh| {
+                    (
+                        Reverse(sh.service().priority),
+                        sh.start_after().len() + sh.depends_on_healthy().len(),
+                        service_name.clone(),
+                    )
+                }),
+            _ => None,
+        };
+        to_be_run.sort_by_cached_key(start_order);
+        events.extend(to_be_run);
+        events
+    }
+
+    /// Keeps the first `startup_burst_limit` ToBeRun events, which should be sorted by `prioritize`.
+    /// The dropped ones will be emitted again in the next iterations.
+    fn limit_startup_burst(&self, events: Vec<Event>) -> Vec<Event> {
+        let limit = match self.horust_config.startup_burst_limit {
+            Some(limit) => limit,
+            None => return events,
+        };
+        let (mut to_be_run, mut events): (Vec<Event>, Vec<Event>) =
+            events.into_iter().partition(is_to_be_run);
+        if to_be_run.len() > limit {
+            debug!(
+                "Startup burst limit reached, delaying: {:?}",
+                &to_be_run[limit..]
+            );
+            to_be_run.truncate(limit);
+        }
+        events.extend(to_be_run);
+        events
+    }
+
     /// Blocking call. Tries to move state machines forward
     pub fn run(&mut self) {
         loop {
@@ -351,16 +923,30 @@ impl Runtime {
             if signal_handling::is_sigterm_received() && !self.is_shutting_down {
                 self.repo.send_ev(Event::ShuttingDownInitiated);
             }
+            signal_handling::take_forwardable_signals()
+                .into_iter()
+                .for_each(|signal| self.repo.send_ev(Event::SignalReceived(signal)));
+            if signal_handling::take_config_reload() {
+                self.reload_config();
+            }
 
             events.into_iter().for_each(|ev| self.apply_event(ev));
+            self.remove_finished_services();
+
+            if self.horust_config.fail_fast && !self.is_shutting_down && self.has_startup_failed() {
+                error!("Fail fast is enabled, going to shut down all the services.");
+                self.exit_code = 1;
+                self.repo.send_ev(Event::ShuttingDownInitiated);
+            }
+            self.update_can_start_idle();
 
             let events: Vec<Event> = self
                 .repo
                 .services
                 .iter()
-                .map(|sh| self.next(sh))
-                .flatten()
+                .flat_map(|sh| self.next(sh))
                 .collect();
+            let events = self.limit_startup_burst(self.prioritize(events));
             debug!("Going to emit events: {:?}", events);
             events.into_iter().for_each(|ev| self.repo.send_ev(ev));
             // TODO: apply some clever check and exit if no service will never be started again.
@@ -370,44 +956,107 @@ impl Runtime {
             }
             thread::sleep(Duration::from_millis(200));
         }
-        std::process::exit(0);
+        healthcheck::cleanup();
+        if let Some(path) = self.horust_config.pid_file.as_ref() {
+            pid_file::remove(path);
+        }
+        self.repo.send_ev(Event::Exiting(self.exit_code));
     }
 }
 
-fn should_force_kill(service_handler: &ServiceHandler) -> bool {
-    if let Some(shutting_down_elapsed_secs) = service_handler.shutting_down_start.clone() {
-        let shutting_down_elapsed_secs = shutting_down_elapsed_secs.elapsed().as_secs();
+/// The services defined in both `old` and `new` which differ, with what's needed for applying
+/// their changes.
+fn changed_services(old: &[Service], new: &[Service]) -> Vec<(ServiceName, ChangeImpact)> {
+    old.iter()
+        .filter_map(|old| {
+            let new = new.iter().find(|new| new.name == old.name)?;
+            let changes = Service::diff(old, new);
+            (!changes.is_empty()).then(|| (old.name.clone(), changes_impact(&changes)))
+        })
+        .collect()
+}
+
+/// The log target of the messages about a service, e.g. `horust::runtime::my_service` for `my-service.toml`.
+/// It can be used in Horust's `log-levels`.
+pub(crate) fn service_log_target(service_name: &str) -> String {
+    let name = service_name.trim_end_matches(".toml");
+    let name: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    format!("horust::runtime::{}", name)
+}
+
+fn is_to_be_run(ev: &Event) -> bool {
+    matches!(ev, Event::StatusChanged(_, ServiceStatus::ToBeRun))
+}
+
+fn should_force_kill(service_handler: &ServiceHandler, default_wait: Option<Duration>) -> bool {
+    if let Some(shutting_down_start) = service_handler.shutting_down_start {
+        let shutting_down_elapsed = shutting_down_start.elapsed();
+        let termination_wait = service_handler.service().termination.wait_or(default_wait);
         debug!(
-            "{}, should not force kill. Elapsed: {}, termination wait: {}",
+            target: service_log_target(service_handler.name()).as_str(),
+            "{}, should not force kill. Elapsed: {:?}, termination wait: {:?}",
             service_handler.name(),
-            shutting_down_elapsed_secs,
-            service_handler.service().termination.wait.clone().as_secs()
+            shutting_down_elapsed,
+            termination_wait
         );
-        shutting_down_elapsed_secs > service_handler.service().termination.wait.clone().as_secs()
+        shutting_down_elapsed > termination_wait
     } else {
         error!("There is no shutting down elapsed secs!!");
         false
     }
 }
 
+/// Once the wait of the current step is over, starts the next step of the termination sequence.
+/// After the last step (or instead of a KILL step) the service is force killed.
+fn next_termination_step(service_handler: &ServiceHandler) -> Option<Event> {
+    let elapsed = service_handler.shutting_down_start?.elapsed();
+    let sequence = &service_handler.service().termination.sequence;
+    let started = service_handler.termination_step;
+    let current_step_end: Duration = sequence.iter().take(started).map(|step| step.wait).sum();
+    if elapsed < current_step_end {
+        return None;
+    }
+    match sequence.get(started) {
+        Some(step) if step.signal != TerminationSignal::KILL => Some(Event::TerminationStep(
+            service_handler.name().clone(),
+            started,
+        )),
+        _ => Some(Event::new_force_kill(service_handler.name())),
+    }
+}
+
 fn handle_restart_strategy(service_handler: &ServiceHandler, is_failed: bool) -> Event {
     let new_status = |status| Event::new_status_changed(service_handler.name(), status);
-    let ev = match service_handler.service().restart.strategy {
-        RestartStrategy::Never => {
-            if is_failed {
-                new_status(ServiceStatus::FinishedFailed)
-            } else {
-                new_status(ServiceStatus::Finished)
-            }
-        }
-        RestartStrategy::OnFailure => {
-            if is_failed {
-                new_status(ServiceStatus::Initial)
-            } else {
-                new_status(ServiceStatus::Finished)
-            }
+    let restart = &service_handler.service().restart;
+    let is_abnormal = service_handler
+        .last_exit_status
+        .is_some_and(|exit_status| exit_status.is_signaled());
+    let should_restart = if is_failed && is_abnormal {
+        restart.restarts_on_abnormal()
+    } else if is_failed {
+        restart.restarts_on_failure()
+    } else {
+        restart.restarts_on_success()
+    };
+    let ev = if should_restart && service_handler.is_start_limit_hit() {
+        warn!(
+            target: service_log_target(service_handler.name()).as_str(),
+            "Service: {} has been started too often, not restarting it.",
+            service_handler.name()
+        );
+        match restart.start_limit_action {
+            StartLimitAction::Restart => new_status(ServiceStatus::StartLimitHit),
+            StartLimitAction::None => new_status(ServiceStatus::FinishedFailed),
         }
-        RestartStrategy::Always => new_status(ServiceStatus::Initial),
+    } else if should_restart {
+        new_status(ServiceStatus::Initial)
+    } else if is_failed {
+        new_status(ServiceStatus::FinishedFailed)
+    } else {
+        new_status(ServiceStatus::Finished)
     };
     debug!("Restart strategy applied, ev: {:?}", ev);
     ev
@@ -418,7 +1067,10 @@ fn handle_failure_strategy(deps: Vec<ServiceName>, failed_sh: &ServiceHandler) -
     match failed_sh.service().failure.strategy {
         FailureStrategy::Shutdown => vec![Event::ShuttingDownInitiated],
         FailureStrategy::KillDependents => {
-            debug!("Failed service has kill-dependents strategy, going to mark them all..");
+            debug!(
+                target: service_log_target(failed_sh.name()).as_str(),
+                "Failed service has kill-dependents strategy, going to mark them all.."
+            );
             deps.iter()
                 .map(|sh| Event::new_status_changed(sh, ServiceStatus::ToBeKilled))
                 .collect()
@@ -428,39 +1080,121 @@ fn handle_failure_strategy(deps: Vec<ServiceName>, failed_sh: &ServiceHandler) -
 }
 
 fn kill(sh: &ServiceHandler, signal: Signal) {
-    debug!("Going to send {} signal to pid {:?}", signal, sh.pid());
-    if let Some(pid) = sh.pid() {
+    debug!(
+        target: service_log_target(sh.name()).as_str(),
+        "Going to send {} signal to pid {:?}",
+        signal,
+        sh.pid()
+    );
+    let kill_mode = sh.service().effective_kill_mode();
+    if kill_mode == KillMode::None {
+        debug!(
+            target: service_log_target(sh.name()).as_str(),
+            "Service: {} has kill-mode none, not sending {}",
+            sh.name(),
+            signal
+        );
+    } else if let (KillMode::ProcessGroup, Some(pid)) = (kill_mode, sh.pid()) {
+        // The pid read from an `exec-wait-pid-file` might not be the group's leader:
+        let pgid = match nix::unistd::getpgid(Some(pid)) {
+            Ok(pgid) if pgid != nix::unistd::getpgrp() => pgid,
+            _ => pid,
+        };
+        kill_process_group(pgid, signal);
+    } else if let Some(pid) = sh.pid() {
         if let Err(error) = signal::kill(pid, signal) {
             match error.as_errno().expect("errno empty!") {
                 nix::errno::Errno::ESRCH => (),
                 _ => error!(
+                    target: service_log_target(sh.name()).as_str(),
                     "Error killing the process: {}, service: {}, pid: {:?}",
                     error,
                     sh.name(),
-                    pid,
+                    pid
                 ),
             }
         }
     } else {
-        error!("Missing pid to kill but process was in running state.");
+        error!(
+            target: service_log_target(sh.name()).as_str(),
+            "Missing pid to kill but process was in running state."
+        );
+    }
+}
+
+/// Runs the `stop-command`, without waiting for it: the termination wait is applied as for a signal.
+fn spawn_stop_command(sh: &ServiceHandler, command: &str) {
+    debug!(
+        target: service_log_target(sh.name()).as_str(),
+        "Service: {}, running stop command: {}",
+        sh.name(),
+        command
+    );
+    let chunks = shlex::split(command).unwrap_or_default();
+    if let Some((program, args)) = chunks.split_first() {
+        let mut stop_command = std::process::Command::new(program);
+        stop_command.args(args);
+        if let Some(pid) = sh.pid() {
+            stop_command.env("MAINPID", pid.to_string());
+        }
+        if let Err(error) = stop_command.spawn() {
+            error!(
+                target: service_log_target(sh.name()).as_str(),
+                "Service: {}, failed running the stop command: {}",
+                sh.name(),
+                error
+            );
+        }
+    }
+}
+
+/// Services are session leaders (see `setsid` in `exec_service`), so their pid is also their process group id.
+fn kill_process_group(pid: Pid, signal: Signal) {
+    debug!("Going to send {} signal to process group {:?}", signal, pid);
+    if let Err(error) = signal::killpg(pid, signal) {
+        match error.as_errno().expect("errno empty!") {
+            nix::errno::Errno::ESRCH => (),
+            _ => error!(
+                "Error killing the process group: {}, pgid: {:?}",
+                error, pid,
+            ),
+        }
     }
 }
 
 /// Run another thread that will wait for the start delay, and handle the fork / exec.
-fn run_spawning_thread(service: Service, backoff: Duration, mut repo: Repo) {
+fn run_spawning_thread(service: Service, backoff: Duration, mut repo: Repo, pipes: Pipes) {
     std::thread::spawn(move || {
         // todo: we should wake up every second, in case someone wants to kill this process.
         std::thread::sleep(service.start_delay.add(backoff));
-        let evs = match spawn_process(&service) {
+        let waited = wait_for_port(&service).and_then(|_| wait_for_file(&service));
+        let mut service = inherit_env(service, &repo);
+        let spawned = waited
+            .and_then(|_| remove_pid_file(&service))
+            .and_then(|_| add_env_file(&mut service))
+            .and_then(|_| JoinedNamespaces::open(&service, &repo))
+            .and_then(|joined| {
+                let stdio = pipes.take_stdio(&service)?;
+                spawn_process(&service, stdio, joined)
+            });
+        let evs = match spawned {
             Ok(pid) => {
-                debug!("Setting pid:{} for service: {}", pid, service.name);
+                debug!(
+                    target: service_log_target(&service.name).as_str(),
+                    "Setting pid:{} for service: {}",
+                    pid, service.name
+                );
                 vec![
                     Event::new_pid_changed(service.name.clone(), pid),
                     Event::new_status_changed(&service.name, ServiceStatus::Starting),
                 ]
             }
             Err(error) => {
-                error!("Failed spawning the process: {}", error);
+                error!(
+                    target: service_log_target(&service.name).as_str(),
+                    "Failed spawning the process: {}",
+                    error
+                );
                 vec![Event::new_status_changed(
                     &service.name,
                     ServiceStatus::Failed,
@@ -471,32 +1205,730 @@ fn run_spawning_thread(service: Service, backoff: Duration, mut repo: Repo) {
     });
 }
 
-/// Fork the process
-fn spawn_process(service: &Service) -> Result<Pid> {
+/// Removes the `exec-wait-pid-file` of the previous run, which is not valid anymore.
+fn remove_pid_file(service: &Service) -> Result<()> {
+    if let Some(path) = service.exec_wait_pid_file.as_ref() {
+        if let Err(error) = std::fs::remove_file(path) {
+            if error.kind() != std::io::ErrorKind::NotFound {
+                return Err(error.into());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Blocking, until the service's `wait-for-port` accepts connections. Fails after its timeout.
+fn wait_for_port(service: &Service) -> Result<()> {
+    let wait = match service.wait_for_port.as_ref() {
+        Some(wait) => wait,
+        None => return Ok(()),
+    };
+    info!(
+        target: service_log_target(&service.name).as_str(),
+        "Service: {}, waiting for {}:{} to accept connections.",
+        service.name, wait.host, wait.port
+    );
+    let retry_interval = Duration::from_millis(500);
+    let deadline = Instant::now() + wait.timeout;
+    loop {
+        let is_open = (wait.host.as_str(), wait.port)
+            .to_socket_addrs()
+            .map(|mut addresses| {
+                addresses
+                    .any(|address| TcpStream::connect_timeout(&address, retry_interval).is_ok())
+            })
+            .unwrap_or(false);
+        if is_open {
+            debug!(
+                target: service_log_target(&service.name).as_str(),
+                "Service: {}, {}:{} is open.",
+                service.name, wait.host, wait.port
+            );
+            return Ok(());
+        }
+        if wait.timeout != Duration::from_secs(0) && Instant::now() >= deadline {
+            let message = format!(
+                "{}:{} didn't accept connections within {:?}",
+                wait.host, wait.port, wait.timeout
+            );
+            return Err(std::io::Error::new(std::io::ErrorKind::TimedOut, message).into());
+        }
+        std::thread::sleep(retry_interval);
+    }
+}
+
+/// Blocking, until the service's `wait-for-file` exists. Fails after its timeout.
+fn wait_for_file(service: &Service) -> Result<()> {
+    let wait = match service.wait_for_file.as_ref() {
+        Some(wait) => wait,
+        None => return Ok(()),
+    };
+    info!(
+        target: service_log_target(&service.name).as_str(),
+        "Service: {}, waiting for {} to exist.",
+        service.name,
+        wait.path.display()
+    );
+    let deadline = Instant::now() + wait.timeout;
+    // The watch is added before checking the file, so its creation can't be missed:
+    let inotify = watch_parent_dir(&wait.path)
+        .map_err(|error| {
+            debug!(
+                target: service_log_target(&service.name).as_str(),
+                "Can't watch {}: {}",
+                wait.path.display(),
+                error
+            )
+        })
+        .ok();
+    let waited = loop {
+        if wait.path.exists() {
+            break Ok(());
+        }
+        let now = Instant::now();
+        if wait.timeout != Duration::from_secs(0) && now >= deadline {
+            let message = format!(
+                "{} didn't exist within {:?}",
+                wait.path.display(),
+                wait.timeout
+            );
+            break Err(std::io::Error::new(std::io::ErrorKind::TimedOut, message).into());
+        }
+        match inotify {
+            Some(inotify) => {
+                // At most a second, in case the directory is replaced:
+                let timeout = if wait.timeout == Duration::from_secs(0) {
+                    Duration::from_secs(1)
+                } else {
+                    deadline
+                        .saturating_duration_since(now)
+                        .min(Duration::from_secs(1))
+                };
+                let mut fds = [PollFd::new(inotify.as_raw_fd(), PollFlags::POLLIN)];
+                if poll(&mut fds, timeout.as_millis() as i32).unwrap_or(0) > 0 {
+                    // Only for draining them: the file is checked again anyway.
+                    let _ = inotify.read_events();
+                }
+            }
+            None => std::thread::sleep(Duration::from_millis(100)),
+        }
+    };
+    if let Some(inotify) = inotify {
+        let _ = nix::unistd::close(inotify.as_raw_fd());
+    }
+    waited
+}
+
+/// An inotify instance, which is notified about the files created or moved into `path`'s directory.
+fn watch_parent_dir(path: &Path) -> nix::Result<Inotify> {
+    let parent = match path.parent() {
+        Some(parent) if parent != Path::new("") => parent,
+        _ => Path::new("."),
+    };
+    let inotify = Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC)?;
+    let flags = AddWatchFlags::IN_CREATE | AddWatchFlags::IN_MOVED_TO;
+    if let Err(error) = inotify.add_watch(parent, flags) {
+        let _ = nix::unistd::close(inotify.as_raw_fd());
+        return Err(error);
+    }
+    Ok(inotify)
+}
+
+/// Adds the `inherit-env-from` variables to the service's environment, reading them from the
+/// environment of the other service's process.
+fn inherit_env(mut service: Service, repo: &Repo) -> Service {
+    let environment = match service.environment.as_mut() {
+        Some(environment) => environment,
+        None => return service,
+    };
+    let inherit = match environment.inherit_env_from.clone() {
+        Some(inherit) => inherit,
+        None => return service,
+    };
+    let pid = repo
+        .services
+        .iter()
+        .find(|sh| *sh.name() == inherit.service)
+        .and_then(|sh| sh.pid());
+    let inherited = match pid.map(read_process_environment) {
+        Some(Ok(inherited)) => inherited,
+        Some(Err(error)) => {
+            warn!(
+                target: service_log_target(&service.name).as_str(),
+                "Service: {}, failed reading the environment of: {}: {}",
+                service.name, inherit.service, error
+            );
+            return service;
+        }
+        None => {
+            warn!(
+                target: service_log_target(&service.name).as_str(),
+                "Service: {}, {} isn't running: there's no environment to inherit.",
+                service.name, inherit.service
+            );
+            return service;
+        }
+    };
+    for var in &inherit.vars {
+        match inherited.get(var) {
+            Some(value) => {
+                environment.key_val.insert(var.clone(), value.clone());
+            }
+            None => warn!(
+                target: service_log_target(&service.name).as_str(),
+                "Service: {}, {} has no env variable: {}",
+                service.name, inherit.service, var
+            ),
+        }
+    }
+    service
+}
+
+/// Adds the variables of the `env-file` to the service's environment, unless they're already set.
+fn add_env_file(service: &mut Service) -> Result<()> {
+    let environment = match service.environment.as_mut() {
+        Some(environment) => environment,
+        None => return Ok(()),
+    };
+    if let Some(path) = environment.env_file.as_ref() {
+        for (key, value) in read_env_file(path, environment.env_file_format)? {
+            environment.key_val.entry(key).or_insert(value);
+        }
+    }
+    Ok(())
+}
+
+/// The environment of the process with this pid, as in `/proc/<pid>/environ`.
+fn read_process_environment(pid: Pid) -> std::io::Result<HashMap<String, String>> {
+    let content = std::fs::read(format!("/proc/{}/environ", pid))?;
+    let environment = content
+        .split(|byte| *byte == 0)
+        .filter_map(|key_val| {
+            let key_val = String::from_utf8_lossy(key_val);
+            let (key, value) = key_val.split_once('=')?;
+            Some((key.to_string(), value.to_string()))
+        })
+        .collect();
+    Ok(environment)
+}
+
+/// The namespaces of other services joined by a service (see `ipc-namespace`, `network-namespace`
+/// and `pid-namespace`).
+struct JoinedNamespaces {
+    /// Joined by the service's process.
+    namespaces: Vec<(std::fs::File, CloneFlags)>,
+    /// Joined before the fork, as only the children of the caller enter a pid namespace.
+    pid: Option<std::fs::File>,
+}
+
+impl JoinedNamespaces {
+    /// Opens the namespaces, in Horust: the other services should be running.
+    fn open(service: &Service, repo: &Repo) -> Result<Self> {
+        let joined = [
+            (&service.ipc_namespace, "ipc", CloneFlags::CLONE_NEWIPC),
+            (&service.network_namespace, "net", CloneFlags::CLONE_NEWNET),
+            (&service.pid_namespace, "pid", CloneFlags::CLONE_NEWPID),
+        ];
+        let mut namespaces = vec![];
+        let mut pid_namespace = None;
+        for (namespace, kind, flag) in joined.iter() {
+            let name = match namespace.service() {
+                Some(name) => name,
+                None => continue,
+            };
+            let pid = repo
+                .services
+                .iter()
+                .find(|sh| sh.name() == name)
+                .and_then(|sh| sh.pid())
+                .ok_or_else(|| {
+                    std::io::Error::other(format!(
+                        "{} isn't running: there's no {} namespace to join",
+                        name, kind
+                    ))
+                })?;
+            let file = std::fs::File::open(format!("/proc/{}/ns/{}", pid, kind))?;
+            if *flag == CloneFlags::CLONE_NEWPID {
+                pid_namespace = Some(file);
+            } else {
+                namespaces.push((file, *flag));
+            }
+        }
+        Ok(JoinedNamespaces {
+            namespaces,
+            pid: pid_namespace,
+        })
+    }
+
+    /// In the service's process.
+    fn join(&self) -> nix::Result<()> {
+        self.namespaces
+            .iter()
+            .try_for_each(|(file, flag)| nix::sched::setns(file.as_raw_fd(), *flag))
+    }
+}
+
+/// In the thread spawning the service, which is not used for anything else afterwards: its next
+/// child, the service, is created in the joined or in a new pid namespace.
+fn enter_pid_namespace(service: &Service, joined: &JoinedNamespaces) -> nix::Result<()> {
+    if let Some(file) = joined.pid.as_ref() {
+        nix::sched::setns(file.as_raw_fd(), CloneFlags::CLONE_NEWPID)?;
+    } else if service.pid_namespace == Namespace::Private {
+        unshare(CloneFlags::CLONE_NEWPID)?;
+    }
+    Ok(())
+}
+
+/// Fork the process. `stdio` is used by the child, and closed in the parent.
+fn spawn_process(service: &Service, stdio: Stdio, joined: JoinedNamespaces) -> Result<Pid> {
+    // Read before the fork, as the LISTEN_PID is Horust's:
+    let activation = if service.inherit_systemd_activation {
+        Activation::from_env()
+    } else {
+        None
+    };
+    let prepared = enter_pid_namespace(service, &joined).and_then(|_| {
+        if service.private_users {
+            UserNamespaceSync::new().map(Some)
+        } else {
+            Ok(None)
+        }
+    });
+    let user_namespace = match prepared {
+        Ok(user_namespace) => user_namespace,
+        Err(error) => {
+            stdio.close();
+            return Err(error.into());
+        }
+    };
     match fork() {
         Ok(ForkResult::Child) => {
-            debug!("Child PID: {}, PPID: {}.", getpid(), getppid());
-            exec_service(service);
+            debug!(
+                target: service_log_target(&service.name).as_str(),
+                "Child PID: {}, PPID: {}.",
+                getpid(),
+                getppid()
+            );
+            if let Some(user_namespace) = user_namespace {
+                user_namespace.unshare().expect("User namespace");
+            }
+            joined.join().expect("Join the namespaces");
+            exec_service(service, &stdio, activation.as_ref());
             unreachable!()
         }
         Ok(ForkResult::Parent { child, .. }) => {
-            debug!("Spawned child with PID {}.", child);
+            debug!(
+                target: service_log_target(&service.name).as_str(),
+                "Spawned child with PID {}.",
+                child
+            );
+            stdio.close();
+            if let Some(user_namespace) = user_namespace {
+                user_namespace.write_maps(child)?;
+            }
             Ok(child)
         }
-        Err(err) => Err(Into::into(err)),
+        Err(err) => {
+            stdio.close();
+            Err(Into::into(err))
+        }
+    }
+}
+
+/// The uids and gids of a `private-users` service, from 0, map to this range outside of its namespace.
+const PRIVATE_USERS_RANGE: (u32, u32) = (100_000, 65_536);
+
+/// The child's new user namespace can only map the whole range of ids if the parent writes them:
+/// the child waits for it before going on.
+struct UserNamespaceSync {
+    /// Written by the child after the `unshare`.
+    unshared: (RawFd, RawFd),
+    /// Written by the parent after the uid and gid maps.
+    mapped: (RawFd, RawFd),
+}
+
+impl UserNamespaceSync {
+    fn new() -> nix::Result<Self> {
+        let unshared = nix::unistd::pipe2(OFlag::O_CLOEXEC)?;
+        let mapped = nix::unistd::pipe2(OFlag::O_CLOEXEC).inspect_err(|_| {
+            let _ = nix::unistd::close(unshared.0);
+            let _ = nix::unistd::close(unshared.1);
+        })?;
+        Ok(UserNamespaceSync { unshared, mapped })
+    }
+
+    /// In the child: moves it into a new user namespace, and waits for its maps.
+    fn unshare(self) -> nix::Result<()> {
+        nix::unistd::close(self.unshared.0)?;
+        nix::unistd::close(self.mapped.1)?;
+        unshare(CloneFlags::CLONE_NEWUSER)?;
+        nix::unistd::write(self.unshared.1, &[0])?;
+        // Nothing is read if the parent failed writing the maps:
+        let read = nix::unistd::read(self.mapped.0, &mut [0])?;
+        nix::unistd::close(self.unshared.1)?;
+        nix::unistd::close(self.mapped.0)?;
+        if read == 0 {
+            return Err(nix::Error::Sys(Errno::EPERM));
+        }
+        Ok(())
+    }
+
+    /// In the parent: writes the maps of the `child`'s user namespace, once it's been created.
+    fn write_maps(self, child: Pid) -> Result<()> {
+        nix::unistd::close(self.unshared.1)?;
+        nix::unistd::close(self.mapped.0)?;
+        let res = self.try_write_maps(child);
+        nix::unistd::close(self.unshared.0)?;
+        nix::unistd::close(self.mapped.1)?;
+        res
+    }
+
+    fn try_write_maps(&self, child: Pid) -> Result<()> {
+        if nix::unistd::read(self.unshared.0, &mut [0])? == 0 {
+            return Err(
+                std::io::Error::other("the child failed creating its user namespace").into(),
+            );
+        }
+        let (start, len) = PRIVATE_USERS_RANGE;
+        let map = format!("0 {} {}\n", start, len);
+        std::fs::write(format!("/proc/{}/uid_map", child), map.as_str())?;
+        std::fs::write(format!("/proc/{}/gid_map", child), map.as_str())?;
+        nix::unistd::write(self.mapped.1, &[0])?;
+        Ok(())
+    }
+}
+
+/// Hidden by `protect-home`. The missing ones are skipped.
+const PROTECTED_HOME_PATHS: [&str; 3] = ["/home", "/root", "/run/user"];
+
+/// Moves the child into a new mount namespace, mounts a fresh tmpfs on every `tmpfs-paths`
+/// (and on /tmp and /run, if they're private), then the bind mounts, makes the `read-only-paths`
+/// (and the protected system) read only and hides the `inaccessible-paths` (and the protected home).
+fn setup_mount_namespace(service: &Service) -> nix::Result<()> {
+    unshare(CloneFlags::CLONE_NEWNS)?;
+    // Otherwise the mounts would be propagated back to the parent's namespace:
+    mount(
+        None::<&str>,
+        "/",
+        None::<&str>,
+        MsFlags::MS_REC | MsFlags::MS_PRIVATE,
+        None::<&str>,
+    )?;
+    if service.private_tmp {
+        debug!(target: service_log_target(&service.name).as_str(), "Mounting a private /tmp");
+        mount_private("/tmp", "mode=1777")?;
+    }
+    if service.private_run {
+        debug!(target: service_log_target(&service.name).as_str(), "Mounting a private /run");
+        mount_private("/run", "mode=755")?;
+    }
+    for path in &service.tmpfs_paths {
+        debug!(
+            target: service_log_target(&service.name).as_str(),
+            "Mounting tmpfs on: {}",
+            path.display()
+        );
+        mount(
+            Some("tmpfs"),
+            path,
+            Some("tmpfs"),
+            MsFlags::empty(),
+            None::<&str>,
+        )?;
+    }
+    for bind in &service.mounts.bind {
+        debug!(
+            target: service_log_target(&service.name).as_str(),
+            "Bind mounting: {} on {}",
+            bind.source.display(),
+            bind.destination.display()
+        );
+        mount(
+            Some(&bind.source),
+            &bind.destination,
+            None::<&str>,
+            MsFlags::MS_BIND,
+            None::<&str>,
+        )?;
+        if bind.read_only {
+            // The read only flag is ignored by the first bind mount, so it needs a remount:
+            mount(
+                None::<&str>,
+                &bind.destination,
+                None::<&str>,
+                MsFlags::MS_BIND | MsFlags::MS_REMOUNT | MsFlags::MS_RDONLY,
+                None::<&str>,
+            )?;
+        }
     }
+    let protected_system = service.protect_system.paths().iter().map(Path::new);
+    for path in protected_system.filter(|path| path.exists()) {
+        debug!(
+            target: service_log_target(&service.name).as_str(),
+            "Making read only: {}",
+            path.display()
+        );
+        mount_read_only(path)?;
+    }
+    for path in &service.read_only_paths {
+        debug!(
+            target: service_log_target(&service.name).as_str(),
+            "Making read only: {}",
+            path.display()
+        );
+        mount_read_only(path)?;
+    }
+    let protected_home = PROTECTED_HOME_PATHS.iter().map(Path::new);
+    let protected_home = protected_home.filter(|path| service.protect_home && path.exists());
+    for path in protected_home {
+        debug!(
+            target: service_log_target(&service.name).as_str(),
+            "Making inaccessible: {}",
+            path.display()
+        );
+        mount_inaccessible(path)?;
+    }
+    for path in &service.inaccessible_paths {
+        debug!(
+            target: service_log_target(&service.name).as_str(),
+            "Making inaccessible: {}",
+            path.display()
+        );
+        mount_inaccessible(path)?;
+    }
+    Ok(())
+}
+
+/// Moves the child into a new network namespace, and brings up its loopback interface:
+/// it's the only one there, and it starts down.
+fn setup_network_namespace() -> nix::Result<()> {
+    unshare(CloneFlags::CLONE_NEWNET)?;
+    let fd = Errno::result(unsafe {
+        libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0)
+    })?;
+    let mut request = IfReq::new("lo");
+    let res = Errno::result(unsafe { libc::ioctl(fd, libc::SIOCGIFFLAGS, &mut request) }).and_then(
+        |_| {
+            request.flags |= libc::IFF_UP as libc::c_short;
+            Errno::result(unsafe { libc::ioctl(fd, libc::SIOCSIFFLAGS, &request) })
+        },
+    );
+    nix::unistd::close(fd)?;
+    res.map(drop)
 }
 
-fn exec_service(service: &Service) {
+/// The `struct ifreq` used by the SIOCGIFFLAGS and SIOCSIFFLAGS ioctls.
+#[repr(C)]
+struct IfReq {
+    name: [libc::c_char; libc::IFNAMSIZ],
+    flags: libc::c_short,
+    /// The rest of the union, which is 24 bytes long.
+    padding: [u8; 22],
+}
+
+impl IfReq {
+    fn new(name: &str) -> Self {
+        let mut request = IfReq {
+            name: [0; libc::IFNAMSIZ],
+            flags: 0,
+            padding: [0; 22],
+        };
+        request
+            .name
+            .iter_mut()
+            .zip(name.bytes())
+            .for_each(|(dst, src)| *dst = src as libc::c_char);
+        request
+    }
+}
+
+/// Mounts an empty tmpfs on `path`, with the same permissions as systemd's private /tmp and /run.
+fn mount_private(path: &str, mode: &str) -> nix::Result<()> {
+    mount(
+        Some("tmpfs"),
+        path,
+        Some("tmpfs"),
+        MsFlags::MS_NOSUID | MsFlags::MS_NODEV,
+        Some(mode),
+    )
+}
+
+/// Covers a directory with an empty read only tmpfs. A tmpfs can't be mounted on a file,
+/// so files are covered with /dev/null.
+fn mount_inaccessible(path: &Path) -> nix::Result<()> {
+    if path.is_dir() {
+        mount(
+            Some("tmpfs"),
+            path,
+            Some("tmpfs"),
+            MsFlags::MS_RDONLY | MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC,
+            Some("mode=000"),
+        )
+    } else {
+        mount(
+            Some("/dev/null"),
+            path,
+            None::<&str>,
+            MsFlags::MS_BIND,
+            None::<&str>,
+        )
+    }
+}
+
+/// Bind mounts `path` on itself, with the mounts below it, and remounts it read only.
+fn mount_read_only(path: &Path) -> nix::Result<()> {
+    mount(
+        Some(path),
+        path,
+        None::<&str>,
+        MsFlags::MS_BIND | MsFlags::MS_REC,
+        None::<&str>,
+    )?;
+    mount(
+        None::<&str>,
+        path,
+        None::<&str>,
+        MsFlags::MS_BIND | MsFlags::MS_REMOUNT | MsFlags::MS_RDONLY,
+        None::<&str>,
+    )
+}
+
+/// Runs the command and waits for it, up to `timeout` (if not zero): the service isn't started if it
+/// fails. It gets the service's environment, but not its argv0 nor its search path.
+fn run_pre_exec_command(
+    command: &str,
+    environment: Vec<String>,
+    timeout: Duration,
+) -> std::io::Result<()> {
+    debug!("Running pre exec command: {}", command);
+    let error = |message: String| std::io::Error::other(message);
+    let chunks = shlex::split(command).unwrap_or_default();
+    let (program, args) = chunks
+        .split_first()
+        .ok_or_else(|| error(format!("Invalid pre exec command: {}", command)))?;
+    let environment = environment.iter().filter_map(|key_val| {
+        let mut key_val = key_val.splitn(2, '=');
+        Some((key_val.next()?, key_val.next()?))
+    });
+    let mut child = std::process::Command::new(program)
+        .args(args)
+        .env_clear()
+        .envs(environment)
+        .spawn()?;
+    let status = if timeout == Duration::from_secs(0) {
+        child.wait()?
+    } else {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if Instant::now() >= deadline {
+                child.kill()?;
+                child.wait()?;
+                return Err(error(format!(
+                    "{} has timed out after {:?}",
+                    command, timeout
+                )));
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    };
+    if !status.success() {
+        return Err(error(format!("{} has failed: {}", command, status)));
+    }
+    Ok(())
+}
+
+/// Closes every fd from `first_fd`, including the ones inherited by Horust without the close-on-exec flag.
+fn close_extra_fds(first_fd: RawFd) {
+    #[cfg(feature = "close-range")]
+    {
+        let res = unsafe { libc::syscall(libc::SYS_close_range, first_fd, libc::c_uint::MAX, 0) };
+        if res == 0 {
+            return;
+        }
+        // Probably an older kernel (ENOSYS), use the fallback below.
+    }
+    // The fds are collected first, because the directory's fd is in the list as well:
+    let fds: Vec<RawFd> = std::fs::read_dir("/proc/self/fd")
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+                .filter(|fd| *fd >= first_fd)
+                .collect()
+        })
+        .unwrap_or_default();
+    fds.into_iter().for_each(|fd| {
+        // The directory's fd is already closed:
+        let _ = nix::unistd::close(fd);
+    });
+}
+
+/// Unblocks all the signals, and restores the default action of SIGPIPE (ignored by Rust's runtime).
+/// The signals handled by Horust are restored by the exec.
+fn reset_signals() -> nix::Result<()> {
+    signal::sigprocmask(SigmaskHow::SIG_SETMASK, Some(&SigSet::empty()), None)?;
+    unsafe { signal::signal(Signal::SIGPIPE, SigHandler::SigDfl) }?;
+    Ok(())
+}
+
+fn exec_service(service: &Service, stdio: &Stdio, activation: Option<&Activation>) {
+    if let Some(stdin) = stdio.stdin {
+        nix::unistd::dup2(stdin, libc::STDIN_FILENO).expect("Set stdin");
+    }
+    if let Some(stdout) = stdio.stdout {
+        nix::unistd::dup2(stdout, libc::STDOUT_FILENO).expect("Set stdout");
+    }
+    if service.close_extra_fds {
+        close_extra_fds(activation.map_or(libc::STDERR_FILENO + 1, Activation::end_fd));
+    }
+    if let Some(activation) = activation {
+        activation.inherit().expect("Inherit the systemd's sockets");
+    }
+    if !service.inherit_signals {
+        reset_signals().expect("Reset signals");
+    }
     let default = PathBuf::from("/");
     let cwd = service.working_directory.as_ref().unwrap_or(&default);
-    debug!("Set cwd: {:?}, ", cwd);
+    debug!(target: service_log_target(&service.name).as_str(), "Set cwd: {:?}, ", cwd);
 
     std::env::set_current_dir(cwd).expect("Set cwd");
     nix::unistd::setsid().expect("Set sid");
+    if service.has_private_network() {
+        setup_network_namespace().expect("Network namespace");
+    }
+    if service.new_cgroup_namespace {
+        unshare(CloneFlags::CLONE_NEWCGROUP).expect("Cgroup namespace");
+    }
+    if service.ipc_namespace == Namespace::Private {
+        unshare(CloneFlags::CLONE_NEWIPC).expect("IPC namespace");
+    }
+    if service.has_mount_namespace() {
+        setup_mount_namespace(service).expect("Mount namespace");
+    }
+    if let Some(filter) = service
+        .coredump_filter
+        .as_deref()
+        .and_then(parse_coredump_filter)
+    {
+        std::fs::write("/proc/self/coredump_filter", format!("{:#x}", filter))
+            .expect("Set the coredump filter");
+    }
+    // Before the setuid, as it needs CAP_SETPCAP:
+    if let Some(capabilities) = &service.capabilities_bounding_set {
+        capabilities::drop_bounding_set(capabilities).expect("Drop the capabilities bounding set");
+    }
     nix::unistd::setuid(service.user.get_uid()).expect("setuid");
-    let chunks: Vec<String> = shlex::split(service.command.as_ref()).unwrap();
-    let program_name = CString::new(chunks.get(0).unwrap().as_str()).unwrap();
+    if let Some(command) = &service.pre_exec_command {
+        run_pre_exec_command(command, service.get_environment(), service.pre_exec_timeout)
+            .expect("Pre exec command");
+    }
+    let mut chunks: Vec<String> = service.command_args();
+    let program_name = CString::new(chunks.first().unwrap().as_str()).unwrap();
+    if let Some(argv0) = &service.argv0 {
+        chunks[0] = argv0.clone();
+    }
     let to_cstring = |s: Vec<String>| {
         s.into_iter()
             .map(|arg| CString::new(arg).map_err(Into::into))
@@ -506,20 +1938,201 @@ fn exec_service(service: &Service) {
     let arg_cstrings = to_cstring(chunks);
     let arg_cptr: Vec<&CStr> = arg_cstrings.iter().map(|c| c.as_c_str()).collect();
 
-    let env_cstrings = to_cstring(service.get_environment());
+    let mut environment = service.get_environment();
+    if let Some(activation) = activation {
+        environment.retain(|key_val| !key_val.starts_with("LISTEN_"));
+        environment.extend(activation.environment(getpid()));
+    }
+    let env_cstrings = to_cstring(environment);
     let env_cptr: Vec<&CStr> = env_cstrings.iter().map(|c| c.as_c_str()).collect();
 
     //arg_cstrings.insert(0, program_name.clone());
-    nix::unistd::execvpe(program_name.as_ref(), arg_cptr.as_ref(), env_cptr.as_ref())
-        .expect("Execvpe() failed: ");
+    let program = program_name.to_str().unwrap();
+    // The command-search-paths come first, then the exec-search-path (or the PATH, via execvpe):
+    let path = find_program(program, &service.command_search_paths).or_else(|| {
+        if service.exec_search_path.is_empty() {
+            return None;
+        }
+        let path = find_program(program, &service.exec_search_path)
+            .unwrap_or_else(|| panic!("{} not found in exec-search-path", program));
+        Some(path)
+    });
+    if let Some(path) = path {
+        let path = CString::new(path.into_os_string().into_vec()).unwrap();
+        let Err(error) = nix::unistd::execve(&path, arg_cptr.as_ref(), env_cptr.as_ref());
+        panic!("Execve() failed: {}", error);
+    }
+    let Err(error) =
+        nix::unistd::execvpe(program_name.as_ref(), arg_cptr.as_ref(), env_cptr.as_ref());
+    panic!("Execvpe() failed: {}", error);
 }
 
 #[cfg(test)]
 mod test {
-    use crate::horust::formats::{FailureStrategy, Service, ServiceHandler};
-    use crate::horust::runtime::{handle_failure_strategy, should_force_kill};
+    use crate::horust::bus::Bus;
+    use crate::horust::formats::{
+        ChangeImpact, Event, ExitStatus, FailureStrategy, HorustConfig, RestartStrategy, Service,
+        ServiceHandler, ServiceStatus, ServiceType, StartLimitAction, StopTimeoutAction,
+        TerminationSignal,
+    };
+    use crate::horust::runtime::{
+        changed_services, handle_failure_strategy, handle_restart_strategy, next_termination_step,
+        service_log_target, should_force_kill, wait_for_file, wait_for_port, Runtime,
+    };
+    use crate::horust::services_status::{self, ServicesStatus};
+    use nix::unistd::Pid;
+    use std::net::TcpListener;
     use std::ops::Sub;
-    use std::time::Duration;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_wait_for_port() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let service = Service::builder()
+            .name("a")
+            .command("/bin/true")
+            .wait_for_port("127.0.0.1", port, Duration::from_millis(100))
+            .build()
+            .unwrap();
+        wait_for_port(&service).unwrap();
+
+        drop(listener);
+        let start = Instant::now();
+        wait_for_port(&service).unwrap_err();
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn test_wait_for_file() {
+        let tempdir = TempDir::new("wait-for-file").unwrap();
+        let path = tempdir.path().join("ready");
+        let service = Service::builder()
+            .name("a")
+            .command("/bin/true")
+            .wait_for_file(&path, Duration::from_secs(5))
+            .build()
+            .unwrap();
+        let created = path.clone();
+        let creator = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(200));
+            std::fs::write(created, "").unwrap();
+        });
+        let start = Instant::now();
+        wait_for_file(&service).unwrap();
+        // Notified by inotify, without waiting for the next check:
+        assert!(start.elapsed() < Duration::from_secs(1));
+        creator.join().unwrap();
+
+        // Polled, as the directory doesn't exist:
+        let service = Service::builder()
+            .name("a")
+            .command("/bin/true")
+            .wait_for_file(
+                tempdir.path().join("missing/ready"),
+                Duration::from_millis(300),
+            )
+            .build()
+            .unwrap();
+        let start = Instant::now();
+        wait_for_file(&service).unwrap_err();
+        assert!(start.elapsed() >= Duration::from_millis(300));
+    }
+
+    #[test]
+    fn test_start_limit() {
+        let service = Service::builder()
+            .name("a")
+            .command("/bin/true")
+            .restart_on_failure(true)
+            .start_limit(2, Duration::from_secs(60), StartLimitAction::Restart)
+            .build()
+            .unwrap();
+        let status_changed = |status| Event::new_status_changed(&"a".into(), status);
+        let mut sh: ServiceHandler = service.into();
+        sh.started_at(Instant::now().sub(Duration::from_secs(120)));
+        sh.started_at(Instant::now());
+        // The first start is out of the interval:
+        assert!(!sh.is_start_limit_hit());
+        assert_eq!(
+            handle_restart_strategy(&sh, true),
+            status_changed(ServiceStatus::Initial)
+        );
+        sh.started_at(Instant::now());
+        assert!(sh.is_start_limit_hit());
+        assert!(!sh.is_start_limit_over());
+        assert_eq!(
+            handle_restart_strategy(&sh, true),
+            status_changed(ServiceStatus::StartLimitHit)
+        );
+
+        let mut service: Service = sh.into();
+        service.restart.start_limit_action = StartLimitAction::None;
+        let mut sh: ServiceHandler = service.into();
+        sh.started_at(Instant::now());
+        sh.started_at(Instant::now());
+        assert_eq!(
+            handle_restart_strategy(&sh, true),
+            status_changed(ServiceStatus::FinishedFailed)
+        );
+    }
+
+    #[test]
+    fn test_next_termination_step() {
+        let service = Service::builder()
+            .name("a")
+            .command("sleep 30")
+            .termination_step(TerminationSignal::TERM, Duration::from_secs(5))
+            .termination_step(TerminationSignal::QUIT, Duration::from_secs(2))
+            .termination_step(TerminationSignal::KILL, Duration::from_secs(0))
+            .build()
+            .unwrap();
+        let mut sh: ServiceHandler = service.into();
+        sh.shutting_down_started();
+        // The first step is started by the ToBeKilled event:
+        sh.termination_step = 1;
+        assert_eq!(next_termination_step(&sh), None);
+        sh.shutting_down_start = Some(sh.shutting_down_start.unwrap().sub(Duration::from_secs(6)));
+        assert_eq!(
+            next_termination_step(&sh),
+            Some(Event::TerminationStep("a".into(), 1))
+        );
+        sh.termination_step = 2;
+        assert_eq!(next_termination_step(&sh), None);
+        sh.shutting_down_start = Some(sh.shutting_down_start.unwrap().sub(Duration::from_secs(2)));
+        assert_eq!(
+            next_termination_step(&sh),
+            Some(Event::new_force_kill(&"a".into()))
+        );
+    }
+
+    #[test]
+    fn test_changed_services() {
+        let old = vec![Service::from_name("a"), Service::from_name("b")];
+        let mut new = old.clone();
+        new[0].description = Some("Only informative".into());
+        new[1].command = "/bin/false".into();
+        new.push(Service::from_name("c"));
+        assert_eq!(
+            changed_services(&old, &new),
+            vec![
+                ("a".to_string(), ChangeImpact::None),
+                ("b".to_string(), ChangeImpact::Restart)
+            ]
+        );
+        assert_eq!(changed_services(&old, &old), vec![]);
+    }
+
+    #[test]
+    fn test_service_log_target() {
+        assert_eq!(
+            service_log_target("my-service.toml"),
+            "horust::runtime::my_service"
+        );
+        assert_eq!(service_log_target("db"), "horust::runtime::db");
+    }
 
     #[test]
     fn test_should_force_kill() {
@@ -529,11 +2142,20 @@ wait = "10s"
 "#;
         let service: Service = toml::from_str(service).unwrap();
         let mut sh: ServiceHandler = service.into();
-        assert!(!should_force_kill(&sh));
+        assert!(!should_force_kill(&sh, None));
+        sh.shutting_down_started();
+        assert!(!should_force_kill(&sh, None));
+        sh.shutting_down_start = Some(sh.shutting_down_start.unwrap().sub(Duration::from_secs(20)));
+        assert!(should_force_kill(&sh, None));
+        // The service's own wait takes precedence:
+        assert!(should_force_kill(&sh, Some(Duration::from_secs(60))));
+
+        // Without it, the global default is used:
+        let mut sh: ServiceHandler = Service::from_name("a").into();
         sh.shutting_down_started();
-        assert!(!should_force_kill(&sh));
         sh.shutting_down_start = Some(sh.shutting_down_start.unwrap().sub(Duration::from_secs(20)));
-        assert!(should_force_kill(&sh))
+        assert!(!should_force_kill(&sh, Some(Duration::from_secs(60))));
+        assert!(should_force_kill(&sh, None));
     }
 
     #[test]
@@ -553,4 +2175,398 @@ wait = "10s"
         println!("evs: {:?}", evs);
         assert_eq!(evs.len(), 1);
     }
+
+    #[test]
+    fn test_add_service() {
+        let mut bus = Bus::new();
+        let mut runtime = Runtime::new(
+            bus.join_bus(),
+            vec![Service::from_name("a")],
+            Default::default(),
+        );
+        runtime.apply_event(Event::new_add_service(Service::start_after("b", vec!["a"])));
+        assert_eq!(runtime.repo.services.len(), 2);
+        // Already existing:
+        runtime.apply_event(Event::new_add_service(Service::from_name("b")));
+        assert_eq!(runtime.repo.services.len(), 2);
+
+        let b = &runtime.repo.services[1];
+        assert_eq!(b.status, ServiceStatus::Initial);
+        assert!(!runtime.repo.is_service_runnable(b));
+        runtime.repo.get_mut_service(&"a".to_string()).status = ServiceStatus::Running;
+        let b = &runtime.repo.services[1];
+        assert_eq!(
+            runtime.next(b),
+            vec![Event::new_status_changed(b.name(), ServiceStatus::ToBeRun)]
+        );
+    }
+
+    #[test]
+    fn test_remove_service() {
+        let mut bus = Bus::new();
+        let mut runtime = Runtime::new(
+            bus.join_bus(),
+            vec![
+                Service::from_name("a"),
+                Service::start_after("b", vec!["a"]),
+            ],
+            Default::default(),
+        );
+        let a = "a".to_string();
+        runtime.repo.get_mut_service(&a).status = ServiceStatus::Success;
+        runtime.apply_event(Event::RemoveService(a.clone()));
+        // It's not restarted:
+        let evs = runtime.next(&runtime.repo.services[0]);
+        assert_eq!(
+            evs,
+            vec![Event::new_status_changed(&a, ServiceStatus::Finished)]
+        );
+        runtime.remove_finished_services();
+        assert_eq!(runtime.repo.services.len(), 2);
+
+        evs.into_iter().for_each(|ev| runtime.apply_event(ev));
+        runtime.remove_finished_services();
+        assert_eq!(runtime.repo.services.len(), 1);
+        assert!(runtime.to_be_removed.is_empty());
+        // Late events are ignored, and b is blocked:
+        runtime.apply_event(Event::new_service_exited(a, ExitStatus::Exited(0)));
+        assert!(runtime.repo.is_startup_over());
+
+        // An initial service is just marked as finished:
+        let b = "b".to_string();
+        runtime.apply_event(Event::RemoveService(b));
+        runtime.remove_finished_services();
+        assert!(runtime.repo.services.is_empty());
+    }
+
+    #[test]
+    fn test_depends_on_healthy() {
+        let mut bus = Bus::new();
+        let mut b = Service::from_name("b");
+        b.depends_on_healthy = vec!["a".into()];
+        let mut runtime = Runtime::new(
+            bus.join_bus(),
+            vec![Service::from_name("a"), b],
+            Default::default(),
+        );
+        let a = "a".to_string();
+        runtime.repo.get_mut_service(&a).status = ServiceStatus::Running;
+        assert!(!runtime.repo.is_service_runnable(&runtime.repo.services[1]));
+        runtime.apply_event(Event::new_service_healthy(&a));
+        assert!(runtime.repo.is_service_runnable(&runtime.repo.services[1]));
+
+        // It won't ever start, if a finishes without being healthy:
+        let sh = runtime.repo.get_mut_service(&a);
+        sh.has_been_healthy = false;
+        sh.status = ServiceStatus::Finished;
+        assert!(runtime.repo.is_blocked(&runtime.repo.services[1], 0));
+    }
+
+    #[test]
+    fn test_min_uptime_for_reset() {
+        let mut bus = Bus::new();
+        let mut service = Service::from_name("a");
+        service.restart.attempts = 1;
+        service.restart.min_uptime_for_reset = Duration::from_secs(10);
+        let mut runtime = Runtime::new(bus.join_bus(), vec![service], Default::default());
+        let a = "a".to_string();
+        let crash = |runtime: &mut Runtime, uptime: Duration| {
+            runtime.apply_event(Event::new_pid_changed(a.clone(), Pid::from_raw(1)));
+            let sh = runtime.repo.get_mut_service(&a);
+            assert!(sh.last_spawn.unwrap().elapsed() < Duration::from_secs(1));
+            sh.status = ServiceStatus::Running;
+            sh.last_spawn = Some(Instant::now().sub(uptime));
+            runtime.apply_event(Event::ServiceExited(a.clone(), ExitStatus::Exited(1)));
+            let sh = runtime.repo.get_mut_service(&a);
+            (sh.status.clone(), sh.restart_attempts)
+        };
+        // Being running doesn't reset the attempts: only the uptime does.
+        assert_eq!(
+            crash(&mut runtime, Duration::from_secs(0)),
+            (ServiceStatus::Initial, 1)
+        );
+        assert_eq!(
+            crash(&mut runtime, Duration::from_secs(60)),
+            (ServiceStatus::Failed, 0)
+        );
+        crash(&mut runtime, Duration::from_secs(0));
+        assert_eq!(
+            crash(&mut runtime, Duration::from_secs(0)),
+            (ServiceStatus::Failed, 2)
+        );
+        assert!(runtime.repo.get_mut_service(&a).restart_attempts_are_over());
+    }
+
+    #[test]
+    fn test_start_after_exit_code() {
+        let mut bus = Bus::new();
+        let mut a = Service::from_name("a");
+        a.restart.ignore_exit_codes = vec![1];
+        a.failure.successful_exit_code = vec![0, 75];
+        let mut runtime = Runtime::new(
+            bus.join_bus(),
+            vec![a, Service::start_after("b", vec!["a"])],
+            Default::default(),
+        );
+        let a = "a".to_string();
+        runtime.repo.get_mut_service(&a).status = ServiceStatus::Running;
+        runtime.apply_event(Event::ServiceExited(a.clone(), ExitStatus::Exited(75)));
+        runtime.repo.get_mut_service(&a).status = ServiceStatus::Finished;
+        assert!(runtime.repo.is_service_runnable(&runtime.repo.services[1]));
+
+        // An ignored exit code isn't a success: b won't ever start.
+        runtime.repo.get_mut_service(&a).status = ServiceStatus::Running;
+        runtime.apply_event(Event::ServiceExited(a.clone(), ExitStatus::Exited(1)));
+        runtime.repo.get_mut_service(&a).status = ServiceStatus::Finished;
+        assert!(!runtime.repo.is_service_runnable(&runtime.repo.services[1]));
+        assert!(runtime.repo.is_blocked(&runtime.repo.services[1], 0));
+    }
+
+    #[test]
+    fn test_stop_timeout_action() {
+        let mut bus = Bus::new();
+        let mut a = Service::from_name("a");
+        a.termination.stop_timeout_action = StopTimeoutAction::Abort;
+        let mut b = Service::from_name("b");
+        b.termination.stop_timeout_action = StopTimeoutAction::Warn;
+        let mut runtime = Runtime::new(bus.join_bus(), vec![a, b], Default::default());
+        let (a, b) = ("a".to_string(), "b".to_string());
+        for name in [&a, &b] {
+            runtime.repo.get_mut_service(name).shutting_down_started();
+        }
+
+        // Aborted, then force killed after another termination wait:
+        runtime.apply_event(Event::new_force_kill(&a));
+        let sh = runtime.repo.get_mut_service(&a);
+        assert_eq!(sh.status, ServiceStatus::InKilling);
+        assert!(sh.has_stop_timed_out);
+        assert!(!should_force_kill(sh, None));
+        runtime.apply_event(Event::new_force_kill(&a));
+        assert_eq!(
+            runtime.repo.get_mut_service(&a).status,
+            ServiceStatus::FinishedFailed
+        );
+
+        // Horust waits for it, until it exits:
+        runtime.apply_event(Event::new_force_kill(&b));
+        let sh = runtime.repo.get_mut_service(&b).clone();
+        assert_eq!(sh.status, ServiceStatus::InKilling);
+        assert!(sh.is_stop_timeout_ignored());
+        assert_eq!(runtime.next(&sh), vec![]);
+        runtime.apply_event(Event::new_service_exited(
+            b.clone(),
+            ExitStatus::Signaled(9, false),
+        ));
+        assert_eq!(
+            runtime.repo.get_mut_service(&b).status,
+            ServiceStatus::FinishedFailed
+        );
+    }
+
+    #[test]
+    fn test_force_killed_status() {
+        let mut bus = Bus::new();
+        let mut runtime = Runtime::new(
+            bus.join_bus(),
+            vec![Service::from_name("a"), Service::from_name("b")],
+            Default::default(),
+        );
+        let services_status = Arc::new(ServicesStatus::default());
+        services_status::spawn(
+            bus.join_bus_auxiliary(services_status::filter),
+            Arc::clone(&services_status),
+        );
+        let (a, b) = ("a".to_string(), "b".to_string());
+        runtime.repo.get_mut_service(&a).shutting_down_started();
+        runtime.apply_event(Event::new_force_kill(&a));
+        // Never started, it's finished right away:
+        runtime.apply_event(Event::new_status_changed(&b, ServiceStatus::ToBeKilled));
+        assert!(bus.drain(Duration::from_secs(1)).is_empty());
+
+        let wait = Duration::from_secs(5);
+        assert_eq!(
+            services_status.wait_for(&a, wait, |status| *status == ServiceStatus::FinishedFailed),
+            Some(ServiceStatus::FinishedFailed)
+        );
+        assert_eq!(
+            services_status.wait_for(&b, wait, |status| *status == ServiceStatus::Finished),
+            Some(ServiceStatus::Finished)
+        );
+    }
+
+    #[test]
+    fn test_restart_on_abnormal() {
+        let mut bus = Bus::new();
+        let mut a = Service::from_name("a");
+        a.restart.strategy = RestartStrategy::OnAbnormal;
+        let mut runtime = Runtime::new(bus.join_bus(), vec![a], Default::default());
+        let a = "a".to_string();
+        let status_changed = |status| Event::new_status_changed(&"a".into(), status);
+        let mut exit = |status, exit_status| {
+            let sh = runtime.repo.get_mut_service(&a);
+            sh.status = status;
+            sh.pid = Some(Pid::from_raw(1234));
+            runtime.apply_event(Event::new_service_exited(a.clone(), exit_status));
+            runtime.repo.get_mut_service(&a).clone()
+        };
+
+        let sh = exit(ServiceStatus::Running, ExitStatus::Signaled(11, true));
+        assert_eq!(sh.status, ServiceStatus::Failed);
+        assert_eq!(
+            handle_restart_strategy(&sh, true),
+            status_changed(ServiceStatus::Initial)
+        );
+        let sh = exit(ServiceStatus::Running, ExitStatus::Exited(1));
+        assert_eq!(sh.status, ServiceStatus::Failed);
+        assert_eq!(
+            handle_restart_strategy(&sh, true),
+            status_changed(ServiceStatus::FinishedFailed)
+        );
+        // Stopped by Horust, it's force killed after the termination wait:
+        let sh = exit(ServiceStatus::InKilling, ExitStatus::Signaled(15, false));
+        assert_eq!(sh.status, ServiceStatus::InKilling);
+        assert_eq!(sh.pid, Some(Pid::from_raw(1234)));
+        // Reaped after being force killed:
+        let sh = exit(
+            ServiceStatus::FinishedFailed,
+            ExitStatus::Signaled(9, false),
+        );
+        assert_eq!(sh.status, ServiceStatus::FinishedFailed);
+        assert_eq!(sh.pid, None);
+    }
+
+    #[test]
+    fn test_idle() {
+        let mut bus = Bus::new();
+        let mut idle = Service::from_name("idle");
+        idle.service_type = ServiceType::Idle;
+        let mut b = Service::from_name("b");
+        b.start_after = vec!["a".into()];
+        let mut c = Service::from_name("c");
+        c.start_after = vec!["idle".into()];
+        let mut runtime = Runtime::new(
+            bus.join_bus(),
+            vec![Service::from_name("a"), b, idle, c],
+            Default::default(),
+        );
+        let a = "a".to_string();
+        runtime.update_can_start_idle();
+        assert!(runtime.next(&runtime.repo.services[2]).is_empty());
+
+        runtime.repo.get_mut_service(&a).status = ServiceStatus::Running;
+        runtime.update_can_start_idle();
+        // b can start now:
+        assert!(runtime.next(&runtime.repo.services[2]).is_empty());
+
+        runtime.repo.get_mut_service(&"b".to_string()).status = ServiceStatus::Running;
+        runtime.update_can_start_idle();
+        // c waits for the idle service:
+        assert_eq!(
+            runtime.next(&runtime.repo.services[2]),
+            vec![Event::new_status_changed(
+                &"idle".to_string(),
+                ServiceStatus::ToBeRun
+            )]
+        );
+
+        // A later restart doesn't hold it anymore:
+        runtime.repo.get_mut_service(&a).status = ServiceStatus::Initial;
+        runtime.update_can_start_idle();
+        assert!(!runtime.next(&runtime.repo.services[2]).is_empty());
+    }
+
+    #[test]
+    fn test_killed_twice() {
+        let mut bus = Bus::new();
+        let mut runtime = Runtime::new(
+            bus.join_bus(),
+            vec![Service::from_name("a")],
+            Default::default(),
+        );
+        let a = "a".to_string();
+        runtime.repo.get_mut_service(&a).status = ServiceStatus::Running;
+        runtime.apply_event(Event::new_status_changed(&a, ServiceStatus::ToBeKilled));
+        runtime.apply_event(Event::new_status_changed(&a, ServiceStatus::ToBeKilled));
+        assert_eq!(
+            runtime.repo.get_mut_service(&a).status,
+            ServiceStatus::ToBeKilled
+        );
+    }
+
+    #[test]
+    fn test_exited_while_killing() {
+        let mut bus = Bus::new();
+        let mut runtime = Runtime::new(
+            bus.join_bus(),
+            vec![Service::from_name("a")],
+            Default::default(),
+        );
+        let a = "a".to_string();
+        runtime.repo.get_mut_service(&a).status = ServiceStatus::ToBeKilled;
+        runtime.apply_event(Event::new_service_exited(a.clone(), ExitStatus::Exited(0)));
+        // A late InKilling shouldn't override the exit:
+        runtime.apply_event(Event::new_status_changed(&a, ServiceStatus::InKilling));
+        assert_eq!(
+            runtime.repo.get_mut_service(&a).status,
+            ServiceStatus::Success
+        );
+    }
+
+    #[test]
+    fn test_limit_startup_burst() {
+        let mut bus = Bus::new();
+        let services = vec![
+            Service::from_name("db"),
+            Service::start_after("a", vec!["db"]),
+            Service::from_name("b"),
+            Service::from_name("c"),
+        ];
+        let horust_config = HorustConfig {
+            startup_burst_limit: Some(2),
+            ..Default::default()
+        };
+        let runtime = Runtime::new(bus.join_bus(), services, horust_config);
+        let to_be_run =
+            |name: &str| Event::new_status_changed(&name.to_string(), ServiceStatus::ToBeRun);
+        let events = vec![
+            to_be_run("a"),
+            Event::ShuttingDownInitiated,
+            to_be_run("b"),
+            to_be_run("c"),
+        ];
+        assert_eq!(
+            runtime.limit_startup_burst(runtime.prioritize(events)),
+            vec![Event::ShuttingDownInitiated, to_be_run("b"), to_be_run("c")]
+        );
+    }
+
+    #[test]
+    fn test_prioritize() {
+        let mut bus = Bus::new();
+        let mut high = Service::from_name("z-high");
+        high.priority = 90;
+        let mut low = Service::from_name("a-low");
+        low.priority = 10;
+        let services = vec![low, Service::from_name("c"), high, Service::from_name("b")];
+        let runtime = Runtime::new(bus.join_bus(), services, Default::default());
+        let to_be_run =
+            |name: &str| Event::new_status_changed(&name.to_string(), ServiceStatus::ToBeRun);
+        let events = vec![
+            to_be_run("a-low"),
+            to_be_run("c"),
+            to_be_run("z-high"),
+            Event::ShuttingDownInitiated,
+            to_be_run("b"),
+        ];
+        assert_eq!(
+            runtime.prioritize(events),
+            vec![
+                Event::ShuttingDownInitiated,
+                to_be_run("z-high"),
+                to_be_run("b"),
+                to_be_run("c"),
+                to_be_run("a-low"),
+            ]
+        );
+    }
 }
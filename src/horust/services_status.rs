@@ -0,0 +1,110 @@
+use crate::horust::bus::BusConnector;
+use crate::horust::formats::{Event, ServiceName, ServiceStatus};
+use std::collections::HashMap;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// Keeps track of the last status of every service, as seen on the bus.
+/// It's used for answering queries coming from outside of the bus (e.g. `Horust::wait_for_service`).
+#[derive(Debug, Default)]
+pub(crate) struct ServicesStatus {
+    statuses: Mutex<HashMap<ServiceName, ServiceStatus>>,
+    changed: Condvar,
+}
+
+/// Spawns a thread which updates `services_status` with the events coming from the bus.
+pub(crate) fn spawn(bus: BusConnector, services_status: Arc<ServicesStatus>) {
+    std::thread::spawn(move || loop {
+        services_status.update(bus.get_events_blocking());
+    });
+}
+
+/// The events needed for tracking the statuses.
+pub(crate) fn filter(ev: &Event) -> bool {
+    matches!(ev, Event::StatusChanged(..))
+}
+
+impl ServicesStatus {
+    fn update(&self, ev: Event) {
+        if let Event::StatusChanged(service_name, status) = ev {
+            self.statuses.lock().unwrap().insert(service_name, status);
+            self.changed.notify_all();
+        }
+    }
+
+    /// Blocks until the status of `service_name` satisfies `predicate`, up to `timeout`.
+    /// Services which haven't changed their status yet are considered in the Initial status.
+    pub(crate) fn wait_for<F>(
+        &self,
+        service_name: &str,
+        timeout: Duration,
+        predicate: F,
+    ) -> Option<ServiceStatus>
+    where
+        F: Fn(&ServiceStatus) -> bool,
+    {
+        let deadline = Instant::now() + timeout;
+        let mut statuses = self.statuses.lock().unwrap();
+        loop {
+            let status = statuses
+                .get(service_name)
+                .cloned()
+                .unwrap_or(ServiceStatus::Initial);
+            if predicate(&status) {
+                return Some(status);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return None;
+            }
+            statuses = self
+                .changed
+                .wait_timeout(statuses, deadline - now)
+                .unwrap()
+                .0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::horust::formats::{Event, ServiceStatus};
+    use crate::horust::services_status::ServicesStatus;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn test_wait_for() {
+        let services_status = Arc::new(ServicesStatus::default());
+        let is_running = |status: &ServiceStatus| *status == ServiceStatus::Running;
+        let wait = Duration::from_millis(100);
+        assert_eq!(services_status.wait_for("a", wait, is_running), None);
+        assert_eq!(
+            services_status.wait_for("a", wait, |status| *status == ServiceStatus::Initial),
+            Some(ServiceStatus::Initial)
+        );
+
+        let updater = Arc::clone(&services_status);
+        let handle = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(100));
+            updater.update(Event::new_status_changed(
+                &"a".to_string(),
+                ServiceStatus::Starting,
+            ));
+            updater.update(Event::new_status_changed(
+                &"a".to_string(),
+                ServiceStatus::Running,
+            ));
+        });
+        assert_eq!(
+            services_status.wait_for("a", Duration::from_secs(5), is_running),
+            Some(ServiceStatus::Running)
+        );
+        handle.join().unwrap();
+        // Already running:
+        assert_eq!(
+            services_status.wait_for("a", wait, is_running),
+            Some(ServiceStatus::Running)
+        );
+    }
+}
@@ -1,12 +1,36 @@
-use nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, SIGINT, SIGTERM};
+use crate::horust::formats::TerminationSignal;
+use nix::sys::signal::{
+    sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal, SIGINT, SIGTERM, SIGUSR1, SIGUSR2,
+};
 use std::panic;
+use std::sync::atomic::{AtomicBool, Ordering};
 static mut SIGTERM_RECEIVED: bool = false;
+static SIGUSR1_RECEIVED: AtomicBool = AtomicBool::new(false);
+static SIGUSR2_RECEIVED: AtomicBool = AtomicBool::new(false);
+static CONFIG_RELOAD_RECEIVED: AtomicBool = AtomicBool::new(false);
 
 pub(crate) fn is_sigterm_received() -> bool {
     unsafe { SIGTERM_RECEIVED }
 }
 
-/// Signal safe print
+/// The signals to be forwarded (see `forward-signals`) received since the last call.
+pub(crate) fn take_forwardable_signals() -> Vec<TerminationSignal> {
+    [
+        (&SIGUSR1_RECEIVED, TerminationSignal::USR1),
+        (&SIGUSR2_RECEIVED, TerminationSignal::USR2),
+    ]
+    .iter()
+    .filter(|(received, _)| received.swap(false, Ordering::SeqCst))
+    .map(|(_, signal)| signal.clone())
+    .collect()
+}
+
+/// True if the `horust-config-reload-signal` has been received since the last call.
+pub(crate) fn take_config_reload() -> bool {
+    CONFIG_RELOAD_RECEIVED.swap(false, Ordering::SeqCst)
+}
+
+// Signal safe print
 /*fn print(s: &str) {
     unsafe {
         libc::write(STDOUT_FILENO, s.as_ptr() as *const c_void, s.len());
@@ -14,8 +38,8 @@ pub(crate) fn is_sigterm_received() -> bool {
 }
 */
 
-/// Setup the signal handlers
-pub(crate) fn init() {
+/// Setup the signal handlers. The `reload_signal` isn't forwarded, even if it's USR1 or USR2.
+pub(crate) fn init(reload_signal: Signal) {
     // To allow auto restart on some syscalls,
     // for example: `waitpid`.
     let flags = SaFlags::SA_RESTART;
@@ -27,6 +51,24 @@ pub(crate) fn init() {
     if let Err(err) = unsafe { sigaction(SIGINT, &sig_action) } {
         panic!("sigaction() failed: {}", err);
     };
+    let sig_action = SigAction::new(
+        SigHandler::Handler(handle_forwardable),
+        flags,
+        SigSet::empty(),
+    );
+    for signal in &[SIGUSR1, SIGUSR2] {
+        if let Err(err) = unsafe { sigaction(*signal, &sig_action) } {
+            panic!("sigaction() failed: {}", err);
+        };
+    }
+    let sig_action = SigAction::new(
+        SigHandler::Handler(handle_config_reload),
+        flags,
+        SigSet::empty(),
+    );
+    if let Err(err) = unsafe { sigaction(reload_signal, &sig_action) } {
+        panic!("sigaction() failed: {}", err);
+    };
 }
 
 extern "C" fn handle_sigterm(_signal: libc::c_int) {
@@ -36,3 +78,15 @@ extern "C" fn handle_sigterm(_signal: libc::c_int) {
         SIGTERM_RECEIVED = true;
     }
 }
+
+extern "C" fn handle_forwardable(signal: libc::c_int) {
+    if signal == libc::SIGUSR1 {
+        SIGUSR1_RECEIVED.store(true, Ordering::SeqCst);
+    } else if signal == libc::SIGUSR2 {
+        SIGUSR2_RECEIVED.store(true, Ordering::SeqCst);
+    }
+}
+
+extern "C" fn handle_config_reload(_signal: libc::c_int) {
+    CONFIG_RELOAD_RECEIVED.store(true, Ordering::SeqCst);
+}
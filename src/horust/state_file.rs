@@ -0,0 +1,95 @@
+use crate::horust::bus::BusConnector;
+use crate::horust::error::Result;
+use crate::horust::formats::{Event, ServiceName};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+/// What is kept across Horust's restarts: the services which have been intentionally stopped
+/// (see `Horust::remove_service`). They won't be started again, until they're added back.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct State {
+    #[serde(default)]
+    pub stopped: BTreeSet<ServiceName>,
+}
+
+impl State {
+    /// Returns true if the state has changed.
+    fn update(&mut self, ev: &Event) -> bool {
+        match ev {
+            Event::RemoveService(service_name) => self.stopped.insert(service_name.clone()),
+            Event::AddService(service) => self.stopped.remove(&service.name),
+            _ => false,
+        }
+    }
+}
+
+/// The events which can change the state.
+pub(crate) fn filter(ev: &Event) -> bool {
+    matches!(ev, Event::RemoveService(_) | Event::AddService(_))
+}
+
+/// Reads the state stored in `path`. A missing file is an empty state.
+pub(crate) fn load(path: &Path) -> Result<State> {
+    if !path.exists() {
+        debug!("State file: {} not found.", path.display());
+        return Ok(Default::default());
+    }
+    let content = std::fs::read_to_string(path)?;
+    let state = serde_json::from_str(content.as_str()).map_err(std::io::Error::from)?;
+    Ok(state)
+}
+
+/// The state is written in a temporary file and then renamed, so it's never partially written.
+fn save(path: &Path, state: &State) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let tmp_path = path.with_file_name(format!(
+        ".{}.tmp",
+        path.file_name().unwrap_or_default().to_string_lossy()
+    ));
+    let content = serde_json::to_string_pretty(state).map_err(std::io::Error::from)?;
+    std::fs::write(&tmp_path, content)?;
+    std::fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+/// Spawns a thread which updates the state file with the events coming from the bus.
+pub(crate) fn spawn(bus: BusConnector, path: PathBuf, mut state: State) {
+    std::thread::spawn(move || loop {
+        if state.update(&bus.get_events_blocking()) {
+            if let Err(error) = save(&path, &state) {
+                error!("Failed writing state file {}: {}", path.display(), error);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use crate::horust::error::Result;
+    use crate::horust::formats::{Event, Service};
+    use crate::horust::state_file::{load, save, State};
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_load_and_save() -> Result<()> {
+        let tempdir = TempDir::new("state-file")?;
+        let path = tempdir.path().join("run").join("state.json");
+        let mut state = load(&path)?;
+        assert_eq!(state, State::default());
+
+        assert!(state.update(&Event::RemoveService("a".into())));
+        assert!(!state.update(&Event::RemoveService("a".into())));
+        save(&path, &state)?;
+        assert_eq!(load(&path)?, state);
+        // Only the state file is left:
+        assert_eq!(std::fs::read_dir(path.parent().unwrap())?.count(), 1);
+
+        assert!(state.update(&Event::new_add_service(Service::from_name("a"))));
+        assert!(state.stopped.is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,103 @@
+use nix::fcntl::{fcntl, FcntlArg, FdFlag};
+use nix::unistd::{getpid, Pid};
+use std::os::unix::io::RawFd;
+
+/// The first fd passed by systemd, as in sd_listen_fds(3).
+const LISTEN_FDS_START: RawFd = 3;
+
+/// The sockets passed to Horust by systemd's socket activation.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Activation {
+    fds: RawFd,
+    names: Option<String>,
+}
+
+impl Activation {
+    /// The sockets passed to Horust, if `LISTEN_PID` is Horust's pid.
+    pub(crate) fn from_env() -> Option<Self> {
+        let var = |name: &str| std::env::var(name).ok();
+        Self::parse(
+            var("LISTEN_PID").as_deref(),
+            var("LISTEN_FDS").as_deref(),
+            var("LISTEN_FDNAMES"),
+            getpid(),
+        )
+    }
+
+    fn parse(
+        listen_pid: Option<&str>,
+        listen_fds: Option<&str>,
+        names: Option<String>,
+        pid: Pid,
+    ) -> Option<Self> {
+        let listen_pid: i32 = listen_pid?.parse().ok()?;
+        if listen_pid != pid.as_raw() {
+            return None;
+        }
+        let fds: RawFd = listen_fds?.parse().ok()?;
+        if fds <= 0 {
+            return None;
+        }
+        Some(Activation { fds, names })
+    }
+
+    /// The fd after the last passed socket.
+    pub(crate) fn end_fd(&self) -> RawFd {
+        LISTEN_FDS_START + self.fds
+    }
+
+    /// The environment of the service which inherits the sockets, whose pid is `pid`.
+    pub(crate) fn environment(&self, pid: Pid) -> Vec<String> {
+        let mut environment = vec![
+            format!("LISTEN_PID={}", pid),
+            format!("LISTEN_FDS={}", self.fds),
+        ];
+        if let Some(names) = &self.names {
+            environment.push(format!("LISTEN_FDNAMES={}", names));
+        }
+        environment
+    }
+
+    /// In the service's process, before the exec: the sockets are inherited by the service.
+    pub(crate) fn inherit(&self) -> nix::Result<()> {
+        (LISTEN_FDS_START..self.end_fd())
+            .try_for_each(|fd| fcntl(fd, FcntlArg::F_SETFD(FdFlag::empty())).map(drop))
+    }
+}
+
+/// Sets the close-on-exec flag on the sockets passed to Horust, so they are inherited only by the
+/// services with `inherit-systemd-activation`.
+pub(crate) fn init() {
+    if let Some(activation) = Activation::from_env() {
+        debug!("Received {} sockets from systemd.", activation.fds);
+        (LISTEN_FDS_START..activation.end_fd()).for_each(|fd| {
+            if let Err(error) = fcntl(fd, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC)) {
+                warn!("Failed setting close-on-exec on fd {}: {}", fd, error);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::horust::systemd_activation::Activation;
+    use nix::unistd::Pid;
+
+    #[test]
+    fn test_parse() {
+        let pid = Pid::from_raw(42);
+        let activation = Activation::parse(Some("42"), Some("2"), Some("web:admin".into()), pid);
+        let activation = activation.unwrap();
+        assert_eq!(activation.end_fd(), 5);
+        assert_eq!(
+            activation.environment(Pid::from_raw(7)),
+            vec!["LISTEN_PID=7", "LISTEN_FDS=2", "LISTEN_FDNAMES=web:admin"]
+        );
+
+        // Meant for another process:
+        assert_eq!(Activation::parse(Some("1"), Some("2"), None, pid), None);
+        assert_eq!(Activation::parse(None, Some("2"), None, pid), None);
+        assert_eq!(Activation::parse(Some("42"), Some("0"), None, pid), None);
+        assert_eq!(Activation::parse(Some("42"), Some("x"), None, pid), None);
+    }
+}
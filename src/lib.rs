@@ -2,4 +2,9 @@
 extern crate log;
 
 pub mod horust;
-pub use crate::horust::{get_sample_service, Horust, HorustError};
+pub use crate::horust::{
+    changes_impact, get_sample_service, init_logging, ChangeImpact, Documentation, EnvFileFormat,
+    FailureStrategy, Horust, HorustConfig, HorustError, KillMode, Namespace, ProtectSystem,
+    RestartStrategy, Service, ServiceBuilder, ServiceFieldChange, ServiceStatus, ServiceType,
+    StartLimitAction, StopTimeoutAction, TerminationSignal, TerminationStep, User,
+};
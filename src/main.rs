@@ -1,5 +1,6 @@
-use horust::Horust;
+use horust::{Horust, HorustConfig};
 use std::path::PathBuf;
+use structopt::clap::Shell;
 use structopt::StructOpt;
 
 #[macro_use]
@@ -11,32 +12,76 @@ extern crate log;
 struct Opts {
     #[structopt(long, default_value = "/etc/horust/horust.toml")]
     /// Horust's config.
-    config: String,
+    config: PathBuf,
+    #[structopt(flatten)]
+    horust_config: HorustConfig,
     #[structopt(long)]
     /// Prints a service file with all the possible options
     sample_service: bool,
+    #[structopt(long, possible_values = &Shell::variants(), case_insensitive = true)]
+    /// Prints the completion script for this shell, e.g. `horust --generate-completions bash > /etc/bash_completion.d/horust`.
+    generate_completions: Option<Shell>,
     #[structopt(long, default_value = "/etc/horust/services")]
-    /// Path to the directory containing the services
+    /// Path to the directory containing the services. More services can be defined in the
+    /// HORUST_SERVICE_0, HORUST_SERVICE_1, ... environment variables: their value is a service's toml,
+    /// or a percent-encoded one if it can't contain newlines (e.g. `command%20%3D%20%22%2Fbin%2Ftrue%22`).
     services_path: PathBuf,
+    #[structopt(long, conflicts_with = "command")]
+    /// Run only this service file, once, and exit with its exit code. Useful for testing a new service.
+    test_service: Option<PathBuf>,
+    #[structopt(subcommand)]
+    subcommand: Option<Subcommand>,
     #[structopt(required = false, multiple = true, min_values = 0, last = true)]
     /// Specify a command to run instead of load services path. Useful if you just want to use the reaping capability. Preceed it with --.
     command: Vec<String>,
 }
 
-fn main() -> Result<(), horust::HorustError> {
-    // Set up logging.
-    let env = env_logger::Env::new()
-        .filter("HORUST_LOG")
-        .write_style("HORUST_LOG_STYLE");
-    env_logger::init_from_env(env);
+#[derive(StructOpt, Debug)]
+enum Subcommand {
+    /// Prints the description and the documentation of a service, read from the services path.
+    Describe {
+        /// The service's name, e.g. `myservice.toml`.
+        service: String,
+    },
+}
 
+fn main() -> Result<(), horust::HorustError> {
     let opts = Opts::from_args();
 
     if opts.sample_service {
         println!("{}", horust::get_sample_service());
         return Ok(());
     }
-    let mut horust = if !opts.command.is_empty() {
+    if let Some(shell) = opts.generate_completions {
+        Opts::clap().gen_completions_to("horust", shell, &mut std::io::stdout());
+        return Ok(());
+    }
+    // The logging setup depends on Horust's config, so it's loaded first.
+    let horust_config = HorustConfig::load_and_merge(opts.horust_config.clone(), &opts.config)?;
+    horust::init_logging(&horust_config)?;
+
+    if let Some(Subcommand::Describe { service }) = opts.subcommand {
+        let horust = Horust::from_services_dir_and_config(&opts.services_path, horust_config)?;
+        let with_extension = format!("{}.toml", service);
+        return match horust
+            .services
+            .iter()
+            .find(|s| s.name == service || s.name == with_extension)
+        {
+            Some(service) => {
+                print!("{}", service.describe());
+                Ok(())
+            }
+            None => {
+                eprintln!("There is no service named: {}", service);
+                std::process::exit(1);
+            }
+        };
+    }
+    let mut horust = if let Some(path) = opts.test_service {
+        debug!("Going to test service: {}", path.display());
+        Horust::from_test_service(&path)?
+    } else if !opts.command.is_empty() {
         debug!("Going to run command: {:?}", opts.command);
 
         Horust::from_command(
@@ -49,9 +94,10 @@ fn main() -> Result<(), horust::HorustError> {
             "Going to load services from directory: {}",
             opts.services_path.display()
         );
-        Horust::from_services_dir(&opts.services_path)?
+        Horust::from_services_dir_and_config(&opts.services_path, horust_config.clone())?
     };
 
-    horust.run();
-    Ok(())
+    horust.set_horust_config(horust_config);
+    horust.set_config_file(opts.config, opts.horust_config);
+    horust.run()
 }
@@ -7,12 +7,16 @@ use predicates::prelude::*;
 use predicates::str::contains;
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
-use std;
+use std::io::Read;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixDatagram;
+use std::os::unix::process::CommandExt;
 use std::path::Path;
 use std::process::Command;
 use std::sync::mpsc;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tempdir::TempDir;
 
 /// Creates script and service file, and stores them in dir.
@@ -28,7 +32,7 @@ fn store_service(
         .sample_iter(&Alphanumeric)
         .take(5)
         .collect::<String>();
-    let service_name = format!("{}.toml", service_name.unwrap_or_else(|| rnd_name.as_str()));
+    let service_name = format!("{}.toml", service_name.unwrap_or(rnd_name.as_str()));
     let script_name = format!("{}.sh", rnd_name);
     let script_path = dir.join(script_name);
     std::fs::write(&script_path, script).unwrap();
@@ -95,11 +99,168 @@ fn test_cli_help() {
     cmd.args(vec!["--help"]).assert().success();
 }
 
+#[test]
+fn test_generate_completions() {
+    let (mut cmd, _temp_dir) = get_cli();
+    cmd.args(vec!["--generate-completions", "bash"])
+        .assert()
+        .success()
+        .stdout(contains("_horust()").and(contains("describe")));
+    let (mut cmd, _temp_dir) = get_cli();
+    cmd.args(vec!["--generate-completions", "tcsh"])
+        .assert()
+        .failure();
+}
+
 fn pid_from_id(id: u32) -> Pid {
     let id: pid_t = id as i32;
     Pid::from_raw(id)
 }
 
+#[test]
+fn test_describe() {
+    let (mut cmd, temp_dir) = get_cli();
+    let service = r#"description = "Says hello."
+documentation = "https://example.com/hello"
+description-file = "hello.md""#;
+    store_service(temp_dir.path(), "", Some(service), Some("hello"));
+    std::fs::write(temp_dir.path().join("hello.md"), "# Hello runbook").unwrap();
+    cmd.args(vec!["describe", "hello"])
+        .assert()
+        .success()
+        .stdout(
+            contains("Description: Says hello.")
+                .and(contains("https://example.com/hello"))
+                .and(contains("# Hello runbook")),
+        );
+
+    let (mut cmd, _temp_dir) = get_cli();
+    cmd.args(vec!["describe", "missing"]).assert().failure();
+}
+
+#[test]
+fn test_exec_search_path() {
+    let (mut cmd, temp_dir) = get_cli();
+    let bin_dir = TempDir::new("bin").unwrap();
+    let program = bin_dir.path().join("horust-test-program");
+    std::fs::write(&program, "#!/bin/bash\necho from-search-path").unwrap();
+    std::fs::set_permissions(&program, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let service = format!(
+        r#"command = "horust-test-program"
+exec-search-path = ["{}"]"#,
+        bin_dir.path().display()
+    );
+    std::fs::write(temp_dir.path().join("program.toml"), service).unwrap();
+    cmd.assert().success().stdout(contains("from-search-path"));
+}
+
+#[test]
+fn test_command_search_paths() {
+    let (mut cmd, temp_dir) = get_cli();
+    let bin_dir = TempDir::new("bin").unwrap();
+    let program = bin_dir.path().join("horust-test-program");
+    std::fs::write(
+        &program,
+        "#!/bin/bash\necho \"from-search-path: $(which bash)\"",
+    )
+    .unwrap();
+    std::fs::set_permissions(&program, std::fs::Permissions::from_mode(0o755)).unwrap();
+    // The PATH is used as usual, e.g. by the program itself:
+    let service = format!(
+        r#"command = "horust-test-program"
+command-search-paths = ["{}"]"#,
+        bin_dir.path().display()
+    );
+    std::fs::write(temp_dir.path().join("program.toml"), service).unwrap();
+    cmd.assert()
+        .success()
+        .stdout(contains("from-search-path: /").and(contains("bash")));
+}
+
+#[test]
+fn test_argv0() {
+    let (mut cmd, temp_dir) = get_cli();
+    let script = r#"#!/bin/bash
+tr '\0' ' ' < /proc/$$/cmdline"#;
+    store_service(
+        temp_dir.path(),
+        script,
+        Some(r#"argv0 = "my-worker""#),
+        None,
+    );
+    cmd.assert().success().stdout(contains("my-worker "));
+}
+
+#[test]
+fn test_exec_as_shell() {
+    let (mut cmd, temp_dir) = get_cli();
+    let service = r#"command = "echo shell-$((40 + 2)) | tr a-z A-Z"
+exec-as-shell = true
+shell = "/bin/bash""#;
+    std::fs::write(temp_dir.path().join("shell.toml"), service).unwrap();
+    cmd.assert().success().stdout(contains("SHELL-42"));
+}
+
+#[test]
+fn test_inherit_systemd_activation() {
+    let temp_dir = TempDir::new("horust").unwrap();
+    let script = r#"echo "fds=$LISTEN_FDS names=$LISTEN_FDNAMES own-pid=$([ "$LISTEN_PID" = "$$" ] && echo yes)"
+readlink /proc/self/fd/3"#;
+    let service = "inherit-systemd-activation = true";
+    store_service(temp_dir.path(), script, Some(service), None);
+    let script = r#"[ -e /proc/self/fd/3 ] || echo "no-socket""#;
+    store_service(temp_dir.path(), script, None, None);
+    let socket = temp_dir.path().join("socket");
+    std::fs::write(&socket, "").unwrap();
+    // As systemd would start Horust:
+    let mut cmd = Command::new("/bin/sh");
+    cmd.args(vec![
+        "-c",
+        r#"export LISTEN_PID=$$ LISTEN_FDS=1 LISTEN_FDNAMES=web; exec "$0" --services-path "$1" 3<"$2""#,
+        assert_cmd::cargo::cargo_bin("horust").to_str().unwrap(),
+        temp_dir.path().to_str().unwrap(),
+        socket.to_str().unwrap(),
+    ]);
+    cmd.assert().success().stdout(
+        contains("fds=1 names=web own-pid=yes")
+            .and(contains(socket.to_str().unwrap()))
+            .and(contains("no-socket")),
+    );
+}
+
+#[test]
+fn test_exec_wait_pid_file() {
+    let (mut cmd, temp_dir) = get_cli();
+    let pid_file = temp_dir.path().join("inner.pid");
+    // The wrapper exits right away, the inner process fails later:
+    let script = format!(
+        r#"(sleep 1; exit 1) &
+echo $! > {}"#,
+        pid_file.display()
+    );
+    let service = format!(
+        r#"exec-wait-pid-file = "{}"
+[failure]
+strategy = "shutdown""#,
+        pid_file.display()
+    );
+    store_service(
+        temp_dir.path(),
+        script.as_str(),
+        Some(service.as_str()),
+        Some("wrapper"),
+    );
+    let sleep_service = r#"[termination]
+wait = "500millis""#;
+    let sleep_script = r#"#!/bin/bash
+sleep 30"#;
+    store_service(temp_dir.path(), sleep_script, Some(sleep_service), None);
+    let start = Instant::now();
+    cmd.output().unwrap();
+    // The shutdown is triggered by the inner process, not by the sleep finishing:
+    assert!(start.elapsed() < Duration::from_secs(15));
+}
+
 #[test]
 fn test_cwd() {
     let (mut cmd, temp_dir) = get_cli();
@@ -225,6 +386,30 @@ printenv"#;
     cmd.assert().success().stdout(contains("bar"));
 }
 
+#[test]
+fn test_env_file() {
+    let (mut cmd, temp_dir) = get_cli();
+    let env_file = temp_dir.path().join("env");
+    std::fs::write(
+        &env_file,
+        "export DB_HOST=db\nexport DB_URL=\"postgres://${DB_HOST}/app\"\nfoo=from-file",
+    )
+    .unwrap();
+    let service = format!(
+        r#"[environment]
+foo = "bar"
+env-file = "{}"
+env-file-format = "shell""#,
+        env_file.display()
+    );
+    let script = r#"#!/bin/bash
+echo "url: $DB_URL, foo: $foo""#;
+    store_service(temp_dir.path(), script, Some(service.as_str()), None);
+    cmd.assert()
+        .success()
+        .stdout(contains("url: postgres://db/app, foo: bar"));
+}
+
 // Test failure strategies
 fn test_failure_strategy(strategy: &str) {
     //debug!("running test: {}", strategy);
@@ -283,12 +468,16 @@ echo "File is there!:D"
 "#,
         temp_dir.path().join("file.temp").display()
     );
+    // The healthiness check never succeeds, so the service fails while it's still starting.
     let service = format!(
         r#"
 [restart]
 attempts = {}
+[healthiness]
+file-path = "{}"
 "#,
-        attempts
+        attempts,
+        temp_dir.path().join("never-created").display()
     );
     store_service(
         temp_dir.path(),
@@ -310,3 +499,1251 @@ fn test_restart_attempts() {
 fn test_restart_attempts_succeed() {
     restart_backoff(true, 1);
 }
+
+#[test]
+fn test_restart_on_abnormal() {
+    let (mut cmd, temp_dir) = get_cli();
+    // Killed by a signal the first time, then it exits with a failure:
+    let script = format!(
+        r#"#!/bin/bash
+echo started
+sleep 1
+if [ ! -f {0} ]; then
+    touch {0}
+    kill -SEGV $$
+fi
+exit 1
+"#,
+        temp_dir.path().join("file.temp").display()
+    );
+    let service = r#"[restart]
+strategy = "on-abnormal"
+attempts = 0"#;
+    store_service(temp_dir.path(), script.as_str(), Some(service), None);
+    let output = cmd.output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout.matches("started").count(), 2, "{}", stdout);
+}
+
+#[test]
+fn test_start_limit() {
+    let (mut cmd, temp_dir) = get_cli();
+    store_service(
+        temp_dir.path(),
+        "#!/bin/bash\necho started",
+        Some(
+            r#"[restart]
+on-success = true
+start-limit-interval = "1m"
+start-limit-burst = 3"#,
+        ),
+        None,
+    );
+    let output = cmd.output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    // Not restarted anymore after the third start:
+    assert_eq!(stdout.matches("started").count(), 3, "{}", stdout);
+}
+
+#[test]
+fn test_fail_fast() {
+    let (mut cmd, temp_dir) = get_cli();
+    let config_dir = TempDir::new("horust-config").unwrap();
+    let config_path = config_dir.path().join("horust.toml");
+    std::fs::write(&config_path, "fail-fast = true").unwrap();
+    cmd.args(vec!["--config", config_path.display().to_string().as_str()]);
+    let failing_script = r#"#!/bin/bash
+exit 1"#;
+    // It never becomes healthy, so it fails while starting:
+    let failing_service = format!(
+        r#"[healthiness]
+file-path = "{}""#,
+        temp_dir.path().join("never-created").display()
+    );
+    store_service(
+        temp_dir.path(),
+        failing_script,
+        Some(failing_service.as_str()),
+        Some("a"),
+    );
+    let sleep_service = r#"[termination]
+wait = "500millis""#;
+    let sleep_script = r#"#!/bin/bash
+sleep 30"#;
+    store_service(temp_dir.path(), sleep_script, Some(sleep_service), None);
+    let start = Instant::now();
+    cmd.assert().code(1);
+    // It doesn't wait for the sleep to finish:
+    assert!(start.elapsed() < Duration::from_secs(15));
+}
+
+#[test]
+fn test_horust_config_reload() {
+    let (mut cmd, temp_dir) = get_cli();
+    let config_dir = TempDir::new("horust-config").unwrap();
+    let config_path = config_dir.path().join("horust.toml");
+    std::fs::write(&config_path, "").unwrap();
+    cmd.args(vec!["--config", config_path.display().to_string().as_str()]);
+    let sleep_service = r#"[termination]
+wait = "500millis""#;
+    let sleep_script = r#"#!/bin/bash
+sleep 30"#;
+    store_service(temp_dir.path(), sleep_script, Some(sleep_service), None);
+    let mut child = spawn_with_output(cmd, &temp_dir);
+    thread::sleep(Duration::from_millis(1000));
+    let config = format!(
+        r#"events-fifo = "{}"
+[log-levels]
+horust = "info""#,
+        config_dir.path().join("fifo").display()
+    );
+    std::fs::write(&config_path, config).unwrap();
+    kill(pid_from_id(child.id()), Signal::SIGHUP).unwrap();
+    thread::sleep(Duration::from_millis(1500));
+    kill(pid_from_id(child.id()), Signal::SIGTERM).unwrap();
+    assert!(child.wait().unwrap().success());
+    let stderr = read_output(&temp_dir, "stderr");
+    // The new log level is applied, the events fifo isn't created:
+    assert!(stderr.contains("Reloaded Horust's config"), "{}", stderr);
+    assert!(
+        stderr.contains("ignored until then: events-fifo"),
+        "{}",
+        stderr
+    );
+    assert!(!config_dir.path().join("fifo").exists());
+}
+
+#[test]
+fn test_ignore_exit_codes() {
+    let (mut cmd, temp_dir) = get_cli();
+    let script = r#"#!/bin/bash
+echo "exiting"
+exit 3"#;
+    let service = r#"[restart]
+ignore-exit-codes = [3]
+[failure]
+strategy = "shutdown""#;
+    store_service(temp_dir.path(), script, Some(service), None);
+    let script = r#"#!/bin/bash
+sleep 2
+echo "not shut down""#;
+    store_service(temp_dir.path(), script, None, None);
+    // It's not restarted, and it doesn't count as failed:
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let stdout = String::from_utf8_lossy(&output);
+    assert_eq!(stdout.matches("exiting").count(), 1, "{}", stdout);
+    assert!(stdout.contains("not shut down"), "{}", stdout);
+}
+
+#[test]
+fn test_events_fifo_invalid_path() {
+    let (mut cmd, temp_dir) = get_cli();
+    // It's not possible to create a directory inside a file:
+    let file_path = temp_dir.path().join("file");
+    std::fs::write(&file_path, "").unwrap();
+    let fifo_path = file_path.join("events");
+    cmd.args(vec![
+        "--events-fifo",
+        fifo_path.display().to_string().as_str(),
+    ]);
+    let script = r#"#!/bin/bash
+echo "Never started""#;
+    store_service(temp_dir.path(), script, None, None);
+    cmd.assert()
+        .failure()
+        .stdout(contains("Never started").not());
+}
+
+#[test]
+fn test_tmpfs_paths() {
+    let (mut cmd, temp_dir) = get_cli();
+    let mount_dir = TempDir::new("tmpfs").unwrap();
+    std::fs::write(mount_dir.path().join("from-host"), "").unwrap();
+    let service = format!(r#"tmpfs-paths = ["{}"]"#, mount_dir.path().display());
+    let script = format!(
+        r#"#!/bin/bash
+touch {0}/from-service
+ls {0}"#,
+        mount_dir.path().display()
+    );
+    store_service(
+        temp_dir.path(),
+        script.as_str(),
+        Some(service.as_str()),
+        None,
+    );
+    // tmpfs-paths require a new mount namespace:
+    cmd.assert()
+        .failure()
+        .stdout(contains("from-service").not());
+
+    if !nix::unistd::getuid().is_root() {
+        println!("Mounting requires root, skipping.");
+        return;
+    }
+    let (mut cmd, temp_dir) = get_cli();
+    let service = format!("new-mount-namespace = true\n{}", service);
+    store_service(
+        temp_dir.path(),
+        script.as_str(),
+        Some(service.as_str()),
+        None,
+    );
+    cmd.assert()
+        .success()
+        .stdout(contains("from-service").and(contains("from-host").not()));
+    // The service's writes ended up in its own tmpfs:
+    assert!(mount_dir.path().join("from-host").exists());
+    assert!(!mount_dir.path().join("from-service").exists());
+}
+
+#[test]
+fn test_bind_mounts() {
+    if !nix::unistd::getuid().is_root() {
+        println!("Mounting requires root, skipping.");
+        return;
+    }
+    let (mut cmd, temp_dir) = get_cli();
+    let source = TempDir::new("source").unwrap();
+    let destination = TempDir::new("destination").unwrap();
+    std::fs::write(source.path().join("secret"), "secret-value").unwrap();
+    let service = format!(
+        r#"new-mount-namespace = true
+[[mounts.bind]]
+source = "{}"
+destination = "{}"
+read-only = true"#,
+        source.path().display(),
+        destination.path().display()
+    );
+    let script = format!(
+        r#"#!/bin/bash
+cat {0}/secret
+touch {0}/written || echo "read only""#,
+        destination.path().display()
+    );
+    store_service(
+        temp_dir.path(),
+        script.as_str(),
+        Some(service.as_str()),
+        None,
+    );
+    cmd.assert()
+        .success()
+        .stdout(contains("secret-value").and(contains("read only")));
+    assert!(!source.path().join("written").exists());
+    assert!(!destination.path().join("secret").exists());
+}
+
+#[test]
+fn test_read_only_paths() {
+    if !nix::unistd::getuid().is_root() {
+        println!("Mounting requires root, skipping.");
+        return;
+    }
+    let (mut cmd, temp_dir) = get_cli();
+    let read_only = TempDir::new("read-only").unwrap();
+    let service = format!(
+        r#"new-mount-namespace = true
+read-only-paths = ["{}"]"#,
+        read_only.path().display()
+    );
+    let script = format!(
+        r#"#!/bin/bash
+touch {0}/written 2>&1
+touch {1}/written && echo "writable""#,
+        read_only.path().display(),
+        temp_dir.path().display()
+    );
+    store_service(
+        temp_dir.path(),
+        script.as_str(),
+        Some(service.as_str()),
+        None,
+    );
+    cmd.assert()
+        .success()
+        .stdout(contains("Read-only file system").and(contains("writable")));
+    assert!(!read_only.path().join("written").exists());
+}
+
+#[test]
+fn test_inaccessible_paths() {
+    if !nix::unistd::getuid().is_root() {
+        println!("Mounting requires root, skipping.");
+        return;
+    }
+    let (mut cmd, temp_dir) = get_cli();
+    let hidden_dir = TempDir::new("hidden").unwrap();
+    std::fs::write(hidden_dir.path().join("in-dir"), "").unwrap();
+    let hidden_file = temp_dir.path().join("hidden-file");
+    std::fs::write(&hidden_file, "secret-value").unwrap();
+    let service = format!(
+        r#"new-mount-namespace = true
+inaccessible-paths = ["{}", "{}"]"#,
+        hidden_dir.path().display(),
+        hidden_file.display()
+    );
+    let script = format!(
+        r#"#!/bin/bash
+ls {0}
+cat {1}
+echo "overwritten" > {1}"#,
+        hidden_dir.path().display(),
+        hidden_file.display()
+    );
+    store_service(
+        temp_dir.path(),
+        script.as_str(),
+        Some(service.as_str()),
+        None,
+    );
+    cmd.assert()
+        .success()
+        .stdout(contains("in-dir").not().and(contains("secret-value").not()));
+    assert_eq!(
+        std::fs::read_to_string(&hidden_file).unwrap(),
+        "secret-value"
+    );
+}
+
+#[test]
+fn test_private_tmp() {
+    if !nix::unistd::getuid().is_root() {
+        println!("Mounting requires root, skipping.");
+        return;
+    }
+    let (mut cmd, temp_dir) = get_cli();
+    let host_dir = TempDir::new("host").unwrap();
+    // The script would be hidden by the private /tmp, so the command is inline.
+    // No new-mount-namespace: it's implied.
+    let service = r#"command = "/bin/sh -c 'ls /tmp; touch /tmp/from-service /run/from-service; ls /tmp /run'"
+private-tmp = true
+private-run = true"#;
+    std::fs::write(temp_dir.path().join("private.toml"), service).unwrap();
+    let host_dir_name = host_dir.path().file_name().unwrap().to_str().unwrap();
+    cmd.assert().success().stdout(
+        contains(host_dir_name)
+            .not()
+            .and(contains("from-service").count(2)),
+    );
+    assert!(!Path::new("/tmp/from-service").exists());
+    assert!(!Path::new("/run/from-service").exists());
+}
+
+#[test]
+fn test_private_network() {
+    if !nix::unistd::getuid().is_root() {
+        println!("A network namespace requires root, skipping.");
+        return;
+    }
+    let (mut cmd, temp_dir) = get_cli();
+    // The loopback is up (so the connection is refused, instead of the network being unreachable),
+    // and it's the only interface:
+    let script = r#"#!/bin/bash
+(echo > /dev/tcp/127.0.0.1/1) 2>&1
+echo "interfaces: $(tail -n +3 /proc/net/dev | wc -l)""#;
+    store_service(
+        temp_dir.path(),
+        script,
+        Some("private-network = true"),
+        None,
+    );
+    cmd.assert()
+        .success()
+        .stdout(contains("Connection refused").and(contains("interfaces: 1")));
+}
+
+#[test]
+fn test_private_users() {
+    if !nix::unistd::getuid().is_root() {
+        println!("Mapping a range of users requires root, skipping.");
+        return;
+    }
+    let (mut cmd, temp_dir) = get_cli();
+    let script = r#"#!/bin/bash
+echo "uid: $(id -u)"
+echo "map: $(awk '{print $1, $2, $3}' /proc/self/uid_map)""#;
+    store_service(temp_dir.path(), script, Some("private-users = true"), None);
+    cmd.assert()
+        .success()
+        .stdout(contains("uid: 0").and(contains("map: 0 100000 65536")));
+}
+
+#[test]
+fn test_new_cgroup_namespace() {
+    if !nix::unistd::getuid().is_root() {
+        println!("A cgroup namespace requires root, skipping.");
+        return;
+    }
+    let (mut cmd, temp_dir) = get_cli();
+    // Every cgroup of the service is the root of its namespace:
+    let script = r#"#!/bin/bash
+echo "not root: $(grep -vc ':/$' /proc/self/cgroup)""#;
+    store_service(
+        temp_dir.path(),
+        script,
+        Some("new-cgroup-namespace = true"),
+        None,
+    );
+    cmd.assert().success().stdout(contains("not root: 0"));
+}
+
+#[test]
+fn test_ipc_namespace() {
+    if !nix::unistd::getuid().is_root() {
+        println!("An IPC namespace requires root, skipping.");
+        return;
+    }
+    let (mut cmd, temp_dir) = get_cli();
+    let script = r#"#!/bin/bash
+ipcmk -Q > /dev/null
+sleep 2"#;
+    let service = r#"ipc-namespace = "private"
+[termination]
+wait = "500millis""#;
+    store_service(temp_dir.path(), script, Some(service), Some("server"));
+    let script = r#"#!/bin/bash
+sleep 0.5
+echo "queues: $(ipcs -q | grep -c '^0x')""#;
+    let service = r#"ipc-namespace = "service:server.toml"
+start-after = ["server.toml"]"#;
+    store_service(temp_dir.path(), script, Some(service), None);
+    let queues = || {
+        let output = Command::new("ipcs").arg("-q").output().unwrap();
+        String::from_utf8_lossy(&output.stdout)
+            .matches("\n0x")
+            .count()
+    };
+    let before = queues();
+    cmd.assert().success().stdout(contains("queues: 1"));
+    // The queue was created in the private namespace only:
+    assert_eq!(queues(), before);
+}
+
+#[test]
+fn test_network_namespace() {
+    if !nix::unistd::getuid().is_root() {
+        println!("A network namespace requires root, skipping.");
+        return;
+    }
+    let (mut cmd, temp_dir) = get_cli();
+    let namespace_file = temp_dir.path().join("main-app-ns");
+    let script = format!(
+        r#"#!/bin/bash
+readlink /proc/self/ns/net > {}
+sleep 2"#,
+        namespace_file.display()
+    );
+    let service = r#"private-network = true
+[termination]
+wait = "500millis""#;
+    store_service(
+        temp_dir.path(),
+        script.as_str(),
+        Some(service),
+        Some("main-app"),
+    );
+    let script = format!(
+        r#"#!/bin/bash
+sleep 0.5
+[ "$(readlink /proc/self/ns/net)" = "$(cat {})" ] && echo "shared""#,
+        namespace_file.display()
+    );
+    let service = r#"network-namespace = "service:main-app.toml"
+start-after = ["main-app.toml"]"#;
+    store_service(temp_dir.path(), script.as_str(), Some(service), None);
+    cmd.assert().success().stdout(contains("shared"));
+    let host_namespace = std::fs::read_link("/proc/self/ns/net").unwrap();
+    let namespace = std::fs::read_to_string(&namespace_file).unwrap();
+    assert_ne!(namespace.trim(), host_namespace.display().to_string());
+}
+
+#[test]
+fn test_pid_namespace() {
+    if !nix::unistd::getuid().is_root() {
+        println!("A pid namespace requires root, skipping.");
+        return;
+    }
+    let (mut cmd, temp_dir) = get_cli();
+    let namespace_file = temp_dir.path().join("main-app-ns");
+    let script = format!(
+        r#"#!/bin/bash
+echo "main-app pid: $$"
+readlink /proc/self/ns/pid > {}
+sleep 2"#,
+        namespace_file.display()
+    );
+    let service = r#"pid-namespace = "private"
+[termination]
+wait = "500millis""#;
+    store_service(
+        temp_dir.path(),
+        script.as_str(),
+        Some(service),
+        Some("main-app"),
+    );
+    let script = format!(
+        r#"#!/bin/bash
+sleep 0.5
+[ "$(readlink /proc/self/ns/pid)" = "$(cat {})" ] && echo "shared""#,
+        namespace_file.display()
+    );
+    let service = r#"pid-namespace = "service:main-app.toml"
+start-after = ["main-app.toml"]"#;
+    store_service(temp_dir.path(), script.as_str(), Some(service), None);
+    cmd.assert()
+        .success()
+        .stdout(contains("main-app pid: 1").and(contains("shared")));
+}
+
+#[test]
+fn test_protect_home_and_system() {
+    if !nix::unistd::getuid().is_root() {
+        println!("Mounting requires root, skipping.");
+        return;
+    }
+    let (mut cmd, temp_dir) = get_cli();
+    let service = "protect-home = true\nprotect-system = \"strict\"";
+    let script = r#"#!/bin/bash
+echo "home: $(ls -A /root | wc -l)"
+touch /etc/horust-protected 2>&1"#;
+    store_service(temp_dir.path(), script, Some(service), None);
+    cmd.assert()
+        .success()
+        .stdout(contains("home: 0").and(contains("Read-only file system")));
+    assert!(!Path::new("/etc/horust-protected").exists());
+}
+
+#[test]
+fn test_capabilities_bounding_set() {
+    if !nix::unistd::getuid().is_root() {
+        println!("Dropping capabilities requires CAP_SETPCAP, skipping.");
+        return;
+    }
+    let (mut cmd, temp_dir) = get_cli();
+    let service = r#"capabilities-bounding-set = ["CAP_CHOWN", "CAP_KILL"]"#;
+    let script = r#"#!/bin/bash
+grep CapBnd /proc/self/status"#;
+    store_service(temp_dir.path(), script, Some(service), None);
+    cmd.assert()
+        .success()
+        .stdout(contains("CapBnd:\t0000000000000021"));
+}
+
+#[test]
+fn test_coredump_filter() {
+    let (mut cmd, temp_dir) = get_cli();
+    let service = r#"coredump-filter = "0x31""#;
+    let script = r#"#!/bin/bash
+cat /proc/self/coredump_filter"#;
+    store_service(temp_dir.path(), script, Some(service), None);
+    cmd.assert().success().stdout(contains("00000031"));
+}
+
+#[test]
+fn test_idle() {
+    let (mut cmd, temp_dir) = get_cli();
+    let script = r#"#!/bin/bash
+echo "idle""#;
+    store_service(
+        temp_dir.path(),
+        script,
+        Some(r#"service-type = "idle""#),
+        None,
+    );
+    let script = r#"#!/bin/bash
+echo "simple"
+sleep 1"#;
+    store_service(temp_dir.path(), script, None, None);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::is_match("(?s)simple.*idle").unwrap());
+}
+
+#[test]
+fn test_pre_exec_command() {
+    let (mut cmd, temp_dir) = get_cli();
+    let generated = temp_dir.path().join("generated");
+    let service = format!(
+        r#"pre-exec-command = "/bin/sh -c 'echo $GREETING > {}'"
+[environment]
+GREETING = "from-pre-exec""#,
+        generated.display()
+    );
+    let script = format!(
+        r#"#!/bin/bash
+cat {}"#,
+        generated.display()
+    );
+    store_service(
+        temp_dir.path(),
+        script.as_str(),
+        Some(service.as_str()),
+        None,
+    );
+    cmd.assert().success().stdout(contains("from-pre-exec"));
+
+    // The service isn't started, if it fails:
+    let (mut cmd, temp_dir) = get_cli();
+    let service = r#"pre-exec-command = "/bin/false""#;
+    let script = r#"#!/bin/bash
+echo "started""#;
+    store_service(temp_dir.path(), script, Some(service), None);
+    cmd.assert().success().stdout(contains("started").not());
+
+    // Nor if it hangs:
+    let (mut cmd, temp_dir) = get_cli();
+    let service = r#"pre-exec-command = "/bin/sleep 30"
+pre-exec-timeout = "500ms""#;
+    store_service(temp_dir.path(), script, Some(service), None);
+    let start = Instant::now();
+    cmd.assert().success().stdout(contains("started").not());
+    assert!(start.elapsed() < Duration::from_secs(15));
+}
+
+#[test]
+fn test_template() {
+    let (mut cmd, temp_dir) = get_cli();
+    let service = r#"command = "/bin/echo instance-%i"
+service-type = "template""#;
+    let template = temp_dir.path().join("worker@.toml");
+    std::fs::write(&template, service).unwrap();
+    ["worker@1.toml", "worker@2.toml"]
+        .iter()
+        .for_each(|instance| {
+            std::os::unix::fs::symlink(&template, temp_dir.path().join(instance)).unwrap()
+        });
+    cmd.assert().success().stdout(
+        contains("instance-1")
+            .and(contains("instance-2"))
+            .and(contains("instance-%i").not()),
+    );
+}
+
+#[test]
+fn test_services_from_env() {
+    let (mut cmd, _temp_dir) = get_cli();
+    cmd.env("HORUST_SERVICE_0", "command = \"/bin/echo from-env-0\"")
+        .env(
+            "HORUST_SERVICE_1",
+            "command%20%3D%20%22%2Fbin%2Fecho%20from-env-1%22%0A%5Brestart%5D%0Astrategy%20%3D%20%22never%22",
+        );
+    cmd.assert()
+        .success()
+        .stdout(contains("from-env-0").and(contains("from-env-1")));
+}
+
+#[test]
+fn test_inherit_env_from() {
+    let (mut cmd, temp_dir) = get_cli();
+    // The port is chosen at runtime, and the exec makes it visible in /proc/<pid>/environ:
+    let script = r#"#!/bin/bash
+export DATABASE_PORT=5433
+exec sleep 2"#;
+    store_service(temp_dir.path(), script, None, Some("database"));
+    let service = r#"start-after = ["database.toml"]
+start-delay = "500ms"
+[environment]
+inherit-env-from = { service = "database.toml", vars = ["DATABASE_PORT", "MISSING"] }"#;
+    let script = r#"#!/bin/bash
+echo "port: $DATABASE_PORT""#;
+    store_service(temp_dir.path(), script, Some(service), None);
+    cmd.assert().success().stdout(contains("port: 5433"));
+}
+
+#[test]
+fn test_stdin_from_service() {
+    let (mut cmd, temp_dir) = get_cli();
+    let script = r#"#!/bin/bash
+echo "from-producer""#;
+    store_service(temp_dir.path(), script, None, Some("producer"));
+    // It exits once the producer has closed the pipe:
+    let script = r#"#!/bin/bash
+while read line; do echo "consumed: $line"; done"#;
+    let service = r#"stdin-from-service = "producer.toml""#;
+    store_service(temp_dir.path(), script, Some(service), None);
+    cmd.assert()
+        .success()
+        .stdout(contains("consumed: from-producer"));
+}
+
+#[test]
+fn test_close_extra_fds() {
+    let (mut cmd, temp_dir) = get_cli();
+    let file = std::fs::File::open("/dev/null").unwrap();
+    let fd = file.as_raw_fd();
+    unsafe {
+        // The fd 7 of Horust won't be close-on-exec:
+        cmd.pre_exec(move || {
+            nix::unistd::dup2(fd, 7)
+                .map(|_| ())
+                .map_err(|_| std::io::Error::last_os_error())
+        });
+    }
+    let script = |name: &str| {
+        format!(
+            r#"#!/bin/bash
+[ -e /proc/$$/fd/7 ] && echo "{0}: inherited" || echo "{0}: closed""#,
+            name
+        )
+    };
+    store_service(
+        temp_dir.path(),
+        script("first").as_str(),
+        Some("close-extra-fds = false"),
+        None,
+    );
+    store_service(temp_dir.path(), script("second").as_str(), None, None);
+    cmd.assert()
+        .success()
+        .stdout(contains("first: inherited").and(contains("second: closed")));
+}
+
+/// Spawns horust with stdout and stderr redirected to files in `temp_dir`.
+fn spawn_with_output(mut cmd: Command, temp_dir: &TempDir) -> std::process::Child {
+    let stdout = std::fs::File::create(temp_dir.path().join("stdout")).unwrap();
+    let stderr = std::fs::File::create(temp_dir.path().join("stderr")).unwrap();
+    cmd.stdout(stdout).stderr(stderr).spawn().unwrap()
+}
+
+fn read_output(temp_dir: &TempDir, name: &str) -> String {
+    std::fs::read_to_string(temp_dir.path().join(name)).unwrap()
+}
+
+/// Stores a notify service "a", and a service "b" which starts after it.
+fn store_notify_services(temp_dir: &TempDir, readiness_timeout: &str) {
+    let notify_service = format!(
+        r#"service-type = "notify"
+readiness-timeout = "{}"
+[termination]
+wait = "500millis""#,
+        readiness_timeout
+    );
+    let notify_script = r#"#!/bin/bash
+echo "a"
+sleep 30"#;
+    store_service(
+        temp_dir.path(),
+        notify_script,
+        Some(notify_service.as_str()),
+        Some("a"),
+    );
+    let script_b = r#"#!/bin/bash
+echo "b""#;
+    store_service(
+        temp_dir.path(),
+        script_b,
+        Some(r#"start-after = ["a.toml"]"#),
+        Some("b"),
+    );
+}
+
+#[test]
+fn test_service_type_notify() {
+    let (cmd, temp_dir) = get_cli();
+    store_notify_services(&temp_dir, "10s");
+    let mut child = spawn_with_output(cmd, &temp_dir);
+    thread::sleep(Duration::from_millis(1500));
+    assert_eq!(read_output(&temp_dir, "stdout"), "a\n");
+
+    let socket_path = std::env::temp_dir()
+        .join(format!("horust-{}", child.id()))
+        .join("a.toml.sock");
+    UnixDatagram::unbound()
+        .unwrap()
+        .send_to(b"STATUS=Ready\nREADY=1\n", &socket_path)
+        .unwrap();
+    thread::sleep(Duration::from_millis(1500));
+    assert_eq!(read_output(&temp_dir, "stdout"), "a\nb\n");
+
+    kill(pid_from_id(child.id()), Signal::SIGTERM).unwrap();
+    assert!(child.wait().unwrap().success());
+    // The sockets are removed:
+    assert!(!socket_path.exists());
+}
+
+#[test]
+fn test_service_type_notify_timeout() {
+    let (cmd, temp_dir) = get_cli();
+    store_notify_services(&temp_dir, "500millis");
+    let mut child = spawn_with_output(cmd, &temp_dir);
+    thread::sleep(Duration::from_secs(3));
+    kill(pid_from_id(child.id()), Signal::SIGTERM).unwrap();
+    child.wait().unwrap();
+    assert_eq!(read_output(&temp_dir, "stdout"), "a\n");
+    assert!(read_output(&temp_dir, "stderr").contains("didn't notify its readiness in time"));
+}
+
+/// False if the process is not there anymore, or it's a zombie.
+fn is_process_running(pid: &str) -> bool {
+    std::fs::read_to_string(format!("/proc/{}/stat", pid))
+        .map(|stat| !stat[stat.rfind(')').unwrap()..].starts_with(") Z"))
+        .unwrap_or(false)
+}
+
+/// Stops Horust, and returns whether the service and its child are still running after a while.
+fn running_after_termination(kill_options: &str) -> (bool, bool) {
+    let (cmd, temp_dir) = get_cli();
+    let pid_file = temp_dir.path().join("child.pid");
+    let service = format!(
+        r#"{}
+[termination]
+wait = "10s""#,
+        kill_options
+    );
+    let script = format!(
+        r#"#!/bin/bash
+sleep 30 &
+echo "$$ $!" > {}
+wait"#,
+        pid_file.display()
+    );
+    store_service(
+        temp_dir.path(),
+        script.as_str(),
+        Some(service.as_str()),
+        None,
+    );
+    let mut child = spawn_with_output(cmd, &temp_dir);
+    thread::sleep(Duration::from_millis(1500));
+    let pids = std::fs::read_to_string(&pid_file).unwrap();
+    let pids: Vec<&str> = pids.split_whitespace().collect();
+    assert!(pids.iter().all(|pid| is_process_running(pid)));
+    kill(pid_from_id(child.id()), Signal::SIGTERM).unwrap();
+    thread::sleep(Duration::from_millis(1500));
+    let is_running = (is_process_running(pids[0]), is_process_running(pids[1]));
+    kill(pid_from_id(child.id()), Signal::SIGKILL).unwrap();
+    child.wait().unwrap();
+    pids.iter().for_each(|pid| {
+        let _ = kill(Pid::from_raw(pid.parse().unwrap()), Signal::SIGKILL);
+    });
+    is_running
+}
+
+#[test]
+fn test_signal_process_group() {
+    assert_eq!(
+        running_after_termination("signal-process-group = true"),
+        (false, false)
+    );
+    assert_eq!(
+        running_after_termination("signal-process-group = false"),
+        (false, true)
+    );
+}
+
+#[test]
+fn test_kill_mode() {
+    assert_eq!(
+        running_after_termination(r#"kill-mode = "process-group""#),
+        (false, false)
+    );
+    assert_eq!(
+        running_after_termination(r#"kill-mode = "process""#),
+        (false, true)
+    );
+    assert_eq!(
+        running_after_termination(r#"kill-mode = "none""#),
+        (true, true)
+    );
+}
+
+#[test]
+fn test_forward_signals() {
+    let (cmd, temp_dir) = get_cli();
+    let output = temp_dir.path().join("signals");
+    let script = format!(
+        r#"#!/bin/bash
+trap 'echo usr1 >> {0}' USR1
+trap 'echo usr2 >> {0}' USR2
+while true; do sleep 0.1; done"#,
+        output.display()
+    );
+    store_service(
+        temp_dir.path(),
+        script.as_str(),
+        Some(r#"forward-signals = ["USR1"]"#),
+        None,
+    );
+    let recv = run_async(cmd, true);
+    thread::sleep(Duration::from_millis(1000));
+    kill(recv.1, Signal::SIGUSR1).unwrap();
+    // Not forwarded, and ignored by Horust:
+    kill(recv.1, Signal::SIGUSR2).unwrap();
+    thread::sleep(Duration::from_millis(1000));
+    let signals = std::fs::read_to_string(&output).unwrap();
+    kill(recv.1, Signal::SIGTERM).unwrap();
+    recv.recv_or_kill(Duration::from_secs(3));
+    assert_eq!(signals, "usr1\n");
+}
+
+/// Returns the signals ignored by the service, as shown in /proc/<pid>/status.
+fn ignored_signals(inherit_signals: bool) -> u64 {
+    let (mut cmd, temp_dir) = get_cli();
+    let script = r#"#!/bin/bash
+grep SigIgn /proc/$$/status"#;
+    let service = format!("inherit-signals = {}", inherit_signals);
+    store_service(temp_dir.path(), script, Some(service.as_str()), None);
+    let output = cmd.output().unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mask = stdout.trim().trim_start_matches("SigIgn:").trim();
+    u64::from_str_radix(mask, 16).unwrap()
+}
+
+#[test]
+fn test_inherit_signals() {
+    let sigpipe = 1 << (libc::SIGPIPE - 1);
+    assert_eq!(ignored_signals(false) & sigpipe, 0);
+    assert_eq!(ignored_signals(true) & sigpipe, sigpipe);
+}
+
+/// Returns the first log line of Horust.
+fn first_log_line(log_timestamps: bool) -> String {
+    let (mut cmd, temp_dir) = get_cli();
+    store_service(temp_dir.path(), "#!/bin/bash\n", None, None);
+    let log_timestamps = log_timestamps.to_string();
+    let output = cmd
+        .env("HORUST_LOG", "info")
+        .args(vec!["--log-timestamps", log_timestamps.as_str()])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    stderr.lines().next().unwrap().to_string()
+}
+
+#[test]
+fn test_log_timestamps() {
+    let line = first_log_line(false);
+    assert!(line.starts_with("[INFO "), "{}", line);
+    let line = first_log_line(true);
+    assert!(!line.starts_with("[INFO "), "{}", line);
+    assert!(line.contains(" INFO "), "{}", line);
+}
+
+#[test]
+fn test_state_file() {
+    let (mut cmd, temp_dir) = get_cli();
+    let state_dir = TempDir::new("horust-state").unwrap();
+    let state_file = state_dir.path().join("state.json");
+    // As left by a previous run, where the service has been stopped:
+    std::fs::write(&state_file, r#"{"stopped": ["stopped.toml"]}"#).unwrap();
+    store_service(
+        temp_dir.path(),
+        "#!/bin/bash\necho started",
+        None,
+        Some("stopped"),
+    );
+    store_service(temp_dir.path(), "#!/bin/bash\necho running", None, None);
+    cmd.args(vec![
+        "--state-file",
+        state_file.display().to_string().as_str(),
+    ])
+    .assert()
+    .success()
+    .stdout(contains("running").and(contains("started").not()));
+}
+
+#[test]
+fn test_condition_hostname() {
+    let (mut cmd, temp_dir) = get_cli();
+    let mut buf = [0u8; 256];
+    let hostname = nix::unistd::gethostname(&mut buf).unwrap();
+    let matching = format!(
+        r#"condition-hostname = "{}""#,
+        regex::escape(hostname.to_str().unwrap())
+    );
+    store_service(
+        temp_dir.path(),
+        "#!/bin/bash\necho on-this-host",
+        Some(matching.as_str()),
+        None,
+    );
+    store_service(
+        temp_dir.path(),
+        "#!/bin/bash\necho on-another-host",
+        Some(r#"condition-hostname = "another-host""#),
+        None,
+    );
+    cmd.assert()
+        .success()
+        .stdout(contains("on-this-host").and(contains("on-another-host").not()));
+}
+
+#[test]
+fn test_inline_services() {
+    let (mut cmd, temp_dir) = get_cli();
+    let config_path = temp_dir.path().join("horust-config");
+    std::fs::write(
+        &config_path,
+        r#"[[services]]
+name = "inline.toml"
+command = "/bin/echo from-config"
+
+[[services]]
+name = "other"
+command = "/bin/echo other-from-config""#,
+    )
+    .unwrap();
+    store_service(
+        temp_dir.path(),
+        "#!/bin/bash\necho from-file",
+        None,
+        Some("inline"),
+    );
+    cmd.args(vec!["--config", config_path.display().to_string().as_str()])
+        .assert()
+        .success()
+        .stdout(
+            contains("from-config")
+                .and(contains("other-from-config"))
+                .and(contains("from-file").not()),
+        );
+}
+
+#[test]
+fn test_test_service() {
+    let (mut cmd, temp_dir) = get_cli();
+    let service_dir = TempDir::new("horust-test-service").unwrap();
+    let service = r#"start-after = ["missing.toml"]
+[restart]
+strategy = "always""#;
+    let service_name = store_service(
+        service_dir.path(),
+        "#!/bin/bash\necho tested\nexit 3",
+        Some(service),
+        None,
+    );
+    let service_path = service_dir.path().join(service_name);
+    // The services in the services path are not started:
+    store_service(temp_dir.path(), "#!/bin/bash\necho other", None, None);
+    let output = cmd
+        .args(vec![
+            "--test-service",
+            service_path.display().to_string().as_str(),
+        ])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(3));
+    // Started once, regardless of the restart strategy:
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "tested\n");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("exited with code: 3"));
+}
+
+#[test]
+fn test_log_levels() {
+    let (mut cmd, temp_dir) = get_cli();
+    let config_path = temp_dir.path().join("horust-config");
+    std::fs::write(
+        &config_path,
+        "[log-levels]\n\"horust::runtime::my-service\" = \"debug\"",
+    )
+    .unwrap();
+    store_service(temp_dir.path(), "#!/bin/bash\n", None, Some("my-service"));
+    store_service(temp_dir.path(), "#!/bin/bash\n", None, Some("other"));
+    let output = cmd
+        .env_remove("HORUST_LOG")
+        .args(vec!["--config", config_path.display().to_string().as_str()])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Applying event"), "{}", stderr);
+    assert!(
+        stderr.contains("Setting pid") && stderr.contains("my-service.toml successfully exited"),
+        "{}",
+        stderr
+    );
+    // Nothing about the other service:
+    assert!(!stderr.contains("other.toml"), "{}", stderr);
+}
+
+#[test]
+fn test_termination_sequence() {
+    let (cmd, temp_dir) = get_cli();
+    let output = temp_dir.path().join("signals");
+    let script = format!(
+        r#"#!/bin/bash
+trap 'echo term >> {0}' TERM
+trap 'echo quit >> {0}; exit 0' QUIT
+while true; do sleep 0.1; done"#,
+        output.display()
+    );
+    let service = r#"[[termination.sequence]]
+signal = "TERM"
+wait = "1s"
+[[termination.sequence]]
+signal = "QUIT"
+wait = "10s"
+[[termination.sequence]]
+signal = "KILL""#;
+    store_service(temp_dir.path(), script.as_str(), Some(service), None);
+    let recv = run_async(cmd, true);
+    kill(recv.1, Signal::SIGTERM).unwrap();
+    // It exits on QUIT, before the last step:
+    recv.recv_or_kill(Duration::from_secs(4));
+    assert_eq!(std::fs::read_to_string(&output).unwrap(), "term\nquit\n");
+}
+
+#[test]
+fn test_stop_command() {
+    let (cmd, temp_dir) = get_cli();
+    let output = temp_dir.path().join("stopped");
+    // It ignores TERM, and waits for the stop command:
+    let script = format!(
+        r#"#!/bin/bash
+trap '' TERM
+while [ ! -f {0} ]; do sleep 0.1; done
+echo "stopped" >> {0}"#,
+        output.display()
+    );
+    let service = format!(
+        r#"[termination]
+wait = "10s"
+stop-command = "/bin/sh -c 'echo $MAINPID > {}'""#,
+        output.display()
+    );
+    store_service(
+        temp_dir.path(),
+        script.as_str(),
+        Some(service.as_str()),
+        None,
+    );
+    let recv = run_async(cmd, true);
+    kill(recv.1, Signal::SIGTERM).unwrap();
+    recv.recv_or_kill(Duration::from_secs(5));
+    let output = std::fs::read_to_string(&output).unwrap();
+    let lines: Vec<&str> = output.lines().collect();
+    assert!(lines[0].parse::<i32>().is_ok(), "{}", output);
+    assert_eq!(lines[1], "stopped");
+}
+
+#[test]
+fn test_stop_timeout_action_abort() {
+    let (cmd, temp_dir) = get_cli();
+    let output = temp_dir.path().join("aborted");
+    // It ignores TERM, and it's aborted once the termination wait is over:
+    let script = format!(
+        r#"#!/bin/bash
+trap '' TERM
+trap 'echo aborted > {}; exit 1' ABRT
+while true; do sleep 0.1; done"#,
+        output.display()
+    );
+    let service = r#"[termination]
+wait = "1s"
+stop-timeout-action = "abort""#;
+    store_service(temp_dir.path(), script.as_str(), Some(service), None);
+    let recv = run_async(cmd, true);
+    kill(recv.1, Signal::SIGTERM).unwrap();
+    recv.recv_or_kill(Duration::from_secs(4));
+    assert_eq!(std::fs::read_to_string(&output).unwrap(), "aborted\n");
+}
+
+#[test]
+fn test_pid_file() {
+    let (mut cmd, temp_dir) = get_cli();
+    let pid_dir = TempDir::new("horust-pid").unwrap();
+    let pid_file = pid_dir.path().join("horust.pid");
+    let pid_file_arg = pid_file.display().to_string();
+    let script = format!(
+        r#"#!/bin/bash
+cat {}"#,
+        pid_file.display()
+    );
+    store_service(temp_dir.path(), script.as_str(), None, None);
+    let output = cmd
+        .args(vec!["--pid-file", pid_file_arg.as_str()])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(!String::from_utf8_lossy(&output.stdout).trim().is_empty());
+    assert!(!pid_file.exists());
+
+    // It belongs to a running process:
+    std::fs::write(&pid_file, std::process::id().to_string()).unwrap();
+    let (mut cmd, temp_dir) = get_cli();
+    store_service(temp_dir.path(), "#!/bin/bash\n", None, None);
+    cmd.args(vec!["--pid-file", pid_file_arg.as_str()])
+        .assert()
+        .failure();
+    let (mut cmd, temp_dir) = get_cli();
+    store_service(temp_dir.path(), "#!/bin/bash\n", None, None);
+    cmd.args(vec![
+        "--pid-file",
+        pid_file_arg.as_str(),
+        "--pid-file-force",
+    ])
+    .assert()
+    .success();
+    assert!(!pid_file.exists());
+}
+
+#[test]
+fn test_restart_events() {
+    // Timing based: it's run only on CI, where the machines are expected to be idle enough.
+    if std::env::var("CI").as_deref() != Ok("1") {
+        eprintln!("Skipping test_restart_events, CI=1 isn't set.");
+        return;
+    }
+    let (mut cmd, temp_dir) = get_cli();
+    let fifo_dir = TempDir::new("horust-fifo").unwrap();
+    let fifo_path = fifo_dir.path().join("events");
+    nix::unistd::mkfifo(&fifo_path, nix::sys::stat::Mode::S_IRWXU).unwrap();
+    let mut fifo = std::fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(&fifo_path)
+        .unwrap();
+    cmd.args(vec![
+        "--events-fifo",
+        fifo_path.display().to_string().as_str(),
+    ]);
+    let script = r#"#!/bin/bash
+sleep 0.1"#;
+    let service = r#"[restart]
+strategy = "always""#;
+    let service_name = store_service(temp_dir.path(), script, Some(service), None);
+    let mut child = spawn_with_output(cmd, &temp_dir);
+
+    let started = format!(r#"{{"StatusChanged":["{}","Starting"]}}"#, service_name);
+    let count_starts = |events: &str| events.lines().filter(|line| *line == started).count();
+    let mut events = String::new();
+    let start = Instant::now();
+    let mut first_start = None;
+    let mut third_start = None;
+    let mut buf = [0; 4096];
+    // Stop as soon as there are enough events, to keep the test fast on idle machines:
+    while third_start.is_none() && start.elapsed() < Duration::from_secs(15) {
+        // The fifo is non blocking, so this returns an error if there is nothing to read:
+        match fifo.read(&mut buf) {
+            Ok(size) if size > 0 => events.push_str(&String::from_utf8_lossy(&buf[..size])),
+            _ => thread::sleep(Duration::from_millis(10)),
+        }
+        let starts = count_starts(&events);
+        if starts >= 1 && first_start.is_none() {
+            first_start = Some(Instant::now());
+        }
+        if starts >= 3 {
+            third_start = Some(Instant::now());
+        }
+    }
+    kill(pid_from_id(child.id()), Signal::SIGTERM).unwrap();
+    child.wait().unwrap();
+
+    let starts = count_starts(&events);
+    assert!(
+        starts >= 3,
+        "Expected at least two restarts, events: {}",
+        events
+    );
+    let restarts_duration = third_start.unwrap() - first_start.unwrap();
+    assert!(
+        restarts_duration < Duration::from_secs(2),
+        "Restarted twice in {:?}, events: {}",
+        restarts_duration,
+        events
+    );
+}